@@ -0,0 +1,3 @@
+.ORIG
+FOO R1
+.BLKW x
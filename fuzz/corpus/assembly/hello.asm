@@ -0,0 +1,10 @@
+; Prints a greeting
+        .ORIG x3000
+MAIN    LEA R0, HELLO   ; address of the string
+        PUTS
+LOOP    BRnz LOOP
+        HALT
+HELLO   .STRINGZ "Hi; \"you\""
+BUFFER  .BLKW #2
+VALUE   .FILL x1234
+        .END
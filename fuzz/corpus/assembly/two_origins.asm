@@ -0,0 +1,7 @@
+.ORIG x3000
+AND R0, R0, #0
+.END
+.orig 0x4000
+label .blkw 3
+ret
+.end
@@ -0,0 +1,189 @@
+use crate::symbols::SymbolTable;
+
+/// How a frame was entered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// A subroutine called with `JSR` or `JSRR`, returning with `RET`.
+    Subroutine,
+    /// A trap routine, whose vector is the frame's `subroutine`.
+    Trap,
+    /// An interrupt handler, returning with `RTI`.
+    Interrupt,
+}
+
+/// A subroutine call, trap or interrupt that has not returned yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// How the frame was entered.
+    pub kind: FrameKind,
+    /// Address of the `JSR`/`JSRR`/`TRAP` instruction, or of the instruction after which the
+    /// interrupt was serviced.
+    pub call_site: u16,
+    /// Address of the subroutine or interrupt handler, or the trap vector.
+    pub subroutine: u16,
+    /// Address the frame is expected to return to.
+    pub return_address: u16,
+    /// R6 on entry to the frame.
+    pub stack_pointer: u16,
+}
+
+/// Shadow call stack, following calls and returns to describe how the program got somewhere.
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    /// Active frames, outermost first.
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    /// Creates an empty call stack.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `CallStack`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the active frames, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Enters a frame.
+    pub fn push(&mut self, frame: Frame) {
+        self.frames.push(frame);
+    }
+
+    /// Leaves the innermost frame, e.g. once a trap routine completed.
+    pub fn pop(&mut self) -> Option<Frame> {
+        self.frames.pop()
+    }
+
+    /// Leaves the innermost frame on `RET`.
+    ///
+    /// Returning to the return address of an outer frame instead also leaves the frames
+    /// in between, as when a subroutine jumps back past its callers.
+    ///
+    /// # Parameters
+    ///
+    /// - `target`: The address returned to.
+    ///
+    /// # Returns
+    ///
+    /// The innermost frame, or `None` if no frame was active.
+    pub fn ret(&mut self, target: u16) -> Option<Frame> {
+        let frame = self.frames.pop()?;
+        if target != frame.return_address {
+            if let Some(depth) = self.frames.iter().rposition(|f| f.return_address == target) {
+                self.frames.truncate(depth);
+            }
+        }
+        Some(frame)
+    }
+
+    /// Leaves the innermost interrupt handler and the frames it entered, on `RTI`.
+    pub fn rti(&mut self) {
+        if let Some(depth) = self
+            .frames
+            .iter()
+            .rposition(|f| f.kind == FrameKind::Interrupt)
+        {
+            self.frames.truncate(depth);
+        }
+    }
+
+    /// Formats the active frames as a backtrace, innermost first, one per line.
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: Address of the current instruction, e.g. the one that faulted.
+    /// - `symbols`: Labels used to describe the addresses.
+    ///
+    /// # Returns
+    ///
+    /// Lines such as `#1 x3002 in OUTER` for the current instruction and the call site of
+    /// every frame.
+    pub fn backtrace(&self, pc: u16, symbols: &SymbolTable) -> String {
+        let location = |address: u16| {
+            let description = symbols.describe(address);
+            if description == format!("x{:04X}", address) {
+                description
+            } else {
+                format!("x{:04X} in {}", address, description)
+            }
+        };
+        let mut lines = Vec::new();
+        // The instruction of a trap frame is the trap's call site
+        if self.frames.last().is_none_or(|f| f.kind != FrameKind::Trap) {
+            lines.push(location(pc));
+        }
+        for frame in self.frames.iter().rev() {
+            match frame.kind {
+                FrameKind::Subroutine => lines.push(location(frame.call_site)),
+                FrameKind::Trap => {
+                    lines.push(format!("trap routine x{:02X}", frame.subroutine));
+                    lines.push(location(frame.call_site));
+                }
+                FrameKind::Interrupt => {
+                    lines.push(format!("{} (interrupted)", location(frame.call_site)))
+                }
+            }
+        }
+        lines
+            .iter()
+            .enumerate()
+            .map(|(depth, line)| format!("#{} {}\n", depth, line))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(call_site: u16, subroutine: u16) -> Frame {
+        Frame {
+            kind: FrameKind::Subroutine,
+            call_site,
+            subroutine,
+            return_address: call_site + 1,
+            stack_pointer: 0,
+        }
+    }
+
+    #[test]
+    fn return_to_outer_call_site_unwinds_inner_frames() {
+        let mut stack = CallStack::new();
+        stack.push(call(0x3000, 0x3010));
+        stack.push(call(0x3012, 0x3020));
+        stack.push(call(0x3022, 0x3030));
+        assert_eq!(stack.ret(0x3001), Some(call(0x3022, 0x3030)));
+        assert!(stack.frames().is_empty());
+        assert_eq!(stack.ret(0x3001), None);
+    }
+
+    #[test]
+    fn backtrace_names_call_sites() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x3000);
+        symbols.insert("PRINT", 0x3010);
+        let mut stack = CallStack::new();
+        stack.push(call(0x3001, 0x3010));
+        stack.push(Frame {
+            kind: FrameKind::Trap,
+            call_site: 0x3011,
+            subroutine: 0x22,
+            return_address: 0x3012,
+            stack_pointer: 0,
+        });
+        assert_eq!(
+            stack.backtrace(0x3011, &symbols),
+            "#0 trap routine x22\n#1 x3011 in PRINT+1\n#2 x3001 in MAIN+1\n"
+        );
+        stack.pop();
+        assert_eq!(
+            stack.backtrace(0x3013, &SymbolTable::new()),
+            "#0 x3013\n#1 x3001\n"
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use crate::analysis::{Observer, Step};
+use crate::hardware::memory::MEMORY_SIZE;
+use crate::hardware::registers::Register;
+use crate::isa::instructions::is_conditional_branch;
+use crate::source_map::SourceMap;
+use crate::utils::parse_word;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// First line of the files written by `Coverage::to_text`.
+const COVERAGE_MAGIC: &str = "LC3COV 1";
+
+/// Outcomes of a branch instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BranchOutcomes {
+    /// Number of times the branch was taken.
+    pub taken: u64,
+    /// Number of times execution fell through to the next instruction.
+    pub not_taken: u64,
+}
+
+/// Observer collecting the addresses executed and the outcomes of every conditional branch.
+///
+/// Coverage of several runs can be merged, e.g. one run per test case, saved with `to_text`
+/// and read back with `read`, and exported as an lcov tracefile with `write_lcov`, whose
+/// lines come from a `SourceMap`.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// Executions of each address.
+    counts: Vec<u64>,
+    /// Outcomes of each executed branch.
+    branches: HashMap<u16, BranchOutcomes>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    /// Creates an empty coverage.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Coverage`.
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; MEMORY_SIZE],
+            branches: HashMap::new(),
+        }
+    }
+
+    /// Reads coverage saved with `to_text`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the file cannot be read or is not a coverage file.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    /// Parses coverage saved with `to_text`: a line per executed address (`x3000 5`) and
+    /// per branch (`BR x3004 3 2`, taken then not taken).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error naming the first invalid line.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(COVERAGE_MAGIC) {
+            return Err(format!(
+                "Not a coverage file, expected '{}'",
+                COVERAGE_MAGIC
+            ));
+        }
+        let mut coverage = Self::new();
+        for (number, line) in lines {
+            let invalid = || format!("Invalid coverage on line {}: '{}'", number + 1, line);
+            let count = |text: &str| text.parse::<u64>().map_err(|_| invalid());
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [] => {}
+                [address, executions] => {
+                    let address = parse_word(address).map_err(|_| invalid())?;
+                    coverage.counts[address as usize] += count(executions)?;
+                }
+                ["BR", address, taken, not_taken] => {
+                    let address = parse_word(address).map_err(|_| invalid())?;
+                    let outcomes = coverage.branches.entry(address).or_default();
+                    outcomes.taken += count(taken)?;
+                    outcomes.not_taken += count(not_taken)?;
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(coverage)
+    }
+
+    /// Formats the coverage so it can be read back with `parse`.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", COVERAGE_MAGIC);
+        for (address, count) in self.counts.iter().enumerate() {
+            if *count > 0 {
+                text.push_str(&format!("x{:04X} {}\n", address, count));
+            }
+        }
+        for (address, outcomes) in self.sorted_branches() {
+            text.push_str(&format!(
+                "BR x{:04X} {} {}\n",
+                address, outcomes.taken, outcomes.not_taken
+            ));
+        }
+        text
+    }
+
+    /// Adds the coverage of another run.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        for (address, outcomes) in &other.branches {
+            let merged = self.branches.entry(*address).or_default();
+            merged.taken += outcomes.taken;
+            merged.not_taken += outcomes.not_taken;
+        }
+    }
+
+    /// Returns the number of times the instruction at an address was executed.
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[address as usize]
+    }
+
+    /// Returns the outcomes of the branch at an address, if it was executed.
+    pub fn branch(&self, address: u16) -> Option<BranchOutcomes> {
+        self.branches.get(&address).copied()
+    }
+
+    /// Writes the coverage as an lcov tracefile, with a record per source file.
+    ///
+    /// Every instruction of the source map gets a line entry (`DA`) and every branch two
+    /// branch entries (`BRDA`), taken then not taken. Data words and executed addresses
+    /// missing from the source map are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `sources` - Source lines of the addresses.
+    /// * `writer` - Destination of the tracefile.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if writing fails.
+    pub fn write_lcov(&self, sources: &SourceMap, writer: &mut dyn Write) -> Result<(), String> {
+        // Executions and branch outcomes of each line, per file
+        let mut files: Vec<BTreeMap<u32, (u64, Option<BranchOutcomes>)>> =
+            vec![BTreeMap::new(); sources.files().len()];
+        for (address, location) in sources.locations().filter(|(_, l)| l.code) {
+            let line = files[location.file].entry(location.line).or_default();
+            line.0 += self.count(address);
+            if location.branch {
+                let outcomes = line.1.get_or_insert_with(BranchOutcomes::default);
+                if let Some(branch) = self.branch(address) {
+                    outcomes.taken += branch.taken;
+                    outcomes.not_taken += branch.not_taken;
+                }
+            }
+        }
+        let mut text = String::new();
+        for (file, lines) in sources.files().iter().zip(files) {
+            if lines.is_empty() {
+                continue;
+            }
+            text.push_str(&format!("TN:\nSF:{}\n", file));
+            let (mut branches, mut branches_hit) = (0, 0);
+            for (line, (count, outcomes)) in &lines {
+                let Some(outcomes) = outcomes else {
+                    continue;
+                };
+                for (branch, taken) in [outcomes.taken, outcomes.not_taken].iter().enumerate() {
+                    // Branches on lines never executed are reported as `-`
+                    let taken = if *count > 0 {
+                        taken.to_string()
+                    } else {
+                        "-".to_string()
+                    };
+                    branches_hit += (taken != "-" && taken != "0") as usize;
+                    text.push_str(&format!("BRDA:{},0,{},{}\n", line, branch, taken));
+                }
+                branches += 2;
+            }
+            text.push_str(&format!("BRF:{}\nBRH:{}\n", branches, branches_hit));
+            for (line, (count, _)) in &lines {
+                text.push_str(&format!("DA:{},{}\n", line, count));
+            }
+            let hit = lines.values().filter(|(count, _)| *count > 0).count();
+            text.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", lines.len(), hit));
+        }
+        writer.write_all(text.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    /// Returns the branch outcomes sorted by address.
+    fn sorted_branches(&self) -> Vec<(u16, BranchOutcomes)> {
+        let mut branches: Vec<(u16, BranchOutcomes)> =
+            self.branches.iter().map(|(a, o)| (*a, *o)).collect();
+        branches.sort_by_key(|(address, _)| *address);
+        branches
+    }
+}
+
+impl Observer for Coverage {
+    fn step(&mut self, step: &Step) -> Result<(), String> {
+        self.counts[step.pc as usize] += 1;
+        if is_conditional_branch(step.instr) {
+            // BR leaves the condition codes as they were before it
+            let cond = step.registers.read(Register::COND);
+            let outcomes = self.branches.entry(step.pc).or_default();
+            if (step.instr >> 9) & 0x7 & cond != 0 {
+                outcomes.taken += 1;
+            } else {
+                outcomes.not_taken += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Image;
+    use crate::vm::VM;
+
+    const SOURCE: &str = "\
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #2
+LOOP    ADD R0, R0, #-1
+        BRp LOOP
+        BRn NEVER
+        HALT
+NEVER   HALT
+        .END
+";
+
+    fn run() -> Coverage {
+        let program = [0x5020, 0x1022, 0x103F, 0x03FE, 0x0801, 0xF025, 0xF025];
+        let mut vm = VM::new();
+        vm.add_observer(Box::new(Coverage::new()));
+        vm.load_image(&Image::new("loop", 0x3000, program.to_vec()));
+        assert!(vm.run().is_ok());
+        vm.observer::<Coverage>().unwrap().clone()
+    }
+
+    #[test]
+    fn records_executions_and_branch_outcomes() {
+        let coverage = run();
+        assert_eq!(coverage.count(0x3002), 2);
+        assert_eq!(coverage.count(0x3006), 0);
+        assert_eq!(
+            coverage.branch(0x3003),
+            Some(BranchOutcomes {
+                taken: 1,
+                not_taken: 1
+            })
+        );
+    }
+
+    #[test]
+    fn branches_are_told_apart_by_condition_codes() {
+        let program = [
+            0x5020, // x3000 AND R0, R0, #0 (sets z)
+            0x0400, // x3001 BRz #0, taken to the next instruction
+            0x0E00, // x3002 BRnzp #0, always taken
+            0x0200, // x3003 BRp #0, not taken
+            0xF025, // x3004 HALT
+        ];
+        let mut vm = VM::new();
+        vm.add_observer(Box::new(Coverage::new()));
+        vm.load_image(&Image::new("zero", 0x3000, program.to_vec()));
+        assert!(vm.run().is_ok());
+        let coverage = vm.observer::<Coverage>().unwrap();
+        let outcomes = |taken, not_taken| Some(BranchOutcomes { taken, not_taken });
+        assert_eq!(coverage.branch(0x3001), outcomes(1, 0));
+        assert_eq!(coverage.branch(0x3002), None);
+        assert_eq!(coverage.branch(0x3003), outcomes(0, 1));
+    }
+
+    #[test]
+    fn merged_runs_round_trip_through_text() {
+        let mut coverage = run();
+        coverage.merge(&run());
+        let parsed = Coverage::parse(&coverage.to_text()).unwrap();
+        assert_eq!(parsed.count(0x3002), 4);
+        assert_eq!(
+            parsed.branch(0x3004),
+            Some(BranchOutcomes {
+                taken: 0,
+                not_taken: 2
+            })
+        );
+        assert!(Coverage::parse("x3000 1\n").is_err());
+    }
+
+    #[test]
+    fn lcov_maps_addresses_to_source_lines() {
+        let mut sources = SourceMap::new();
+        sources.add_assembly("loop.asm", SOURCE).unwrap();
+        let mut lcov = Vec::new();
+        run().write_lcov(&sources, &mut lcov).unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            "TN:\nSF:loop.asm\n\
+             BRDA:5,0,0,1\nBRDA:5,0,1,1\nBRDA:6,0,0,0\nBRDA:6,0,1,1\nBRF:4\nBRH:3\n\
+             DA:2,1\nDA:3,1\nDA:4,2\nDA:5,2\nDA:6,1\nDA:7,1\nDA:8,0\n\
+             LF:7\nLH:6\nend_of_record\n"
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use crate::analysis::call_stack::CallStack;
+use crate::hardware::memory::Access;
+use crate::hardware::registers::Registers;
+use crate::isa::interrupts::Interrupt;
+use std::any::Any;
+
+/// Module implementing the shadow call stack that describes how the program reached an
+/// instruction, as a backtrace.
+pub mod call_stack;
+
+/// Module implementing code coverage collection, merged across runs and exported as lcov.
+pub mod coverage;
+
+/// Module implementing the profiler that counts executed instructions per address, opcode
+/// and subroutine.
+pub mod profiler;
+
+/// Module implementing the sanitizer that reports reads of uninitialized memory and
+/// registers, and execution of data words.
+pub mod sanitizer;
+
+/// Module implementing the checker of the R6-based calling convention, which pairs
+/// subroutine calls with their returns.
+pub mod stack;
+
+/// An instruction executed by the VM, as seen by observers.
+pub struct Step<'a> {
+    /// Address the instruction was fetched from.
+    pub pc: u16,
+    /// The instruction.
+    pub instr: u16,
+    /// Registers after the instruction was executed.
+    pub registers: &'a Registers,
+    /// Accesses to RAM made by the instruction, starting with its fetch. Trap routines
+    /// run within the `TRAP` instruction, so their accesses are included, and so are the
+    /// words written by devices through direct memory access after the instruction.
+    pub accesses: &'a [Access],
+    /// Shadow call stack after the instruction, e.g. with a new frame after `JSR`.
+    pub call_stack: &'a CallStack,
+}
+
+/// Trait implemented by analyses run alongside the VM, e.g. the sanitizer.
+///
+/// Observers are added with `VM::add_observer` and are notified of every executed
+/// instruction and serviced interrupt.
+pub trait Observer: Any {
+    /// Inspects an executed instruction.
+    ///
+    /// # Parameters
+    ///
+    /// - `step`: The instruction and its effects.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error to stop the VM, which reports it at the instruction's address.
+    fn step(&mut self, step: &Step) -> Result<(), String>;
+
+    /// Notes that an interrupt was serviced after the last instruction.
+    ///
+    /// # Parameters
+    ///
+    /// - `interrupt`: The serviced interrupt.
+    /// - `registers`: Registers on entry to the handler.
+    fn interrupt(&mut self, _interrupt: Interrupt, _registers: &Registers) {}
+}
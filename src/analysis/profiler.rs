@@ -0,0 +1,345 @@
+use crate::analysis::call_stack::{Frame, FrameKind};
+use crate::analysis::{Observer, Step};
+use crate::hardware::memory::MEMORY_SIZE;
+use crate::hardware::registers::{Register, Registers};
+use crate::isa::disassembler::disassemble;
+use crate::isa::interrupts::Interrupt;
+use crate::symbols::SymbolTable;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+/// Mnemonics of the opcodes, indexed by opcode.
+const OPCODE_NAMES: [&str; 16] = [
+    "BR", "ADD", "LD", "ST", "JSR", "AND", "LDR", "STR", "RTI", "NOT", "LDI", "STI", "JMP", "RES",
+    "LEA", "TRAP",
+];
+
+/// Instructions executed by a subroutine or interrupt handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubroutineProfile {
+    /// Address of the subroutine; the entry point of the program for its top level.
+    pub address: u16,
+    /// Number of times the subroutine was entered.
+    pub calls: u64,
+    /// Instructions executed while the subroutine was active, including its callees.
+    pub inclusive: u64,
+    /// Instructions executed by the subroutine itself.
+    pub exclusive: u64,
+}
+
+/// Observer counting the instructions executed per address, per opcode and per subroutine.
+///
+/// One instruction counts as one cycle. Subroutines are followed with the VM's shadow call
+/// stack, so each instruction is attributed to the stack of subroutines it executed in;
+/// trap routines run within their `TRAP` instruction and are not subroutines of their own.
+pub struct Profiler {
+    /// Executions of each address.
+    counts: Vec<u64>,
+    /// Last instruction executed at each address.
+    instructions: Vec<u16>,
+    /// Executions of each opcode.
+    opcodes: [u64; 16],
+    /// Subroutines the next instruction executes in, outermost first, starting with the
+    /// entry point of the program.
+    stack: Vec<u16>,
+    /// Instructions executed in each stack of subroutines.
+    stacks: HashMap<Vec<u16>, u64>,
+    /// Number of times each subroutine was entered.
+    calls: HashMap<u16, u64>,
+    /// Instructions executed.
+    total: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    /// Creates a profiler with no instructions counted.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Profiler`.
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; MEMORY_SIZE],
+            instructions: vec![0; MEMORY_SIZE],
+            opcodes: [0; 16],
+            stack: Vec::new(),
+            stacks: HashMap::new(),
+            calls: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    /// Returns the number of instructions executed.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the number of times the instruction at an address was executed.
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[address as usize]
+    }
+
+    /// Returns the number of times instructions with an opcode (0 to 15) were executed.
+    pub fn opcode_count(&self, opcode: u16) -> u64 {
+        self.opcodes[opcode as usize & 0xF]
+    }
+
+    /// Returns the instructions executed by each subroutine, the top level of the program
+    /// included, sorted by inclusive count, highest first.
+    pub fn subroutines(&self) -> Vec<SubroutineProfile> {
+        let mut profiles: HashMap<u16, SubroutineProfile> = HashMap::new();
+        for (stack, count) in &self.stacks {
+            let mut seen = HashSet::new();
+            for (depth, address) in stack.iter().enumerate() {
+                let profile = profiles.entry(*address).or_insert(SubroutineProfile {
+                    address: *address,
+                    calls: self.calls.get(address).copied().unwrap_or_default(),
+                    inclusive: 0,
+                    exclusive: 0,
+                });
+                // Recursive calls count once towards the inclusive total
+                if seen.insert(*address) {
+                    profile.inclusive += count;
+                }
+                if depth == stack.len() - 1 {
+                    profile.exclusive += count;
+                }
+            }
+        }
+        let mut profiles: Vec<SubroutineProfile> = profiles.into_values().collect();
+        profiles.sort_by(|a, b| {
+            (b.inclusive, b.exclusive, a.address).cmp(&(a.inclusive, a.exclusive, b.address))
+        });
+        profiles
+    }
+
+    /// Writes a report of the instructions executed per opcode, the most executed addresses
+    /// and the instructions executed per subroutine.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbols` - Labels used to name addresses and disassemble instructions.
+    /// * `top` - Maximum number of addresses listed.
+    /// * `writer` - Destination of the report.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if writing fails.
+    pub fn write_report(
+        &self,
+        symbols: &SymbolTable,
+        top: usize,
+        writer: &mut dyn Write,
+    ) -> Result<(), String> {
+        let percent = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+        let mut text = format!("Instructions executed: {}\n", self.total);
+
+        text.push_str("\nBy opcode:\n");
+        let mut opcodes: Vec<(usize, u64)> = self.opcodes.iter().copied().enumerate().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (opcode, count) in opcodes.into_iter().filter(|(_, count)| *count > 0) {
+            text.push_str(&format!(
+                "  {:<5} {:>12} {:>6.1}%\n",
+                OPCODE_NAMES[opcode],
+                count,
+                percent(count)
+            ));
+        }
+
+        let mut addresses: Vec<u16> = (0..=u16::MAX)
+            .filter(|address| self.counts[*address as usize] > 0)
+            .collect();
+        addresses.sort_by(|a, b| self.count(*b).cmp(&self.count(*a)).then(a.cmp(b)));
+        text.push_str(&format!(
+            "\nHot spots ({} of {} addresses):\n",
+            top.min(addresses.len()),
+            addresses.len()
+        ));
+        for address in addresses.into_iter().take(top) {
+            let count = self.count(address);
+            text.push_str(&format!(
+                "  x{:04X} {:<16} {:>12} {:>6.1}%  {}\n",
+                address,
+                symbols.describe(address),
+                count,
+                percent(count),
+                disassemble(self.instructions[address as usize], address, symbols)
+            ));
+        }
+
+        text.push_str("\nBy subroutine:\n");
+        text.push_str(&format!(
+            "  {:<16} {:>8} {:>12} {:>7} {:>12} {:>7}\n",
+            "Subroutine", "Calls", "Inclusive", "%", "Exclusive", "%"
+        ));
+        for profile in self.subroutines() {
+            text.push_str(&format!(
+                "  {:<16} {:>8} {:>12} {:>6.1}% {:>12} {:>6.1}%\n",
+                symbols.describe(profile.address),
+                profile.calls,
+                profile.inclusive,
+                percent(profile.inclusive),
+                profile.exclusive,
+                percent(profile.exclusive)
+            ));
+        }
+        writer.write_all(text.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    /// Writes the instructions executed in each stack of subroutines in the collapsed
+    /// format read by flame graph tools: one `MAIN;OUTER;INNER 42` line per stack.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbols` - Labels used to name the subroutines.
+    /// * `writer` - Destination of the stacks.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if writing fails.
+    pub fn write_collapsed(
+        &self,
+        symbols: &SymbolTable,
+        writer: &mut dyn Write,
+    ) -> Result<(), String> {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let names: Vec<String> = stack.iter().map(|a| symbols.describe(*a)).collect();
+                format!("{} {}\n", names.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        writer
+            .write_all(lines.concat().as_bytes())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Follows the subroutines entered and left by the last instruction.
+    fn sync(&mut self, frames: &[Frame]) {
+        let subroutines = frames.iter().filter(|f| f.kind != FrameKind::Trap);
+        let unchanged = self.stack.len() == subroutines.clone().count() + 1
+            && self.stack.last() == subroutines.clone().next_back().map(|f| &f.subroutine);
+        if unchanged {
+            return;
+        }
+        let root = self.stack[0];
+        let previous = std::mem::replace(&mut self.stack, vec![root]);
+        for (depth, frame) in subroutines.enumerate() {
+            if previous.get(depth + 1) != Some(&frame.subroutine) {
+                *self.calls.entry(frame.subroutine).or_default() += 1;
+            }
+            self.stack.push(frame.subroutine);
+        }
+    }
+}
+
+impl Observer for Profiler {
+    fn step(&mut self, step: &Step) -> Result<(), String> {
+        if self.stack.is_empty() {
+            self.stack.push(step.pc);
+        }
+        self.counts[step.pc as usize] += 1;
+        self.instructions[step.pc as usize] = step.instr;
+        self.opcodes[(step.instr >> 12) as usize] += 1;
+        self.total += 1;
+        match self.stacks.get_mut(&self.stack) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+        self.sync(step.call_stack.frames());
+        Ok(())
+    }
+
+    fn interrupt(&mut self, _interrupt: Interrupt, registers: &Registers) {
+        // The handler's frame is only seen after its first instruction
+        let handler = registers.read(Register::PC);
+        self.stack.push(handler);
+        *self.calls.entry(handler).or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Image;
+    use crate::vm::VM;
+
+    fn profile() -> (VM, SymbolTable) {
+        let program = [
+            0x4802, // x3000 MAIN: JSR SUB
+            0x4801, // x3001 JSR SUB
+            0xF025, // x3002 HALT
+            0x1021, // x3003 SUB: ADD R0, R0, #1
+            0xC1C0, // x3004 RET
+        ];
+        let mut vm = VM::new();
+        vm.add_observer(Box::new(Profiler::new()));
+        vm.load_image(&Image::new("test", 0x3000, program.to_vec()));
+        assert!(vm.run().is_ok());
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x3000);
+        symbols.insert("SUB", 0x3003);
+        (vm, symbols)
+    }
+
+    #[test]
+    fn counts_instructions_per_address_and_opcode() {
+        let (vm, _) = profile();
+        let profiler = vm.observer::<Profiler>().unwrap();
+        assert_eq!(profiler.total(), 7);
+        assert_eq!(profiler.count(0x3003), 2);
+        assert_eq!(profiler.count(0x3002), 1);
+        assert_eq!(profiler.opcode_count(0x4), 2);
+        assert_eq!(profiler.opcode_count(0xC), 2);
+    }
+
+    #[test]
+    fn attributes_instructions_to_subroutines() {
+        let (vm, symbols) = profile();
+        let profiler = vm.observer::<Profiler>().unwrap();
+        assert_eq!(
+            profiler.subroutines(),
+            [
+                SubroutineProfile {
+                    address: 0x3000,
+                    calls: 0,
+                    inclusive: 7,
+                    exclusive: 3
+                },
+                SubroutineProfile {
+                    address: 0x3003,
+                    calls: 2,
+                    inclusive: 4,
+                    exclusive: 4
+                },
+            ]
+        );
+        let mut collapsed = Vec::new();
+        profiler.write_collapsed(&symbols, &mut collapsed).unwrap();
+        assert_eq!(
+            String::from_utf8(collapsed).unwrap(),
+            "MAIN 3\nMAIN;SUB 4\n"
+        );
+    }
+
+    #[test]
+    fn report_lists_hot_spots() {
+        let (vm, symbols) = profile();
+        let profiler = vm.observer::<Profiler>().unwrap();
+        let mut report = Vec::new();
+        profiler.write_report(&symbols, 1, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("Instructions executed: 7\n"));
+        assert!(report.contains("Hot spots (1 of 5 addresses):\n  x3003 SUB"));
+        assert!(report.contains("ADD R0, R0, #1\n"));
+    }
+}
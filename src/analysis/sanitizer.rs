@@ -0,0 +1,279 @@
+use crate::analysis::{Observer, Step};
+use crate::hardware::registers::{Register, Registers};
+use crate::hardware::shadow::ShadowMemory;
+use crate::isa::interrupts::Interrupt;
+use std::collections::HashSet;
+use std::fmt;
+
+/// What the sanitizer does when it finds a violation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SanitizerMode {
+    /// Record the violation and keep running.
+    #[default]
+    Report,
+    /// Stop the VM with an error.
+    Halt,
+}
+
+impl SanitizerMode {
+    /// Parses `report` or `halt`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error for any other text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "report" => Ok(SanitizerMode::Report),
+            "halt" => Ok(SanitizerMode::Halt),
+            _ => Err(format!(
+                "Unknown sanitizer mode '{}', expected report or halt",
+                text
+            )),
+        }
+    }
+}
+
+/// Kinds of violations found by the sanitizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ViolationKind {
+    /// A word that was never loaded or written was read, or fetched as an instruction.
+    UninitializedRead(u16),
+    /// A general-purpose register was used before anything was written to it.
+    UninitializedRegister(u16),
+    /// The instruction was a word previously read or written as data.
+    DataExecution,
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ViolationKind::UninitializedRead(address) => {
+                write!(f, "read of uninitialized address x{:04X}", address)
+            }
+            ViolationKind::UninitializedRegister(reg) => {
+                write!(f, "use of uninitialized register R{}", reg)
+            }
+            ViolationKind::DataExecution => write!(f, "execution of data word"),
+        }
+    }
+}
+
+/// A violation, together with the address of the offending instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Violation {
+    /// Address of the offending instruction.
+    pub pc: u16,
+    /// What the instruction did wrong.
+    pub kind: ViolationKind,
+}
+
+/// Observer reporting reads of uninitialized memory and registers, and execution of data words.
+///
+/// Memory initialization is tracked by `Memory` itself, so `VM::flag_uninitialized_reads`
+/// must be called before loading images for uninitialized reads to be found. The sanitizer
+/// keeps its own shadow bitmap of the words accessed as data, i.e. read or written by
+/// loads, stores, trap routines and disk transfers, and flags any of them that is later
+/// executed.
+pub struct Sanitizer {
+    /// What to do on a violation.
+    mode: SanitizerMode,
+    /// Whether R0 to R7 were written.
+    registers: [bool; 8],
+    /// Words read or written as data.
+    data: ShadowMemory,
+    /// Violations found so far, once per instruction and kind.
+    violations: Vec<Violation>,
+    /// Set of the violations, to report each one once.
+    seen: HashSet<Violation>,
+}
+
+impl Sanitizer {
+    /// Creates a sanitizer for which no register is initialized.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - What to do on a violation.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Sanitizer`.
+    pub fn new(mode: SanitizerMode) -> Self {
+        Self {
+            mode,
+            registers: [false; 8],
+            data: ShadowMemory::new(),
+            violations: Vec::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Marks a register as initialized, e.g. one set from the command line.
+    pub fn initialize_register(&mut self, reg: Register) {
+        if (reg as usize) < self.registers.len() {
+            self.registers[reg as usize] = true;
+        }
+    }
+
+    /// Returns the violations found so far, in the order they were found.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Records a violation, failing in halt mode.
+    fn report(&mut self, pc: u16, kind: ViolationKind) -> Result<(), String> {
+        let violation = Violation { pc, kind };
+        if self.seen.insert(violation) {
+            self.violations.push(violation);
+        }
+        match self.mode {
+            SanitizerMode::Report => Ok(()),
+            SanitizerMode::Halt => Err(format!("Sanitizer: {}", kind)),
+        }
+    }
+}
+
+impl Observer for Sanitizer {
+    fn step(&mut self, step: &Step) -> Result<(), String> {
+        let (fetch, accesses) = match step.accesses.split_first() {
+            Some((fetch, rest)) if fetch.address == step.pc && !fetch.write => (Some(fetch), rest),
+            _ => (None, step.accesses),
+        };
+        if let Some(fetch) = fetch {
+            if !fetch.initialized {
+                self.report(step.pc, ViolationKind::UninitializedRead(step.pc))?;
+            } else if self.data.get(step.pc) {
+                self.report(step.pc, ViolationKind::DataExecution)?;
+            }
+        }
+        for access in accesses {
+            if !access.write && !access.initialized {
+                self.report(step.pc, ViolationKind::UninitializedRead(access.address))?;
+            }
+            self.data.set(access.address);
+        }
+
+        let (sources, destinations) = operands(step.instr);
+        for reg in sources {
+            if !self.registers[reg as usize] {
+                self.report(step.pc, ViolationKind::UninitializedRegister(reg))?;
+            }
+        }
+        for reg in destinations {
+            self.registers[reg as usize] = true;
+        }
+        Ok(())
+    }
+
+    fn interrupt(&mut self, _interrupt: Interrupt, _registers: &Registers) {
+        // The handler runs on the supervisor stack, whose pointer is set by the hardware
+        self.registers[Register::R6 as usize] = true;
+    }
+}
+
+/// Returns the general-purpose registers read and written by an instruction.
+fn operands(instr: u16) -> (Vec<u16>, Vec<u16>) {
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    match instr >> 12 {
+        // AND with #0 clears the register whatever its contents
+        0x5 if instr & 0x3F == 0x20 => (vec![], vec![dr]),
+        0x1 | 0x5 if instr & 0x20 != 0 => (vec![sr1], vec![dr]),
+        0x1 | 0x5 => (vec![sr1, instr & 0x7], vec![dr]),
+        0x2 | 0xA | 0xE => (vec![], vec![dr]),
+        0x3 | 0xB => (vec![dr], vec![]),
+        0x4 if instr & 0x800 != 0 => (vec![], vec![7]),
+        0x4 => (vec![sr1], vec![7]),
+        0x6 => (vec![sr1], vec![dr]),
+        0x7 => (vec![dr, sr1], vec![]),
+        0x8 => (vec![6], vec![6]),
+        0x9 => (vec![sr1], vec![dr]),
+        0xC => (vec![sr1], vec![]),
+        0xF => match instr & 0xFF {
+            0x20 | 0x23 => (vec![], vec![0, 7]),
+            0x21 | 0x22 | 0x24 => (vec![0], vec![7]),
+            _ => (vec![], vec![7]),
+        },
+        _ => (vec![], vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::disk::{transfer_program, Disk, TRANSFER_END};
+    use crate::loader::Image;
+    use crate::vm::VM;
+
+    fn sanitize(program: &[u16], mode: SanitizerMode) -> (VM, Result<(), String>) {
+        let mut vm = VM::new();
+        vm.flag_uninitialized_reads();
+        vm.add_observer(Box::new(Sanitizer::new(mode)));
+        vm.load_image(&Image::new("test", 0x3000, program.to_vec()));
+        let result = vm.run();
+        (vm, result)
+    }
+
+    #[test]
+    fn reports_uninitialized_memory_and_registers() {
+        let program = [
+            0x5020, // x3000 AND R0, R0, #0
+            0x2203, // x3001 LD R1, x3005 (never written)
+            0x1042, // x3002 ADD R0, R1, R2 (R2 never written)
+            0xF025, // x3003 HALT
+        ];
+        let (vm, result) = sanitize(&program, SanitizerMode::Report);
+        assert!(result.is_ok());
+        let sanitizer = vm.observer::<Sanitizer>().unwrap();
+        assert_eq!(
+            sanitizer.violations(),
+            [
+                Violation {
+                    pc: 0x3001,
+                    kind: ViolationKind::UninitializedRead(0x3005)
+                },
+                Violation {
+                    pc: 0x3002,
+                    kind: ViolationKind::UninitializedRegister(2)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn halts_on_execution_of_data_word() {
+        let program = [
+            0x2001, // x3000 LD R0, DATA
+            0x0000, // x3001 NOP
+            0x1021, // x3002 DATA: ADD R0, R0, #1, loaded above then executed
+            0xF025, // x3003 HALT
+        ];
+        let (vm, result) = sanitize(&program, SanitizerMode::Halt);
+        assert_eq!(
+            result,
+            Err("Sanitizer: execution of data word at x3002".to_string())
+        );
+        assert_eq!(vm.observer::<Sanitizer>().unwrap().violations().len(), 1);
+    }
+
+    #[test]
+    fn halts_on_execution_of_word_read_from_disk() {
+        // The sector holding HALT is read right where the program goes on
+        let (disk, program) = transfer_program("sanitize", &[0xF025], TRANSFER_END);
+        let mut vm = VM::new();
+        vm.register_device(Disk::range(), Box::new(disk)).unwrap();
+        vm.add_observer(Box::new(Sanitizer::new(SanitizerMode::Halt)));
+        vm.load_image(&Image::new("test", 0x3000, program));
+        assert_eq!(
+            vm.run(),
+            Err("Sanitizer: execution of data word at x3009".to_string())
+        );
+    }
+
+    #[test]
+    fn operands_of_traps_and_subroutine_calls() {
+        assert_eq!(operands(0xF022), (vec![0], vec![7])); // PUTS
+        assert_eq!(operands(0xF020), (vec![], vec![0, 7])); // GETC
+        assert_eq!(operands(0x4080), (vec![2], vec![7])); // JSRR R2
+        assert_eq!(operands(0x7283), (vec![1, 2], vec![])); // STR R1, R2, #3
+    }
+}
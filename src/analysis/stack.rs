@@ -0,0 +1,239 @@
+use crate::analysis::call_stack::{CallStack, Frame, FrameKind};
+use crate::analysis::{Observer, Step};
+use crate::hardware::registers::{Register, Registers};
+use crate::isa::interrupts::Interrupt;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Kinds of stack discipline violations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StackWarningKind {
+    /// `RET` executed while no subroutine was called.
+    ReturnWithoutCall,
+    /// `RET` jumped somewhere else than after the call site, e.g. because R7 was clobbered.
+    UnexpectedReturn {
+        /// Address returned to.
+        target: u16,
+        /// Address after the call site.
+        expected: u16,
+    },
+    /// The subroutine returned with a different R6 than it was called with.
+    StackImbalance {
+        /// R6 on entry.
+        entry: u16,
+        /// R6 on return.
+        exit: u16,
+    },
+}
+
+impl fmt::Display for StackWarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackWarningKind::ReturnWithoutCall => write!(f, "return without a subroutine call"),
+            StackWarningKind::UnexpectedReturn { target, expected } => write!(
+                f,
+                "return to x{:04X} instead of x{:04X} after the call site (was R7 overwritten?)",
+                target, expected
+            ),
+            StackWarningKind::StackImbalance { entry, exit } => write!(
+                f,
+                "return with R6 = x{:04X} but the subroutine was called with R6 = x{:04X} ({:+} words)",
+                exit,
+                entry,
+                exit.wrapping_sub(*entry) as i16
+            ),
+        }
+    }
+}
+
+/// A stack discipline violation, together with the address of the offending `RET`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StackWarning {
+    /// Address of the `RET` instruction.
+    pub pc: u16,
+    /// Address of the subroutine that returned, if one was called.
+    pub subroutine: Option<u16>,
+    /// What went wrong.
+    pub kind: StackWarningKind,
+}
+
+/// Observer checking that subroutines follow the R6-based calling convention.
+///
+/// Each `JSR`/`JSRR` pushes a frame recording the return address and R6, and each `RET`
+/// pops it, warning if it returns elsewhere or with a different R6. Interrupts push a frame
+/// popped by `RTI`.
+#[derive(Debug, Default)]
+pub struct StackChecker {
+    /// Frames of the subroutines and interrupt handlers that have not returned yet.
+    call_stack: CallStack,
+    /// Address of the last instruction, after which an interrupt may be serviced.
+    last_pc: u16,
+    /// PC after the last instruction, where an interrupt returns to.
+    next_pc: u16,
+    /// Warnings found so far, once per instruction and kind.
+    warnings: Vec<StackWarning>,
+    /// Set of the warnings, to report each one once.
+    seen: HashSet<StackWarning>,
+}
+
+impl StackChecker {
+    /// Creates a checker with no active frames.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `StackChecker`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the active frames, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        self.call_stack.frames()
+    }
+
+    /// Returns the warnings found so far, in the order they were found.
+    pub fn warnings(&self) -> &[StackWarning] {
+        &self.warnings
+    }
+
+    /// Records a warning.
+    fn warn(&mut self, pc: u16, subroutine: Option<u16>, kind: StackWarningKind) {
+        let warning = StackWarning {
+            pc,
+            subroutine,
+            kind,
+        };
+        if self.seen.insert(warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Pops the frame of a subroutine returning to `target` with `R6 = sp`.
+    fn ret(&mut self, pc: u16, target: u16, sp: u16) {
+        let Some(frame) = self.call_stack.ret(target) else {
+            self.warn(pc, None, StackWarningKind::ReturnWithoutCall);
+            return;
+        };
+        if target != frame.return_address {
+            let kind = StackWarningKind::UnexpectedReturn {
+                target,
+                expected: frame.return_address,
+            };
+            self.warn(pc, Some(frame.subroutine), kind);
+        } else if sp != frame.stack_pointer {
+            let kind = StackWarningKind::StackImbalance {
+                entry: frame.stack_pointer,
+                exit: sp,
+            };
+            self.warn(pc, Some(frame.subroutine), kind);
+        }
+    }
+}
+
+impl Observer for StackChecker {
+    fn step(&mut self, step: &Step) -> Result<(), String> {
+        let pc = step.registers.read(Register::PC);
+        let sp = step.registers.read(Register::R6);
+        match step.instr >> 12 {
+            0x4 => self.call_stack.push(Frame {
+                kind: FrameKind::Subroutine,
+                call_site: step.pc,
+                subroutine: pc,
+                return_address: step.pc.wrapping_add(1),
+                stack_pointer: sp,
+            }),
+            0xC if (step.instr >> 6) & 0x7 == 7 => self.ret(step.pc, pc, sp),
+            0x8 => self.call_stack.rti(),
+            _ => {}
+        }
+        self.last_pc = step.pc;
+        self.next_pc = pc;
+        Ok(())
+    }
+
+    fn interrupt(&mut self, _interrupt: Interrupt, registers: &Registers) {
+        self.call_stack.push(Frame {
+            kind: FrameKind::Interrupt,
+            call_site: self.last_pc,
+            subroutine: registers.read(Register::PC),
+            return_address: self.next_pc,
+            stack_pointer: registers.read(Register::R6),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Image;
+    use crate::vm::VM;
+
+    fn check(program: &[u16]) -> (VM, Result<(), String>) {
+        let mut vm = VM::new();
+        vm.add_observer(Box::new(StackChecker::new()));
+        vm.load_image(&Image::new("test", 0x3000, program.to_vec()));
+        let result = vm.run();
+        (vm, result)
+    }
+
+    #[test]
+    fn balanced_calls_are_accepted() {
+        let program = [
+            0x4802, // x3000 JSR SUB
+            0x4801, // x3001 JSR SUB
+            0xF025, // x3002 HALT
+            0x1DBF, // x3003 SUB: ADD R6, R6, #-1
+            0x1DA1, // x3004 ADD R6, R6, #1
+            0xC1C0, // x3005 RET
+        ];
+        let (vm, result) = check(&program);
+        assert!(result.is_ok());
+        let checker = vm.observer::<StackChecker>().unwrap();
+        assert!(checker.warnings().is_empty());
+        assert!(checker.frames().is_empty());
+    }
+
+    #[test]
+    fn unbalanced_stack_and_clobbered_r7_are_reported() {
+        let program = [
+            0x4804, // x3000 JSR PUSH
+            0x4805, // x3001 JSR CLOBBER
+            0xF025, // x3002 HALT
+            0xEFFE, // x3003 STRAY: LEA R7, x3002
+            0xC1C0, // x3004 RET, without a call
+            0x1DBF, // x3005 PUSH: ADD R6, R6, #-1
+            0xC1C0, // x3006 RET
+            0xEFFB, // x3007 CLOBBER: LEA R7, STRAY
+            0xC1C0, // x3008 RET, to STRAY
+        ];
+        let (vm, result) = check(&program);
+        assert!(result.is_ok());
+        let checker = vm.observer::<StackChecker>().unwrap();
+        assert_eq!(
+            checker.warnings(),
+            [
+                StackWarning {
+                    pc: 0x3006,
+                    subroutine: Some(0x3005),
+                    kind: StackWarningKind::StackImbalance {
+                        entry: 0,
+                        exit: 0xFFFF
+                    }
+                },
+                StackWarning {
+                    pc: 0x3008,
+                    subroutine: Some(0x3007),
+                    kind: StackWarningKind::UnexpectedReturn {
+                        target: 0x3003,
+                        expected: 0x3002
+                    }
+                },
+                StackWarning {
+                    pc: 0x3004,
+                    subroutine: None,
+                    kind: StackWarningKind::ReturnWithoutCall
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use crate::analysis::{Observer, Step};
+use crate::hardware::registers::Register;
+use crate::hardware::rng::Rng;
+use crate::isa::disassembler::disassemble;
+use crate::loader::Image;
+use crate::reference::{Reference, DEVICE_PAGE, N, P, Z};
+use crate::symbols::SymbolTable;
+use crate::vm::VM;
+use std::fmt;
+
+/// Opcodes of the generated instructions: all of them but `RTI`, the reserved one and `TRAP`,
+/// which the reference model leaves out.
+const OPCODES: [u16; 13] = [
+    0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x9, 0xA, 0xB, 0xC, 0xE,
+];
+
+/// Address the generated instructions are placed at.
+const ORIGIN: u16 = 0x3000;
+
+/// First instruction on which the VM and the reference model disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Seed of the case, which `check_case` reproduces.
+    pub seed: u64,
+    /// Instructions executed before the divergent one, as address and instruction.
+    pub trace: Vec<(u16, u16)>,
+    /// Address of the divergent instruction.
+    pub pc: u16,
+    /// The divergent instruction.
+    pub instr: u16,
+    /// What differs once it was executed, e.g. `R1: VM x0001, reference x0002`.
+    pub differences: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbols = SymbolTable::new();
+        let instruction = |pc: u16, instr: u16| {
+            format!(
+                "x{:04X} {} (x{:04X})",
+                pc,
+                disassemble(instr, pc, &symbols),
+                instr
+            )
+        };
+        writeln!(
+            f,
+            "Case seed {}: {} diverged after {} instructions",
+            self.seed,
+            instruction(self.pc, self.instr),
+            self.trace.len()
+        )?;
+        for difference in &self.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        if !self.trace.is_empty() {
+            writeln!(f, "Executed before:")?;
+            for (pc, instr) in &self.trace {
+                writeln!(f, "  {}", instruction(*pc, *instr))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Observer keeping the addresses written by the last instruction.
+#[derive(Default)]
+struct Writes(Vec<u16>);
+
+impl Observer for Writes {
+    fn step(&mut self, step: &Step) -> Result<(), String> {
+        self.0 = step
+            .accesses
+            .iter()
+            .filter(|access| access.write)
+            .map(|access| access.address)
+            .collect();
+        Ok(())
+    }
+}
+
+/// Runs random cases on the VM and on the reference model, stopping at the first divergence.
+///
+/// Case `i` is generated from `seed + i`, so a divergent case can be rerun on its own.
+///
+/// # Arguments
+///
+/// * `seed` - Seed of the first case.
+/// * `cases` - Number of cases.
+/// * `length` - Number of generated instructions per case.
+///
+/// # Returns
+///
+/// The first divergence, or `None` if the VM agreed with the model on every case.
+pub fn find_divergence(seed: u64, cases: usize, length: usize) -> Option<Divergence> {
+    (0..cases as u64).find_map(|case| check_case(seed.wrapping_add(case), length))
+}
+
+/// Runs a random case on the VM and on the reference model, comparing them after every
+/// instruction.
+///
+/// The case starts with random registers, condition codes and memory below the device page,
+/// and `length` random instructions at x3000. It runs for `length` instructions, or until
+/// the model reaches an instruction it leaves out, e.g. a `TRAP` or an access to the device
+/// page, which is not executed.
+///
+/// # Arguments
+///
+/// * `seed` - Seed the case is generated from.
+/// * `length` - Number of generated instructions.
+///
+/// # Returns
+///
+/// The first divergence, or `None` if the VM agreed with the model.
+pub fn check_case(seed: u64, length: usize) -> Option<Divergence> {
+    let mut rng = Rng::new(seed);
+    let mut model = Reference::new();
+    for word in &mut model.memory[..DEVICE_PAGE as usize] {
+        *word = rng.next_u16();
+    }
+    for offset in 0..length {
+        let opcode = OPCODES[rng.next_u64() as usize % OPCODES.len()];
+        let address = ORIGIN.wrapping_add(offset as u16);
+        if address < DEVICE_PAGE {
+            model.memory[address as usize] = opcode << 12 | (rng.next_u16() & 0x0FFF);
+        }
+    }
+    for register in &mut model.registers {
+        *register = rng.next_u16();
+    }
+    model.cond = [N, Z, P][rng.next_u64() as usize % 3];
+    model.pc = ORIGIN;
+
+    let mut vm = VM::new();
+    vm.add_observer(Box::new(Writes::default()));
+    let ram = model.memory[..DEVICE_PAGE as usize].to_vec();
+    vm.load_image(&Image::new("random", 0, ram));
+    for (register, value) in model.registers.iter().enumerate() {
+        vm.registers_mut()
+            .write(Register::from(register as u16), *value);
+    }
+    vm.registers_mut().write(Register::COND, model.cond);
+    vm.registers_mut().write(Register::PC, model.pc);
+
+    let mut trace = Vec::new();
+    for _ in 0..length {
+        let pc = model.pc;
+        let instr = model.memory[pc as usize];
+        if model.step().is_err() {
+            break;
+        }
+        let differences = match vm.run_for(1) {
+            Ok(_) => compare(&vm, &model),
+            Err(e) => vec![format!("VM error: {}", e)],
+        };
+        if !differences.is_empty() {
+            return Some(Divergence {
+                seed,
+                trace,
+                pc,
+                instr,
+                differences,
+            });
+        }
+        trace.push((pc, instr));
+    }
+    None
+}
+
+/// Describes how the VM differs from the model after an instruction.
+fn compare(vm: &VM, model: &Reference) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut differ = |name: String, vm: u16, model: u16| {
+        if vm != model {
+            differences.push(format!(
+                "{}: VM x{:04X}, reference x{:04X}",
+                name, vm, model
+            ));
+        }
+    };
+    let registers = vm.registers();
+    for (register, value) in model.registers.iter().enumerate() {
+        let vm = registers.read(Register::from(register as u16));
+        differ(format!("R{}", register), vm, *value);
+    }
+    differ("PC".to_string(), registers.read(Register::PC), model.pc);
+    differ(
+        "COND".to_string(),
+        registers.read(Register::COND),
+        model.cond,
+    );
+
+    let written = vm.observer::<Writes>().map_or(&[][..], |w| &w.0);
+    let expected: Vec<u16> = model.written.into_iter().collect();
+    for address in written.iter().chain(&expected) {
+        differ(
+            format!("memory x{:04X}", address),
+            vm.peek(*address),
+            model.memory[*address as usize],
+        );
+    }
+    if written != expected {
+        differences.push(format!(
+            "written addresses: VM {:04X?}, reference {:04X?}",
+            written, expected
+        ));
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vm_agrees_with_the_reference_model() {
+        if let Some(divergence) = find_divergence(1, 200, 32) {
+            panic!("{}", divergence);
+        }
+    }
+
+    #[test]
+    fn divergences_show_the_instructions_executed_before() {
+        let divergence = Divergence {
+            seed: 7,
+            trace: vec![(0x3000, 0x1021)],
+            pc: 0x3001,
+            instr: 0x41C0,
+            differences: vec!["PC: VM x3002, reference x4000".to_string()],
+        };
+        assert_eq!(
+            divergence.to_string(),
+            "Case seed 7: x3001 JSRR R7 (x41C0) diverged after 1 instructions\n  \
+             PC: VM x3002, reference x4000\n\
+             Executed before:\n  x3000 ADD R0, R0, #1 (x1021)\n"
+        );
+    }
+}
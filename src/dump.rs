@@ -0,0 +1,229 @@
+use crate::formats::{self, ImageFormat};
+use crate::isa::disassembler::disassemble;
+use crate::loader::Image;
+use crate::symbols::SymbolTable;
+use crate::utils::parse_word;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// Number of words on each line of a hexdump.
+const WORDS_PER_LINE: usize = 8;
+
+/// Ways a memory region can be dumped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Hexadecimal words with the low byte of each shown as ASCII, repeated lines collapsed.
+    Hexdump,
+    /// One instruction per line, with its address, contents and label.
+    Disassembly,
+    /// A program image, e.g. an `.obj` file that can be loaded again.
+    Image(ImageFormat),
+}
+
+impl DumpFormat {
+    /// Parses `hexdump`, `disasm` or an image format (`obj`, `hex`, `bin`, `ihex` or `raw`).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error for any other text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "hexdump" => Ok(DumpFormat::Hexdump),
+            "disasm" => Ok(DumpFormat::Disassembly),
+            _ => ImageFormat::parse(text)
+                .map(DumpFormat::Image)
+                .map_err(|_| {
+                    format!(
+                        "Unknown dump format '{}', expected hexdump, disasm, obj, hex, bin, ihex or raw",
+                        text
+                    )
+                }),
+        }
+    }
+}
+
+/// Parses a range of addresses given as `START-END` (inclusive) or a single address.
+///
+/// Each address is a number (`x3000`, `0x3000` or decimal) or a label of `symbols`,
+/// optionally followed by `+N`, e.g. `RESULT+9`.
+///
+/// # Errors
+///
+/// Returns a `String` error if an address is invalid or the range is empty.
+pub fn parse_range(text: &str, symbols: &SymbolTable) -> Result<RangeInclusive<u16>, String> {
+    let address = |text: &str| {
+        let (base, offset) = match text.split_once('+') {
+            Some((base, offset)) => (base, parse_word(offset)?),
+            None => (text, 0),
+        };
+        let base = match symbols.address(base) {
+            Some(address) => address,
+            None => parse_word(base).map_err(|_| format!("Unknown address '{}'", base))?,
+        };
+        Ok::<u16, String>(base.wrapping_add(offset))
+    };
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (address(start)?, address(end)?),
+        None => (address(text)?, address(text)?),
+    };
+    if start > end {
+        return Err(format!("Empty range '{}'", text));
+    }
+    Ok(start..=end)
+}
+
+/// Writes the words of a memory region in the given format.
+///
+/// # Arguments
+///
+/// * `words` - Contents of the region.
+/// * `origin` - Address of the first word.
+/// * `format` - How to write the words.
+/// * `symbols` - Labels shown in disassembly.
+/// * `writer` - Destination of the dump.
+///
+/// # Errors
+///
+/// Returns a `String` error if writing fails.
+pub fn write_dump(
+    words: &[u16],
+    origin: u16,
+    format: DumpFormat,
+    symbols: &SymbolTable,
+    writer: &mut dyn Write,
+) -> Result<(), String> {
+    match format {
+        DumpFormat::Hexdump => write_hexdump(words, origin, writer),
+        DumpFormat::Disassembly => write_disassembly(words, origin, symbols, writer),
+        DumpFormat::Image(format) => {
+            let image = Image::new("dump", origin, words.to_vec());
+            writer
+                .write_all(&formats::encode(format, &image))
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Writes words as lines of `WORDS_PER_LINE` words, collapsing repeated lines into `*`.
+fn write_hexdump(words: &[u16], origin: u16, writer: &mut dyn Write) -> Result<(), String> {
+    let mut previous: Option<&[u16]> = None;
+    let mut collapsed = false;
+    for (index, line) in words.chunks(WORDS_PER_LINE).enumerate() {
+        if previous == Some(line) && line.len() == WORDS_PER_LINE {
+            if !collapsed {
+                writeln!(writer, "*").map_err(|e| e.to_string())?;
+                collapsed = true;
+            }
+            continue;
+        }
+        previous = Some(line);
+        collapsed = false;
+        let address = origin.wrapping_add((index * WORDS_PER_LINE) as u16);
+        let hex: Vec<String> = line.iter().map(|word| format!("{:04X}", word)).collect();
+        let text: String = line
+            .iter()
+            .map(|word| printable(*word).unwrap_or('.'))
+            .collect();
+        writeln!(
+            writer,
+            "x{:04X}  {:<width$}  |{}|",
+            address,
+            hex.join(" "),
+            text,
+            width = WORDS_PER_LINE * 5 - 1
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Writes one disassembled instruction per line, annotating words that hold a character.
+fn write_disassembly(
+    words: &[u16],
+    origin: u16,
+    symbols: &SymbolTable,
+    writer: &mut dyn Write,
+) -> Result<(), String> {
+    for (offset, word) in words.iter().enumerate() {
+        let address = origin.wrapping_add(offset as u16);
+        let label = symbols.label(address).unwrap_or_default();
+        let mut line = format!(
+            "x{:04X}  {:04X}  {:<12} {}",
+            address,
+            word,
+            label,
+            disassemble(*word, address, symbols)
+        );
+        if let Some(c) = printable(*word) {
+            line = format!("{:<48} ; '{}'", line, c);
+        }
+        writeln!(writer, "{}", line.trim_end()).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Returns the character held by a word, if it is printable ASCII.
+fn printable(word: u16) -> Option<char> {
+    (0x20..0x7F).contains(&word).then_some(word as u8 as char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(words: &[u16], format: DumpFormat, symbols: &SymbolTable) -> String {
+        let mut output = Vec::new();
+        write_dump(words, 0x3000, format, symbols, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn hexdump_collapses_repeated_lines() {
+        let mut words = vec![0x0048, 0x0069];
+        words.resize(32, 0);
+        let text = dump(&words, DumpFormat::Hexdump, &SymbolTable::new());
+        assert_eq!(
+            text,
+            "x3000  0048 0069 0000 0000 0000 0000 0000 0000  |Hi......|\n\
+             x3008  0000 0000 0000 0000 0000 0000 0000 0000  |........|\n\
+             *\n"
+        );
+    }
+
+    #[test]
+    fn disassembly_shows_labels_and_characters() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("START", 0x3000);
+        symbols.insert("TEXT", 0x3002);
+        let text = dump(&[0xE001, 0xF025, 0x0041], DumpFormat::Disassembly, &symbols);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "x3000  E001  START        LEA R0, TEXT");
+        assert_eq!(lines[1], "x3001  F025               HALT");
+        assert!(lines[2].starts_with("x3002  0041  TEXT         NOP"));
+        assert!(lines[2].ends_with("; 'A'"));
+    }
+
+    #[test]
+    fn image_formats_can_be_loaded_again() {
+        let bytes = dump(
+            &[0x1234],
+            DumpFormat::Image(ImageFormat::Hex),
+            &SymbolTable::new(),
+        );
+        assert_eq!(bytes, "3000\n1234\n");
+    }
+
+    #[test]
+    fn parse_range_accepts_addresses_and_labels() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("RESULT", 0x4000);
+        assert_eq!(parse_range("x3000-x30FF", &symbols), Ok(0x3000..=0x30FF));
+        assert_eq!(
+            parse_range("RESULT-RESULT+9", &symbols),
+            Ok(0x4000..=0x4009)
+        );
+        assert_eq!(parse_range("x3000", &symbols), Ok(0x3000..=0x3000));
+        assert!(parse_range("x3001-x3000", &symbols).is_err());
+        assert!(parse_range("MISSING", &symbols).is_err());
+    }
+}
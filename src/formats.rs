@@ -0,0 +1,428 @@
+use crate::loader::Image;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// Formats program images can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Big-endian binary with a leading origin word, as written by the LC-3 assembler (`.obj`).
+    Object,
+    /// lc3tools ASCII hexadecimal: the origin then one word per line, e.g. `3000` (`.hex`).
+    Hex,
+    /// lc3tools ASCII binary: the origin then one word per line of 16 digits (`.bin`).
+    Bin,
+    /// Intel HEX records, with big-endian words at byte address `2 * address` (`.ihex`).
+    IntelHex,
+    /// Headless big-endian binary loaded at an explicit origin (`.raw`).
+    Raw,
+}
+
+impl ImageFormat {
+    /// Parses `obj`, `hex`, `bin`, `ihex` or `raw`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error for any other text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "obj" => Ok(ImageFormat::Object),
+            "hex" => Ok(ImageFormat::Hex),
+            "bin" => Ok(ImageFormat::Bin),
+            "ihex" => Ok(ImageFormat::IntelHex),
+            "raw" => Ok(ImageFormat::Raw),
+            _ => Err(format!(
+                "Unknown image format '{}', expected obj, hex, bin, ihex or raw",
+                text
+            )),
+        }
+    }
+
+    /// Selects the format from the extension of `path` and, when it is ambiguous or
+    /// missing, from the contents of the file.
+    ///
+    /// A `.hex` file made of `:` records is Intel HEX, and a `.bin` file that is not
+    /// ASCII binary text is raw. Files with other extensions are recognized as Intel HEX,
+    /// ASCII binary or ASCII hexadecimal by their contents, and as object files otherwise.
+    pub fn detect(path: &Path, bytes: &[u8]) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("obj") => ImageFormat::Object,
+            Some("ihex" | "ihx") => ImageFormat::IntelHex,
+            Some("raw") => ImageFormat::Raw,
+            Some("hex") if looks_like_intel_hex(bytes) => ImageFormat::IntelHex,
+            Some("hex") => ImageFormat::Hex,
+            Some("bin") if looks_like_words(bytes, 16, |c| c == '0' || c == '1') => {
+                ImageFormat::Bin
+            }
+            Some("bin") => ImageFormat::Raw,
+            _ if looks_like_intel_hex(bytes) => ImageFormat::IntelHex,
+            _ if looks_like_words(bytes, 16, |c| c == '0' || c == '1') => ImageFormat::Bin,
+            _ if looks_like_words(bytes, 4, |c| c.is_ascii_hexdigit()) => ImageFormat::Hex,
+            _ => ImageFormat::Object,
+        }
+    }
+}
+
+/// Reads a program image in any supported format.
+///
+/// # Arguments
+///
+/// * `path` - Path to the image. Its file name without extension names the images.
+/// * `format` - Format of the file, detected from its extension and contents if `None`.
+/// * `origin` - Load address of raw images, which have no origin of their own.
+///
+/// # Returns
+///
+/// The contiguous blocks of the image: a single one, except for Intel HEX files with gaps.
+///
+/// # Errors
+///
+/// Returns a `String` error if the file cannot be read or is not valid in its format.
+pub fn read(
+    path: &Path,
+    format: Option<ImageFormat>,
+    origin: Option<u16>,
+) -> Result<Vec<Image>, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let name = path
+        .file_stem()
+        .map_or_else(|| path.to_string_lossy(), |stem| stem.to_string_lossy());
+    let format = format.unwrap_or_else(|| ImageFormat::detect(path, &bytes));
+    decode(format, &name, &bytes, origin)
+}
+
+/// Decodes a program image.
+///
+/// # Errors
+///
+/// Returns a `String` error if the contents are not valid in the given format, or if a
+/// raw image is decoded without an origin.
+pub fn decode(
+    format: ImageFormat,
+    name: &str,
+    bytes: &[u8],
+    origin: Option<u16>,
+) -> Result<Vec<Image>, String> {
+    match format {
+        ImageFormat::Object => Ok(vec![Image::from_bytes(name, bytes)?]),
+        ImageFormat::Raw => {
+            let origin = origin.ok_or("Raw images need an origin")?;
+            let mut with_origin = origin.to_be_bytes().to_vec();
+            with_origin.extend_from_slice(bytes);
+            Ok(vec![Image::from_bytes(name, &with_origin)?])
+        }
+        ImageFormat::Hex => decode_text(name, bytes, 16, 4),
+        ImageFormat::Bin => decode_text(name, bytes, 2, 16),
+        ImageFormat::IntelHex => decode_intel_hex(name, bytes),
+    }
+}
+
+/// Encodes a program image.
+///
+/// # Returns
+///
+/// The contents of the file. The origin is lost when encoding as raw.
+pub fn encode(format: ImageFormat, image: &Image) -> Vec<u8> {
+    let words = std::iter::once(image.origin).chain(image.words.iter().copied());
+    match format {
+        ImageFormat::Object => words.flat_map(u16::to_be_bytes).collect(),
+        ImageFormat::Raw => image.words.iter().flat_map(|w| w.to_be_bytes()).collect(),
+        ImageFormat::Hex => words
+            .map(|w| format!("{:04X}\n", w))
+            .collect::<String>()
+            .into(),
+        ImageFormat::Bin => words
+            .map(|w| format!("{:016b}\n", w))
+            .collect::<String>()
+            .into(),
+        ImageFormat::IntelHex => encode_intel_hex(image).into(),
+    }
+}
+
+/// Writes a program image to a file.
+///
+/// # Errors
+///
+/// Returns a `String` error if the file cannot be written.
+pub fn write(path: &Path, format: ImageFormat, image: &Image) -> Result<(), String> {
+    fs::write(path, encode(format, image)).map_err(|e| e.to_string())
+}
+
+/// Checks whether the first line of a file is an Intel HEX record.
+fn looks_like_intel_hex(bytes: &[u8]) -> bool {
+    bytes.trim_ascii_start().starts_with(b":")
+}
+
+/// Checks whether a file is text made of words of `digits` characters accepted by `is_digit`.
+fn looks_like_words(bytes: &[u8], digits: usize, is_digit: fn(char) -> bool) -> bool {
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return false;
+    };
+    let mut words = text.lines().map(strip_comment).filter(|l| !l.is_empty());
+    words.clone().next().is_some()
+        && words.all(|word| word.len() == digits && word.chars().all(is_digit))
+}
+
+/// Removes the `;` comment and surrounding whitespace from a line of a text image.
+fn strip_comment(line: &str) -> &str {
+    line.split(';').next().unwrap_or_default().trim()
+}
+
+/// Decodes an ASCII image whose lines hold the origin followed by one word each.
+fn decode_text(name: &str, bytes: &[u8], radix: u32, digits: usize) -> Result<Vec<Image>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "Image is not text".to_string())?;
+    let mut words = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let word = strip_comment(line);
+        if word.is_empty() {
+            continue;
+        }
+        match u16::from_str_radix(word, radix) {
+            Ok(value) if word.len() == digits => words.push(value),
+            _ => return Err(format!("Invalid word '{}' on line {}", word, number + 1)),
+        }
+    }
+    let bytes: Vec<u8> = words.into_iter().flat_map(u16::to_be_bytes).collect();
+    Ok(vec![Image::from_bytes(name, &bytes)?])
+}
+
+/// Decodes Intel HEX records into blocks of contiguous words.
+fn decode_intel_hex(name: &str, bytes: &[u8]) -> Result<Vec<Image>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "Image is not text".to_string())?;
+    // Bytes of the image by byte address
+    let mut data = std::collections::BTreeMap::new();
+    let mut base = 0u32;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |reason: &str| format!("Invalid record on line {}: {}", number + 1, reason);
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| invalid("missing ':'"))?;
+        if !hex.is_ascii() {
+            return Err(invalid("not hexadecimal"));
+        }
+        if hex.len() % 2 != 0 || hex.len() < 10 {
+            return Err(invalid("truncated"));
+        }
+        let record = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid("not hexadecimal"))?;
+        let length = record[0] as usize;
+        if record.len() != length + 5 {
+            return Err(invalid("wrong length"));
+        }
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(invalid("wrong checksum"));
+        }
+        let address = u16::from_be_bytes([record[1], record[2]]) as u32;
+        let payload = &record[4..4 + length];
+        let expected = match record[3] {
+            0x00 => None,
+            0x01 => Some(0),
+            0x02 | 0x04 => Some(2),
+            0x03 | 0x05 => Some(4),
+            kind => return Err(invalid(&format!("unsupported record type {:02X}", kind))),
+        };
+        if expected.is_some_and(|expected| expected != length) {
+            return Err(invalid(&format!(
+                "invalid length for record type {:02X}",
+                record[3]
+            )));
+        }
+        match record[3] {
+            0x00 => {
+                for (offset, byte) in payload.iter().enumerate() {
+                    let byte_address = base + address + offset as u32;
+                    if byte_address >= 0x20000 {
+                        return Err(invalid("address outside of memory"));
+                    }
+                    data.insert(byte_address, *byte);
+                }
+            }
+            0x01 => break,
+            0x02 => base = (u16::from_be_bytes([payload[0], payload[1]]) as u32) << 4,
+            0x04 => base = (u16::from_be_bytes([payload[0], payload[1]]) as u32) << 16,
+            _ => {}
+        }
+    }
+    if data.is_empty() {
+        return Err("Image has no data records".to_string());
+    }
+
+    let mut images: Vec<Image> = Vec::new();
+    let word_addresses: std::collections::BTreeSet<u32> = data.keys().map(|a| a / 2).collect();
+    for address in word_addresses {
+        let byte = |a: u32| data.get(&a).copied().unwrap_or(0);
+        let word = u16::from_be_bytes([byte(2 * address), byte(2 * address + 1)]);
+        let address = address as u16;
+        match images.last_mut() {
+            Some(image) if image.origin as usize + image.words.len() == address as usize => {
+                image.words.push(word)
+            }
+            _ => images.push(Image::new(name, address, vec![word])),
+        }
+    }
+    Ok(images)
+}
+
+/// Encodes an image as Intel HEX data records of 16 bytes.
+fn encode_intel_hex(image: &Image) -> String {
+    fn record(text: &mut String, kind: u8, address: u16, payload: &[u8]) {
+        let mut bytes = vec![payload.len() as u8];
+        bytes.extend_from_slice(&address.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(payload);
+        let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_sub(*b));
+        text.push(':');
+        for byte in bytes.iter().chain([checksum].iter()) {
+            let _ = write!(text, "{:02X}", byte);
+        }
+        text.push('\n');
+    }
+
+    let mut text = String::new();
+    let bytes: Vec<u8> = image.words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    let mut upper = 0u16;
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let address = image.origin as u32 * 2 + offset as u32;
+        if (address >> 16) as u16 != upper {
+            upper = (address >> 16) as u16;
+            record(&mut text, 0x04, 0, &upper.to_be_bytes());
+        }
+        // Records must not cross a 64 KiB boundary
+        let length = 16
+            .min(bytes.len() - offset)
+            .min(0x10000 - (address & 0xFFFF) as usize);
+        record(
+            &mut text,
+            0x00,
+            address as u16,
+            &bytes[offset..offset + length],
+        );
+        offset += length;
+    }
+    record(&mut text, 0x01, 0, &[]);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Image {
+        Image::new("prog", 0x3000, vec![0x1234, 0xABCD, 0xF025])
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        for format in [
+            ImageFormat::Object,
+            ImageFormat::Hex,
+            ImageFormat::Bin,
+            ImageFormat::IntelHex,
+        ] {
+            let bytes = encode(format, &sample());
+            assert_eq!(decode(format, "prog", &bytes, None), Ok(vec![sample()]));
+        }
+        let raw = encode(ImageFormat::Raw, &sample());
+        assert_eq!(raw.len(), 6);
+        assert_eq!(
+            decode(ImageFormat::Raw, "prog", &raw, Some(0x3000)),
+            Ok(vec![sample()])
+        );
+        assert!(decode(ImageFormat::Raw, "prog", &raw, None).is_err());
+    }
+
+    #[test]
+    fn text_formats_accept_comments_and_reject_bad_words() {
+        let hex = b"3000 ; origin\n\n1234\nF025\n";
+        let images = decode(ImageFormat::Hex, "prog", hex, None).unwrap();
+        assert_eq!(images, [Image::new("prog", 0x3000, vec![0x1234, 0xF025])]);
+        assert!(decode(ImageFormat::Hex, "prog", b"3000\n12345\n", None).is_err());
+        assert!(decode(ImageFormat::Bin, "prog", b"0011000000000000\n0102\n", None).is_err());
+    }
+
+    #[test]
+    fn intel_hex_splits_blocks_and_checks_checksums() {
+        let text = ":046000001234567888\n:02800000F02569\n:00000001FF\n";
+        let images = decode(ImageFormat::IntelHex, "prog", text.as_bytes(), None).unwrap();
+        assert_eq!(
+            images,
+            [
+                Image::new("prog", 0x3000, vec![0x1234, 0x5678]),
+                Image::new("prog", 0x4000, vec![0xF025]),
+            ]
+        );
+        let corrupted = ":046000001234567889\n";
+        assert!(decode(ImageFormat::IntelHex, "prog", corrupted.as_bytes(), None).is_err());
+        let short_base = ":0100000400FB\n";
+        assert_eq!(
+            decode(ImageFormat::IntelHex, "prog", short_base.as_bytes(), None),
+            Err("Invalid record on line 1: invalid length for record type 04".to_string())
+        );
+        let multibyte = ":0\u{514}000001021F02556\n";
+        assert!(decode(ImageFormat::IntelHex, "prog", multibyte.as_bytes(), None).is_err());
+    }
+
+    #[test]
+    fn intel_hex_without_data_records_is_rejected() {
+        for text in [":00000001FF\n", ":020000040001F9\n:00000001FF\n", ""] {
+            assert_eq!(
+                decode(ImageFormat::IntelHex, "prog", text.as_bytes(), None),
+                Err("Image has no data records".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn intel_hex_uses_extended_addresses_above_x8000() {
+        let image = Image::new("os", 0xFFFF, vec![0x0102]);
+        let text = String::from_utf8(encode(ImageFormat::IntelHex, &image)).unwrap();
+        assert!(text.starts_with(":020000040001F9\n"));
+        assert_eq!(
+            decode(ImageFormat::IntelHex, "os", text.as_bytes(), None),
+            Ok(vec![image])
+        );
+    }
+
+    #[test]
+    fn detect_uses_extension_then_contents() {
+        let intel = b":00000001FF\n";
+        let bin = b"0011000000000000\n";
+        assert_eq!(
+            ImageFormat::detect(Path::new("a.hex"), intel),
+            ImageFormat::IntelHex
+        );
+        assert_eq!(
+            ImageFormat::detect(Path::new("a.hex"), b"3000\n"),
+            ImageFormat::Hex
+        );
+        assert_eq!(
+            ImageFormat::detect(Path::new("a.bin"), bin),
+            ImageFormat::Bin
+        );
+        assert_eq!(
+            ImageFormat::detect(Path::new("a.bin"), &[0x30, 0x00]),
+            ImageFormat::Raw
+        );
+        assert_eq!(
+            ImageFormat::detect(Path::new("a.obj"), bin),
+            ImageFormat::Object
+        );
+        assert_eq!(ImageFormat::detect(Path::new("a"), bin), ImageFormat::Bin);
+        assert_eq!(
+            ImageFormat::detect(Path::new("a"), &[0x30, 0x00]),
+            ImageFormat::Object
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use crate::formats::{decode, ImageFormat};
+use crate::hardware::display::Display;
+use crate::hardware::keyboard::Keyboard;
+use crate::hardware::registers::Register;
+use crate::hardware::rng::Rng;
+use crate::loader::{Image, Loader, OverlapPolicy};
+use crate::source_map::SourceMap;
+use crate::vm::{Fill, VM};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+/// File names the image target picks from, so the format is detected as when reading a file.
+const IMAGE_NAMES: [&str; 7] = [
+    "fuzz.obj",
+    "fuzz.hex",
+    "fuzz.bin",
+    "fuzz.ihex",
+    "fuzz.raw",
+    "fuzz.txt",
+    "fuzz",
+];
+
+/// Longest input generated by mutation.
+const MAX_INPUT: usize = 4096;
+
+/// Input handling code exercised by a fuzz target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Detecting, decoding and loading a program image.
+    Image,
+    /// Executing a single instruction on an arbitrary machine state.
+    Instruction,
+    /// Mapping an assembly source to its lines.
+    Assembly,
+}
+
+impl Target {
+    /// All the targets.
+    pub const ALL: [Target; 3] = [Target::Image, Target::Instruction, Target::Assembly];
+
+    /// Parses `image`, `instruction` or `assembly`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error for any other text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Target::ALL
+            .into_iter()
+            .find(|target| target.name() == text)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown fuzz target '{}', expected image, instruction or assembly",
+                    text
+                )
+            })
+    }
+
+    /// Returns the name of the target, which is also the name of its cargo-fuzz target and
+    /// corpus directory.
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Image => "image",
+            Target::Instruction => "instruction",
+            Target::Assembly => "assembly",
+        }
+    }
+
+    /// Feeds an input to the target. Errors are expected, only panics are bugs.
+    pub fn run(self, data: &[u8]) {
+        match self {
+            Target::Image => image(data),
+            Target::Instruction => instruction(data),
+            Target::Assembly => assembly(data),
+        }
+    }
+}
+
+/// Fuzz target for image loading.
+///
+/// The first byte picks the file name the format is detected from, the next two bytes are
+/// the origin of raw images and the rest is the contents of the file. The decoded images are
+/// added to a loader and loaded into a VM.
+pub fn image(data: &[u8]) {
+    let Some((&name, rest)) = data.split_first() else {
+        return;
+    };
+    let (origin, bytes) = match rest {
+        [high, low, bytes @ ..] => (u16::from_be_bytes([*high, *low]), bytes),
+        _ => (0, rest),
+    };
+    let name = IMAGE_NAMES[name as usize % IMAGE_NAMES.len()];
+    let format = ImageFormat::detect(Path::new(name), bytes);
+    let Ok(images) = decode(format, "fuzz", bytes, Some(origin)) else {
+        return;
+    };
+    let mut loader = Loader::new(OverlapPolicy::Warn);
+    for image in images {
+        let _ = image.range();
+        let _ = loader.add(image);
+    }
+    let _ = loader.memory_map();
+    loader.load(&mut VM::new());
+}
+
+/// Fuzz target for instruction execution.
+///
+/// The input starts with big-endian words: the instruction, the PC it is placed at, the
+/// PSR, the word filling the rest of memory and R0 to R7, missing words being zero. The
+/// rest of the input is the keyboard input; output is captured. The VM runs a single
+/// instruction.
+pub fn instruction(data: &[u8]) {
+    let header = data.len().min(24);
+    let mut words = data[..header]
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]));
+    let mut word = || words.next().unwrap_or(0);
+    let (instr, pc, psr, fill) = (word(), word(), word(), word());
+
+    let mut vm = VM::new();
+    if let Some(keyboard) = vm.device_mut::<Keyboard>() {
+        keyboard.set_input(&data[header..]);
+    }
+    if let Some(display) = vm.device_mut::<Display>() {
+        display.capture();
+    }
+    vm.fill_memory(Fill::Value(fill), &mut Rng::new(0));
+    vm.load_image(&Image::new("fuzz", pc, vec![instr]));
+    let registers = vm.registers_mut();
+    registers.set_psr(psr);
+    for register in 0..8 {
+        registers.write(Register::from(register), word());
+    }
+    registers.write(Register::PC, pc);
+    let _ = vm.run_for(1);
+}
+
+/// Fuzz target for assembly parsing: the input, decoded as UTF-8 with replacement
+/// characters, is mapped to its lines.
+pub fn assembly(data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    let _ = SourceMap::new().add_assembly("fuzz.asm", &text);
+}
+
+/// Input that made a target panic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crash {
+    /// Where the input comes from: a corpus file, or a mutation of one.
+    pub origin: String,
+    /// The input.
+    pub input: Vec<u8>,
+    /// The panic message.
+    pub message: String,
+}
+
+/// Feeds an input to a target, catching a panic.
+///
+/// # Errors
+///
+/// Returns the panic message as a `String` error if the target panicked.
+pub fn check(target: Target, data: &[u8]) -> Result<(), String> {
+    panic::catch_unwind(AssertUnwindSafe(|| target.run(data))).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "panic".to_string())
+    })
+}
+
+/// Reads the files of a corpus directory, sorted by name so runs are deterministic.
+///
+/// # Errors
+///
+/// Returns a `String` error if the directory or one of its files cannot be read.
+pub fn read_corpus(dir: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .map(|entry| entry.map(|e| e.path()).map_err(|e| e.to_string()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+    paths
+        .into_iter()
+        .filter(|path| path.is_file())
+        .map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let bytes = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok((name.into_owned(), bytes))
+        })
+        .collect()
+}
+
+/// Deterministic, offline stand-in for libFuzzer: feeds every corpus input to a target,
+/// then `mutations` random mutations of them.
+///
+/// # Arguments
+///
+/// * `target` - The target to run.
+/// * `corpus` - Named inputs, e.g. from `read_corpus`.
+/// * `mutations` - Number of mutated inputs.
+/// * `seed` - Seed of the mutations, so a run can be reproduced.
+///
+/// # Returns
+///
+/// The inputs that made the target panic.
+pub fn run_corpus(
+    target: Target,
+    corpus: &[(String, Vec<u8>)],
+    mutations: usize,
+    seed: u64,
+) -> Vec<Crash> {
+    let mut crashes = Vec::new();
+    let mut run = |origin: String, input: Vec<u8>| {
+        if let Err(message) = check(target, &input) {
+            crashes.push(Crash {
+                origin,
+                input,
+                message,
+            });
+        }
+    };
+    for (name, input) in corpus {
+        run(name.clone(), input.clone());
+    }
+    let mut rng = Rng::new(seed);
+    for mutation in 0..mutations {
+        let (name, mut input) = match corpus {
+            [] => ("empty input".to_string(), Vec::new()),
+            _ => corpus[rng.next_u64() as usize % corpus.len()].clone(),
+        };
+        for _ in 0..=rng.next_u64() % 4 {
+            mutate(&mut input, &mut rng);
+        }
+        run(format!("mutation {} of {}", mutation, name), input);
+    }
+    crashes
+}
+
+/// Applies a random mutation: flipping a bit, replacing, inserting or removing a byte, or
+/// duplicating a chunk.
+fn mutate(input: &mut Vec<u8>, rng: &mut Rng) {
+    let byte = rng.next_u64() as u8;
+    if input.is_empty() {
+        input.push(byte);
+        return;
+    }
+    let at = rng.next_u64() as usize % input.len();
+    match rng.next_u64() % 5 {
+        0 => input[at] ^= 1 << (byte % 8),
+        1 => input[at] = byte,
+        2 if input.len() < MAX_INPUT => input.insert(at, byte),
+        3 => {
+            input.remove(at);
+        }
+        _ => {
+            let end = (at + 1 + byte as usize % 16).min(input.len());
+            let chunk = input[at..end].to_vec();
+            if input.len() + chunk.len() <= MAX_INPUT {
+                input.splice(at..at, chunk);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corpus_and_mutations_do_not_panic() {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus");
+        for target in Target::ALL {
+            let inputs = read_corpus(&corpus.join(target.name())).unwrap();
+            assert!(!inputs.is_empty());
+            let crashes = run_corpus(target, &inputs, 300, 1);
+            assert!(crashes.is_empty(), "{:?}", crashes.first());
+        }
+    }
+
+    #[test]
+    fn trap_with_unknown_vector_does_not_panic() {
+        // TRAP x00 at x0000, used to panic while decoding the trap vector
+        assert_eq!(check(Target::Instruction, &[0xF0, 0x00]), Ok(()));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::hardware::device::{Device, Dma};
+use crate::hardware::memory::MemoryMappedRegister;
+use crate::isa::interrupts::Interrupt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Default number of instructions the VM is assumed to execute per second.
+pub const DEFAULT_CLOCK_HZ: u64 = 1_000_000;
+
+/// Sample rate of the rendered audio.
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// Control register bit that is set while a tone is playing; writing it starts a tone.
+pub const TONE_PLAYING: u16 = 1 << 15;
+
+/// A tone played by the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tone {
+    /// Cycle at which the tone started.
+    pub start: u64,
+    /// Cycle at which the tone ends.
+    pub end: u64,
+    /// Frequency in hertz.
+    pub frequency: u16,
+    /// Volume, from 0 (silent) to 255 (loudest).
+    pub volume: u8,
+}
+
+/// Tone generator (beeper) mapped at `TGFR`-`TGCR`.
+///
+/// A program sets the frequency (`TGFR`, in hertz), duration (`TGDR`, in milliseconds) and
+/// volume (`TGVR`, 0 to 255) and then writes bit 15 of `TGCR` to start a square-wave tone.
+/// Bit 15 of `TGCR` reads as set until the tone is over; starting a new tone cuts the current one.
+///
+/// Time is measured in executed instructions: the generator assumes the VM runs at
+/// `clock_hz` instructions per second. The tones are not played on a sound card but
+/// rendered to a WAV file with `write_wav`.
+pub struct ToneGenerator {
+    /// Instructions executed per second of audio.
+    clock_hz: u64,
+    /// Instructions executed so far.
+    cycle: u64,
+    /// Frequency register contents.
+    frequency: u16,
+    /// Duration register contents.
+    duration: u16,
+    /// Volume register contents.
+    volume: u16,
+    /// Tones played so far, in order.
+    tones: Vec<Tone>,
+}
+
+impl Default for ToneGenerator {
+    fn default() -> Self {
+        Self::new(DEFAULT_CLOCK_HZ)
+    }
+}
+
+impl ToneGenerator {
+    /// Creates a new, silent tone generator.
+    ///
+    /// # Parameters
+    ///
+    /// - `clock_hz`: Instructions executed per second of audio.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `ToneGenerator`.
+    pub fn new(clock_hz: u64) -> Self {
+        Self {
+            clock_hz: clock_hz.max(1),
+            cycle: 0,
+            frequency: 440,
+            duration: 0,
+            volume: 255,
+            tones: Vec::new(),
+        }
+    }
+
+    /// Returns the tones played so far.
+    pub fn tones(&self) -> &[Tone] {
+        &self.tones
+    }
+
+    /// Renders the tones played so far as 16-bit mono PCM samples.
+    ///
+    /// The audio lasts until the end of the last tone; gaps between tones are silent.
+    pub fn render(&self) -> Vec<i16> {
+        let end = self.tones.iter().map(|t| t.end).max().unwrap_or(0);
+        let mut samples = vec![0i16; self.sample_at(end)];
+        for tone in &self.tones {
+            if tone.frequency == 0 {
+                continue;
+            }
+            let amplitude = (tone.volume as i32 * i16::MAX as i32 / 255) as i16;
+            let start = self.sample_at(tone.start);
+            let end = self.sample_at(tone.end);
+            let half_period = SAMPLE_RATE as f64 / (2.0 * tone.frequency as f64);
+            for (i, sample) in samples[start..end].iter_mut().enumerate() {
+                let high = (i as f64 / half_period) as u64 & 1 == 0;
+                *sample = if high { amplitude } else { -amplitude };
+            }
+        }
+        samples
+    }
+
+    /// Writes the tones played so far to a WAV file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the file cannot be written.
+    pub fn write_wav(&self, path: &Path) -> Result<(), String> {
+        let samples = self.render();
+        let data_len = (samples.len() * 2) as u32;
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut writer = BufWriter::new(file);
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(36 + data_len).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes()); // format chunk size
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&1u16.to_le_bytes()); // mono
+        header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        header.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // byte rate
+        header.extend_from_slice(&2u16.to_le_bytes()); // block align
+        header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_len.to_le_bytes());
+        writer.write_all(&header).map_err(|e| e.to_string())?;
+        for sample in samples {
+            writer
+                .write_all(&sample.to_le_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        writer.flush().map_err(|e| e.to_string())
+    }
+
+    /// Converts a cycle to the index of the corresponding sample.
+    fn sample_at(&self, cycle: u64) -> usize {
+        (cycle as u128 * SAMPLE_RATE as u128 / self.clock_hz as u128) as usize
+    }
+
+    /// Returns `true` while the last tone is playing.
+    fn playing(&self) -> bool {
+        self.tones.last().is_some_and(|t| self.cycle < t.end)
+    }
+
+    /// Starts a tone with the current frequency, duration and volume.
+    fn start(&mut self) {
+        let cycle = self.cycle;
+        if let Some(current) = self.tones.last_mut() {
+            current.end = current.end.min(cycle);
+        }
+        let length = self.duration as u64 * self.clock_hz / 1000;
+        self.tones.push(Tone {
+            start: cycle,
+            end: cycle + length,
+            frequency: self.frequency,
+            volume: self.volume.min(255) as u8,
+        });
+    }
+}
+
+impl Device for ToneGenerator {
+    fn read(&mut self, address: u16) -> Result<u16, String> {
+        match address {
+            a if a == MemoryMappedRegister::TGFR as u16 => Ok(self.frequency),
+            a if a == MemoryMappedRegister::TGDR as u16 => Ok(self.duration),
+            a if a == MemoryMappedRegister::TGVR as u16 => Ok(self.volume),
+            a if a == MemoryMappedRegister::TGCR as u16 => {
+                Ok(if self.playing() { TONE_PLAYING } else { 0 })
+            }
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        match address {
+            a if a == MemoryMappedRegister::TGFR as u16 => self.frequency = value,
+            a if a == MemoryMappedRegister::TGDR as u16 => self.duration = value,
+            a if a == MemoryMappedRegister::TGVR as u16 => self.volume = value,
+            a if a == MemoryMappedRegister::TGCR as u16 && value & TONE_PLAYING != 0 => {
+                self.start()
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, _memory: &mut Dma) -> Option<Interrupt> {
+        self.cycle += 1;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_path;
+
+    fn play(generator: &mut ToneGenerator, frequency: u16, duration: u16) {
+        generator.write(MemoryMappedRegister::TGFR as u16, frequency);
+        generator.write(MemoryMappedRegister::TGDR as u16, duration);
+        generator.write(MemoryMappedRegister::TGCR as u16, TONE_PLAYING);
+    }
+
+    #[test]
+    fn tone_plays_for_its_duration_in_cycles() {
+        // 1000 instructions per second: one instruction per millisecond
+        let mut generator = ToneGenerator::new(1000);
+        play(&mut generator, 440, 3);
+        for _ in 0..2 {
+            generator.tick(&mut Dma::new(&mut []));
+            assert_eq!(
+                generator.read(MemoryMappedRegister::TGCR as u16).unwrap(),
+                TONE_PLAYING
+            );
+        }
+        generator.tick(&mut Dma::new(&mut []));
+        assert_eq!(
+            generator.read(MemoryMappedRegister::TGCR as u16).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn new_tone_cuts_current_one() {
+        let mut generator = ToneGenerator::new(1000);
+        play(&mut generator, 440, 100);
+        generator.tick(&mut Dma::new(&mut []));
+        play(&mut generator, 880, 10);
+        assert_eq!(generator.tones()[0].end, 1);
+        assert_eq!(generator.tones()[1].start, 1);
+        assert_eq!(generator.tones()[1].end, 11);
+    }
+
+    #[test]
+    fn render_produces_square_wave_after_silence() {
+        let mut generator = ToneGenerator::new(SAMPLE_RATE as u64);
+        generator.write(MemoryMappedRegister::TGVR as u16, 255);
+        for _ in 0..10 {
+            generator.tick(&mut Dma::new(&mut []));
+        }
+        // 11025 Hz: two samples high, two samples low
+        play(&mut generator, 11_025, 1);
+        let samples = generator.render();
+        assert_eq!(samples.len(), 10 + 44);
+        assert!(samples[..10].iter().all(|&s| s == 0));
+        assert_eq!(samples[10..14], [i16::MAX, i16::MAX, -i16::MAX, -i16::MAX]);
+    }
+
+    #[test]
+    fn write_wav_writes_pcm_header() {
+        let mut generator = ToneGenerator::new(SAMPLE_RATE as u64);
+        play(&mut generator, 440, 10);
+        let path = temp_path("tone.wav");
+        generator.write_wav(&path).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(
+            u32::from_le_bytes(bytes[40..44].try_into().unwrap()),
+            441 * 2
+        );
+        assert_eq!(bytes.len(), 44 + 441 * 2);
+        std::fs::remove_file(path).unwrap();
+    }
+}
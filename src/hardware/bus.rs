@@ -0,0 +1,254 @@
+use crate::hardware::device::{Device, Dma};
+use crate::isa::interrupts::Interrupt;
+use std::any::Any;
+use std::ops::RangeInclusive;
+
+/// Range of addresses reserved for memory-mapped device registers.
+pub const DEVICE_PAGE: RangeInclusive<u16> = 0xFE00..=0xFFFF;
+
+/// A device together with the range of addresses it is mapped to.
+struct Mapping {
+    range: RangeInclusive<u16>,
+    device: Box<dyn Device>,
+}
+
+/// Bus connecting memory-mapped devices to the memory of the LC-3 VM.
+///
+/// Each device is mapped to a range of addresses; accesses to addresses not claimed by
+/// any device fall through to main memory.
+#[derive(Default)]
+pub struct Bus {
+    /// Registered devices, in registration order.
+    mappings: Vec<Mapping>,
+    /// Lowest address claimed by any device, used to skip the lookup for regular memory.
+    lowest: Option<u16>,
+    /// Whether a device may have been changed by a write or registration since the last
+    /// call to `take_changed`.
+    changed: bool,
+}
+
+impl Bus {
+    /// Creates a new `Bus` with no devices.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Bus`.
+    pub fn new() -> Self {
+        Self {
+            mappings: Vec::new(),
+            lowest: None,
+            changed: false,
+        }
+    }
+
+    /// Maps a device to a range of addresses.
+    ///
+    /// # Parameters
+    ///
+    /// - `range`: The addresses handled by the device.
+    /// - `device`: The device to register.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the range is empty or overlaps the range of another device.
+    pub fn register(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Box<dyn Device>,
+    ) -> Result<(), String> {
+        if range.is_empty() {
+            return Err("Device address range is empty".to_string());
+        }
+        if let Some(mapping) = self
+            .mappings
+            .iter()
+            .find(|m| range.start() <= m.range.end() && m.range.start() <= range.end())
+        {
+            return Err(format!(
+                "Device range x{:04X}-x{:04X} overlaps device at x{:04X}-x{:04X}",
+                range.start(),
+                range.end(),
+                mapping.range.start(),
+                mapping.range.end()
+            ));
+        }
+        self.lowest = Some(
+            self.lowest
+                .map_or(*range.start(), |l| l.min(*range.start())),
+        );
+        self.mappings.push(Mapping { range, device });
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Checks whether an address is claimed by a device.
+    pub fn is_mapped(&self, address: u16) -> bool {
+        self.lowest.is_some_and(|l| address >= l)
+            && self.mappings.iter().any(|m| m.range.contains(&address))
+    }
+
+    /// Reads from the device mapped at `address`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if no device is mapped at the address, otherwise the value read by the device.
+    pub fn read(&mut self, address: u16) -> Result<Option<u16>, String> {
+        match self.mapping_mut(address) {
+            Some(mapping) => mapping.device.read(address).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes to the device mapped at `address`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a device handled the write, `false` if the address belongs to main memory.
+    pub fn write(&mut self, address: u16, value: u16) -> bool {
+        match self.mapping_mut(address) {
+            Some(mapping) => {
+                mapping.device.write(address, value);
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Advances every device by one instruction.
+    ///
+    /// # Parameters
+    ///
+    /// - `memory`: The main memory, for devices performing direct memory access.
+    ///
+    /// # Returns
+    ///
+    /// The pending interrupt with the highest priority, if any.
+    pub fn tick(&mut self, memory: &mut Dma) -> Option<Interrupt> {
+        let mut pending: Option<Interrupt> = None;
+        for mapping in &mut self.mappings {
+            if let Some(irq) = mapping.device.tick(memory) {
+                if pending.is_none_or(|p| irq.priority > p.priority) {
+                    pending = Some(irq);
+                }
+            }
+        }
+        pending
+    }
+
+    /// Returns the first registered device of type `T`.
+    pub fn device<T: Device>(&self) -> Option<&T> {
+        self.mappings
+            .iter()
+            .find_map(|m| (m.device.as_ref() as &dyn Any).downcast_ref::<T>())
+    }
+
+    /// Returns a mutable reference to the first registered device of type `T`.
+    pub fn device_mut<T: Device>(&mut self) -> Option<&mut T> {
+        self.changed = true;
+        self.mappings
+            .iter_mut()
+            .find_map(|m| (m.device.as_mut() as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    /// Checks whether a device was written, registered or borrowed mutably since the last
+    /// call, so that state derived from the devices only needs to be refreshed then.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Finds the mapping that handles `address`.
+    fn mapping_mut(&mut self, address: u16) -> Option<&mut Mapping> {
+        if self.lowest.is_none_or(|l| address < l) {
+            return None;
+        }
+        self.mappings
+            .iter_mut()
+            .find(|m| m.range.contains(&address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device that stores a single register and requests an interrupt when it is non-zero.
+    struct Latch {
+        value: u16,
+        priority: u8,
+    }
+
+    impl Device for Latch {
+        fn read(&mut self, _address: u16) -> Result<u16, String> {
+            Ok(self.value)
+        }
+
+        fn write(&mut self, _address: u16, value: u16) {
+            self.value = value;
+        }
+
+        fn tick(&mut self, _memory: &mut Dma) -> Option<Interrupt> {
+            (self.value != 0).then_some(Interrupt {
+                vector: 0x90,
+                priority: self.priority,
+            })
+        }
+    }
+
+    fn latch(priority: u8) -> Box<Latch> {
+        Box::new(Latch { value: 0, priority })
+    }
+
+    #[test]
+    fn unmapped_addresses_fall_through() {
+        let mut bus = Bus::new();
+        bus.register(0xFE10..=0xFE11, latch(1)).unwrap();
+        assert_eq!(bus.read(0x3000).unwrap(), None);
+        assert_eq!(bus.read(0xFE12).unwrap(), None);
+        assert!(!bus.write(0xFE0F, 1));
+    }
+
+    #[test]
+    fn writes_to_devices_are_reported_once() {
+        let mut bus = Bus::new();
+        bus.register(0xFE10..=0xFE11, latch(1)).unwrap();
+        assert!(bus.take_changed());
+        bus.write(0x3000, 1);
+        bus.read(0xFE10).unwrap();
+        assert!(!bus.take_changed());
+        bus.write(0xFE10, 1);
+        assert!(bus.take_changed());
+        assert!(!bus.take_changed());
+    }
+
+    #[test]
+    fn accesses_are_forwarded_to_device() {
+        let mut bus = Bus::new();
+        bus.register(0xFE10..=0xFE11, latch(1)).unwrap();
+        assert!(bus.write(0xFE11, 42));
+        assert_eq!(bus.read(0xFE10).unwrap(), Some(42));
+        assert_eq!(bus.device::<Latch>().unwrap().value, 42);
+    }
+
+    #[test]
+    fn overlapping_ranges_are_rejected() {
+        let mut bus = Bus::new();
+        bus.register(0xFE10..=0xFE13, latch(1)).unwrap();
+        assert!(bus.register(0xFE13..=0xFE20, latch(1)).is_err());
+        assert!(bus.register(0xFE14..=0xFE20, latch(1)).is_ok());
+    }
+
+    #[test]
+    fn tick_returns_highest_priority_interrupt() {
+        let mut bus = Bus::new();
+        let mut words = [0u16; 4];
+        let mut memory = Dma::new(&mut words);
+        bus.register(0xFE10..=0xFE10, latch(2)).unwrap();
+        bus.register(0xFE12..=0xFE12, latch(5)).unwrap();
+        assert!(bus.tick(&mut memory).is_none());
+
+        bus.write(0xFE10, 1);
+        bus.write(0xFE12, 1);
+        assert_eq!(bus.tick(&mut memory).unwrap().priority, 5);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::isa::interrupts::Interrupt;
+use std::any::Any;
+
+/// Trait implemented by memory-mapped devices.
+///
+/// Devices are registered on the `Bus` for a range of addresses. Reads and writes to
+/// those addresses are forwarded to the device instead of main memory, and every device
+/// is ticked once per executed instruction.
+pub trait Device: Any {
+    /// Reads the register mapped at `address`.
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: The absolute address being read.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the value of the register or an error message.
+    fn read(&mut self, address: u16) -> Result<u16, String>;
+
+    /// Writes the register mapped at `address`.
+    ///
+    /// Devices report failures through their status registers rather than by
+    /// failing the store instruction.
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: The absolute address being written.
+    /// - `value`: The value to write.
+    fn write(&mut self, address: u16, value: u16);
+
+    /// Advances the device by one instruction.
+    ///
+    /// # Parameters
+    ///
+    /// - `memory`: The main memory, for devices performing direct memory access.
+    ///
+    /// # Returns
+    ///
+    /// The interrupt requested by the device, if any.
+    fn tick(&mut self, _memory: &mut Dma) -> Option<Interrupt> {
+        None
+    }
+}
+
+/// Main memory as seen by devices performing direct memory access.
+///
+/// The addresses written are kept so that `Memory` can treat them like stores, e.g. mark
+/// them initialized.
+pub struct Dma<'a> {
+    /// The main memory contents.
+    memory: &'a mut [u16],
+    /// Addresses written so far, in order.
+    written: Vec<u16>,
+}
+
+impl<'a> Dma<'a> {
+    /// Gives devices access to `memory`.
+    pub fn new(memory: &'a mut [u16]) -> Self {
+        Self {
+            memory,
+            written: Vec::new(),
+        }
+    }
+
+    /// Returns the word stored at `address`.
+    pub fn read(&self, address: u16) -> u16 {
+        self.memory[address as usize]
+    }
+
+    /// Stores `value` at `address`.
+    pub fn write(&mut self, address: u16, value: u16) {
+        self.memory[address as usize] = value;
+        self.written.push(address);
+    }
+
+    /// Returns the addresses written, in order.
+    pub fn written(&self) -> &[u16] {
+        &self.written
+    }
+}
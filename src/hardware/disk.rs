@@ -0,0 +1,310 @@
+use crate::hardware::device::{Device, Dma};
+use crate::hardware::memory::MemoryMappedRegister;
+use crate::isa::interrupts::Interrupt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+
+/// Number of 16-bit words in a disk sector.
+pub const SECTOR_WORDS: usize = 256;
+
+/// Interrupt vector used by the disk controller.
+pub const DISK_VECTOR: u8 = 0x82;
+
+/// Priority of the disk controller interrupt.
+pub const DISK_PRIORITY: u8 = 4;
+
+/// Status register bit set when the controller is ready to accept a command.
+pub const DISK_READY: u16 = 1 << 15;
+
+/// Status register bit that enables the completion interrupt.
+pub const DISK_INTERRUPT_ENABLE: u16 = 1 << 14;
+
+/// Status register bit set when the last command failed.
+pub const DISK_ERROR: u16 = 1 << 0;
+
+/// Command that reads the selected sector into memory.
+pub const DISK_CMD_READ: u16 = 1;
+
+/// Command that writes memory into the selected sector.
+pub const DISK_CMD_WRITE: u16 = 2;
+
+/// Disk controller backed by a host file.
+///
+/// The disk is divided into sectors of `SECTOR_WORDS` words, stored big-endian in the file.
+/// A transfer is started by selecting a sector (`DKSN`) and a buffer address (`DKBA`) and then
+/// writing a command to `DKCR`. The controller clears the ready bit of `DKSR`, copies the sector
+/// between the file and memory on the next instruction and sets the ready bit again. If enabled,
+/// the completion interrupt is requested until `DKSR` is read. Sectors beyond the end of the file
+/// read as zeros.
+pub struct Disk {
+    /// Host file backing the disk.
+    file: File,
+    /// Status register contents.
+    status: u16,
+    /// Command waiting to be executed, if any.
+    command: Option<u16>,
+    /// Whether a command completed since the status register was last read.
+    completed: bool,
+    /// Selected sector.
+    sector: u16,
+    /// Address of the memory buffer used by transfers.
+    buffer: u16,
+    /// Message of the last failed command.
+    last_error: Option<String>,
+}
+
+impl Disk {
+    /// Opens the disk image at `path`, creating it if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the file cannot be opened.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| e.to_string())?;
+        Ok(Self::from_file(file))
+    }
+
+    /// Creates a disk controller backed by an already opened file.
+    ///
+    /// # Returns
+    ///
+    /// A new, ready instance of `Disk`.
+    pub fn from_file(file: File) -> Self {
+        Self {
+            file,
+            status: DISK_READY,
+            command: None,
+            completed: false,
+            sector: 0,
+            buffer: 0,
+            last_error: None,
+        }
+    }
+
+    /// Returns the range of addresses occupied by the disk registers.
+    pub fn range() -> RangeInclusive<u16> {
+        MemoryMappedRegister::DKSR as u16..=MemoryMappedRegister::DKBA as u16
+    }
+
+    /// Returns the message of the last failed command, if any.
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Executes a command, transferring a sector between the file and memory.
+    fn execute(&mut self, command: u16, memory: &mut Dma) -> Result<(), String> {
+        let offset = self.sector as u64 * (SECTOR_WORDS as u64 * 2);
+        let mut bytes = [0u8; SECTOR_WORDS * 2];
+        match command {
+            DISK_CMD_READ => {
+                self.file
+                    .seek(SeekFrom::Start(offset))
+                    .map_err(|e| e.to_string())?;
+                read_up_to(&mut self.file, &mut bytes)?;
+                for (i, pair) in bytes.chunks_exact(2).enumerate() {
+                    let address = self.buffer.wrapping_add(i as u16);
+                    memory.write(address, u16::from_be_bytes([pair[0], pair[1]]));
+                }
+            }
+            DISK_CMD_WRITE => {
+                for (i, pair) in bytes.chunks_exact_mut(2).enumerate() {
+                    let address = self.buffer.wrapping_add(i as u16);
+                    pair.copy_from_slice(&memory.read(address).to_be_bytes());
+                }
+                self.file
+                    .seek(SeekFrom::Start(offset))
+                    .map_err(|e| e.to_string())?;
+                self.file.write_all(&bytes).map_err(|e| e.to_string())?;
+                self.file.flush().map_err(|e| e.to_string())?;
+            }
+            _ => return Err(format!("Invalid disk command {}", command)),
+        }
+        Ok(())
+    }
+}
+
+impl Device for Disk {
+    /// Reading the status register acknowledges the completion interrupt.
+    fn read(&mut self, address: u16) -> Result<u16, String> {
+        match address {
+            a if a == MemoryMappedRegister::DKSR as u16 => {
+                self.completed = false;
+                Ok(self.status)
+            }
+            a if a == MemoryMappedRegister::DKCR as u16 => Ok(self.command.unwrap_or(0)),
+            a if a == MemoryMappedRegister::DKSN as u16 => Ok(self.sector),
+            a if a == MemoryMappedRegister::DKBA as u16 => Ok(self.buffer),
+            _ => Ok(0),
+        }
+    }
+
+    /// Commands written while a transfer is in progress are ignored.
+    fn write(&mut self, address: u16, value: u16) {
+        match address {
+            a if a == MemoryMappedRegister::DKSR as u16 => {
+                self.status =
+                    (self.status & !DISK_INTERRUPT_ENABLE) | (value & DISK_INTERRUPT_ENABLE)
+            }
+            a if a == MemoryMappedRegister::DKCR as u16 && self.status & DISK_READY != 0 => {
+                self.status &= !(DISK_READY | DISK_ERROR);
+                self.command = Some(value);
+            }
+            a if a == MemoryMappedRegister::DKSN as u16 => self.sector = value,
+            a if a == MemoryMappedRegister::DKBA as u16 => self.buffer = value,
+            _ => {}
+        }
+    }
+
+    /// Executes the pending command and requests the completion interrupt, if enabled.
+    fn tick(&mut self, memory: &mut Dma) -> Option<Interrupt> {
+        if let Some(command) = self.command.take() {
+            if let Err(e) = self.execute(command, memory) {
+                self.status |= DISK_ERROR;
+                self.last_error = Some(e);
+            }
+            self.status |= DISK_READY;
+            self.completed = true;
+        }
+
+        (self.completed && self.status & DISK_INTERRUPT_ENABLE != 0).then_some(Interrupt {
+            vector: DISK_VECTOR,
+            priority: DISK_PRIORITY,
+        })
+    }
+}
+
+/// Address the program returned by `transfer_program` goes on at once the transfer is started.
+#[cfg(test)]
+pub(crate) const TRANSFER_END: u16 = 0x3009;
+
+/// Opens a disk on a temporary file starting with `words`, and assembles a program at x3000
+/// that reads sector 0 into `buffer`, then jumps to `TRANSFER_END`. The disk is ready for
+/// registration at `Disk::range`.
+#[cfg(test)]
+pub(crate) fn transfer_program(name: &str, words: &[u16], buffer: u16) -> (Disk, Vec<u16>) {
+    let path = crate::utils::temp_path(&format!("{}.img", name));
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    std::fs::write(&path, bytes).unwrap();
+    let disk = Disk::open(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(path).unwrap();
+    let program = vec![
+        0x2004,                           // x3000 LD R0, x3005
+        0xB004,                           // x3001 STI R0, x3006 (DKBA = buffer)
+        0x2004,                           // x3002 LD R0, x3007
+        0xB004,                           // x3003 STI R0, x3008 (DKCR = read)
+        0x0E00 | (TRANSFER_END - 0x3005), // x3004 BRnzp TRANSFER_END
+        buffer,
+        MemoryMappedRegister::DKBA as u16,
+        DISK_CMD_READ,
+        MemoryMappedRegister::DKCR as u16,
+    ];
+    (disk, program)
+}
+
+/// Reads as many bytes as available into `buffer`, leaving the rest untouched.
+fn read_up_to(file: &mut File, buffer: &mut [u8]) -> Result<(), String> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file
+            .read(&mut buffer[filled..])
+            .map_err(|e| e.to_string())?
+        {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::memory::MEMORY_SIZE;
+
+    fn temp_disk(name: &str) -> (Disk, std::path::PathBuf) {
+        let path = crate::utils::temp_path(&format!("{}.img", name));
+        let _ = std::fs::remove_file(&path);
+        (Disk::open(path.to_str().unwrap()).unwrap(), path)
+    }
+
+    fn command(disk: &mut Disk, memory: &mut [u16], sector: u16, buffer: u16, cmd: u16) {
+        disk.write(MemoryMappedRegister::DKSN as u16, sector);
+        disk.write(MemoryMappedRegister::DKBA as u16, buffer);
+        disk.write(MemoryMappedRegister::DKCR as u16, cmd);
+        assert_eq!(
+            disk.read(MemoryMappedRegister::DKSR as u16).unwrap() & DISK_READY,
+            0
+        );
+        disk.tick(&mut Dma::new(memory));
+    }
+
+    #[test]
+    fn write_then_read_sector_roundtrip() {
+        let (mut disk, path) = temp_disk("roundtrip");
+        let mut memory = vec![0u16; MEMORY_SIZE];
+        for i in 0..SECTOR_WORDS {
+            memory[0x4000 + i] = i as u16 * 3;
+        }
+
+        command(&mut disk, &mut memory, 2, 0x4000, DISK_CMD_WRITE);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 3 * 512);
+
+        command(&mut disk, &mut memory, 2, 0x5000, DISK_CMD_READ);
+        assert_eq!(
+            disk.read(MemoryMappedRegister::DKSR as u16).unwrap(),
+            DISK_READY
+        );
+        assert_eq!(memory[0x5000..0x5100], memory[0x4000..0x4100]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reading_past_end_of_file_yields_zeros() {
+        let (mut disk, path) = temp_disk("empty");
+        let mut memory = vec![0xFFFFu16; MEMORY_SIZE];
+        command(&mut disk, &mut memory, 7, 0x4000, DISK_CMD_READ);
+        assert!(memory[0x4000..0x4100].iter().all(|&w| w == 0));
+        assert_eq!(memory[0x4100], 0xFFFF);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_command_sets_error_bit() {
+        let (mut disk, path) = temp_disk("invalid");
+        let mut memory = vec![0u16; MEMORY_SIZE];
+        command(&mut disk, &mut memory, 0, 0x4000, 9);
+        assert_eq!(
+            disk.read(MemoryMappedRegister::DKSR as u16).unwrap(),
+            DISK_READY | DISK_ERROR
+        );
+        assert!(disk.last_error().is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn completion_interrupt_is_requested_when_enabled() {
+        let (mut disk, path) = temp_disk("interrupt");
+        let mut memory = vec![0u16; MEMORY_SIZE];
+        disk.write(MemoryMappedRegister::DKSR as u16, DISK_INTERRUPT_ENABLE);
+        disk.write(MemoryMappedRegister::DKCR as u16, DISK_CMD_READ);
+        assert_eq!(
+            disk.tick(&mut Dma::new(&mut memory)),
+            Some(Interrupt {
+                vector: DISK_VECTOR,
+                priority: DISK_PRIORITY
+            })
+        );
+        assert!(disk.tick(&mut Dma::new(&mut memory)).is_some());
+
+        disk.read(MemoryMappedRegister::DKSR as u16).unwrap();
+        assert!(disk.tick(&mut Dma::new(&mut memory)).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use crate::hardware::device::Device;
+use crate::hardware::memory::MemoryMappedRegister;
+use std::io::{self, Write};
+
+/// Console display mapped at `DSR`/`DDR`.
+///
+/// The display is always ready (bit 15 of the status register); every character written
+/// to the data register is printed to standard output, or kept in memory once output is
+/// captured, e.g. to test programs.
+#[derive(Default)]
+pub struct Display {
+    /// Output kept instead of printed, if captured.
+    output: Option<String>,
+}
+
+impl Display {
+    /// Creates a new `Display`.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Display`.
+    pub fn new() -> Self {
+        Self { output: None }
+    }
+
+    /// Keeps the output from now on instead of printing it.
+    pub fn capture(&mut self) {
+        self.output.get_or_insert_with(String::new);
+    }
+
+    /// Checks whether the output is captured.
+    pub fn is_capturing(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Returns the output captured so far, empty if it is not captured.
+    pub fn output(&self) -> &str {
+        self.output.as_deref().unwrap_or_default()
+    }
+
+    /// Prints text to standard output, or keeps it if the output is captured.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if flushing standard output fails.
+    pub fn write_str(&mut self, text: &str) -> Result<(), String> {
+        match &mut self.output {
+            Some(output) => {
+                output.push_str(text);
+                Ok(())
+            }
+            None => {
+                print!("{}", text);
+                io::stdout().flush().map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+impl Device for Display {
+    fn read(&mut self, address: u16) -> Result<u16, String> {
+        match address {
+            a if a == MemoryMappedRegister::DSR as u16 => Ok(1 << 15),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        if address == MemoryMappedRegister::DDR as u16 {
+            let char = char::from((value & 0xFF) as u8);
+            // A failed flush only delays the output, it is retried on the next write
+            let _ = self.write_str(char.encode_utf8(&mut [0; 4]));
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::hardware::device::Device;
+use crate::hardware::memory::MemoryMappedRegister;
+use crate::utils;
+use std::collections::VecDeque;
+
+/// Console keyboard mapped at `KBSR`/`KBDR`.
+///
+/// Reading the status register reads a character from standard input and stores it in
+/// the data register, setting the ready bit (bit 15) of the status register. Standard input
+/// can be replaced by scripted input, e.g. to test programs.
+#[derive(Default)]
+pub struct Keyboard {
+    /// Keyboard status register contents.
+    status: u16,
+    /// Keyboard data register contents.
+    data: u16,
+    /// Characters read instead of standard input, if scripted.
+    input: Option<VecDeque<u8>>,
+}
+
+impl Keyboard {
+    /// Creates a new `Keyboard` with no character available.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Keyboard`.
+    pub fn new() -> Self {
+        Self {
+            status: 0,
+            data: 0,
+            input: None,
+        }
+    }
+
+    /// Reads characters from the given input instead of standard input.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The characters to read, in order.
+    pub fn set_input(&mut self, input: &[u8]) {
+        self.input = Some(input.iter().copied().collect());
+    }
+
+    /// Reads the next character, from the scripted input if any or else from standard input.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if reading standard input fails or the scripted input
+    /// is exhausted.
+    pub fn getchar(&mut self) -> Result<u16, String> {
+        match &mut self.input {
+            Some(input) => input
+                .pop_front()
+                .map(u16::from)
+                .ok_or_else(|| "End of input".to_string()),
+            None => utils::getchar(),
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self, address: u16) -> Result<u16, String> {
+        match address {
+            a if a == MemoryMappedRegister::KBSR as u16 => {
+                // Exhausted scripted input leaves the keyboard not ready, as no key is pressed
+                let char = match &self.input {
+                    Some(input) if input.is_empty() => 0,
+                    _ => self.getchar()?,
+                };
+                if char != 0 {
+                    self.status = 1 << 15; // Set the ready bit
+                    self.data = char;
+                } else {
+                    self.status = 0; // Clear the ready bit
+                }
+                Ok(self.status)
+            }
+            a if a == MemoryMappedRegister::KBDR as u16 => Ok(self.data),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        if address == MemoryMappedRegister::KBSR as u16 {
+            self.status = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_input_is_read_until_exhausted() {
+        let mut keyboard = Keyboard::new();
+        keyboard.set_input(b"a");
+        assert_eq!(
+            keyboard.read(MemoryMappedRegister::KBSR as u16),
+            Ok(1 << 15)
+        );
+        assert_eq!(keyboard.read(MemoryMappedRegister::KBDR as u16), Ok(0x61));
+        assert_eq!(keyboard.read(MemoryMappedRegister::KBSR as u16), Ok(0));
+        assert!(keyboard.getchar().is_err());
+    }
+}
@@ -0,0 +1,46 @@
+use crate::hardware::device::Device;
+
+/// Clock enable bit of the machine control register.
+pub const MCR_CLOCK_ENABLE: u16 = 1 << 15;
+
+/// Machine control register (`MCR`).
+///
+/// Clearing the clock enable bit (bit 15) stops the VM after the current instruction.
+pub struct MachineControl {
+    /// Machine control register contents.
+    value: u16,
+}
+
+impl Default for MachineControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MachineControl {
+    /// Creates a new `MachineControl` with the clock enabled.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `MachineControl`.
+    pub fn new() -> Self {
+        Self {
+            value: MCR_CLOCK_ENABLE,
+        }
+    }
+
+    /// Returns `true` while the clock enable bit is set.
+    pub fn clock_enabled(&self) -> bool {
+        self.value & MCR_CLOCK_ENABLE != 0
+    }
+}
+
+impl Device for MachineControl {
+    fn read(&mut self, _address: u16) -> Result<u16, String> {
+        Ok(self.value)
+    }
+
+    fn write(&mut self, _address: u16, value: u16) {
+        self.value = value;
+    }
+}
@@ -1,4 +1,7 @@
-use crate::utils::getchar;
+use crate::hardware::bus::Bus;
+use crate::hardware::device::Dma;
+use crate::hardware::shadow::ShadowMemory;
+use crate::isa::interrupts::Interrupt;
 
 /// The size of the memory in the LC-3 VM.
 /// 2^16 = 65536 locations of 16 bits each = 128 KB of memory.
@@ -10,12 +13,82 @@ pub enum MemoryMappedRegister {
     KBSR = 0xFE00,
     /// Keyboard data register.
     KBDR = 0xFE02,
+    /// Display status register.
+    DSR = 0xFE04,
+    /// Display data register.
+    DDR = 0xFE06,
+    /// Timer status register.
+    TMSR = 0xFE08,
+    /// Timer control register.
+    TMCR = 0xFE0A,
+    /// Timer interval register.
+    TMIR = 0xFE0C,
+    /// Disk status register.
+    DKSR = 0xFE10,
+    /// Disk command register.
+    DKCR = 0xFE12,
+    /// Disk sector number register.
+    DKSN = 0xFE14,
+    /// Disk buffer address register.
+    DKBA = 0xFE16,
+    /// Random number data register.
+    RNDR = 0xFE18,
+    /// Real-time clock seconds register.
+    RTSEC = 0xFE20,
+    /// Real-time clock minutes register.
+    RTMIN = 0xFE22,
+    /// Real-time clock hours register.
+    RTHR = 0xFE24,
+    /// Real-time clock day of month register.
+    RTDAY = 0xFE26,
+    /// Real-time clock month register.
+    RTMON = 0xFE28,
+    /// Real-time clock year register.
+    RTYR = 0xFE2A,
+    /// Serial port receiver status register.
+    SRSR = 0xFE30,
+    /// Serial port receiver data register.
+    SRDR = 0xFE32,
+    /// Serial port transmitter status register.
+    STSR = 0xFE34,
+    /// Serial port transmitter data register.
+    STDR = 0xFE36,
+    /// Tone generator frequency register.
+    TGFR = 0xFE38,
+    /// Tone generator duration register.
+    TGDR = 0xFE3A,
+    /// Tone generator volume register.
+    TGVR = 0xFE3C,
+    /// Tone generator control register.
+    TGCR = 0xFE3E,
+    /// Machine control register.
+    MCR = 0xFFFE,
+}
+
+/// An access to a word of RAM, recorded for the analyses run alongside the VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    /// Address of the word.
+    pub address: u16,
+    /// `true` for a write, `false` for a read.
+    pub write: bool,
+    /// Whether the word had been loaded or written before the access; always `true`
+    /// unless initialization is tracked.
+    pub initialized: bool,
 }
 
 /// Struct representing the memory of the LC-3 VM.
 pub struct Memory {
     /// Array storing the memory contents.
     memory: [u16; MEMORY_SIZE],
+    /// Bus of the memory-mapped devices.
+    bus: Bus,
+    /// Words loaded or written so far, if initialization is tracked.
+    initialized: Option<ShadowMemory>,
+    /// Addresses of the uninitialized words read since the last call to `take_uninitialized_reads`.
+    uninitialized_reads: Vec<u16>,
+    /// Accesses to RAM since the last call to `take_accesses`, if they are recorded.
+    accesses: Option<Vec<Access>>,
 }
 
 impl Default for Memory {
@@ -25,7 +98,7 @@ impl Default for Memory {
 }
 
 impl Memory {
-    /// Creates a new `Memory` instance with all locations initialized to zero.
+    /// Creates a new `Memory` instance with all locations initialized to zero and no devices.
     ///
     /// # Returns
     ///
@@ -33,13 +106,17 @@ impl Memory {
     pub fn new() -> Self {
         Self {
             memory: [0; MEMORY_SIZE],
+            bus: Bus::new(),
+            initialized: None,
+            uninitialized_reads: Vec::new(),
+            accesses: None,
         }
     }
 
     /// Reads a value from the specified memory address.
     ///
-    /// If the address corresponds to a memory-mapped register, the appropriate
-    /// behavior (e.g., reading from standard input) is executed.
+    /// If the address is mapped to a device, the read is forwarded to it
+    /// (e.g., the keyboard reads from standard input).
     ///
     /// # Parameters
     ///
@@ -49,32 +126,128 @@ impl Memory {
     ///
     /// A `Result` containing the value read from memory or an error message.
     pub fn read(&mut self, address: u16) -> Result<u16, String> {
-        if address == MemoryMappedRegister::KBSR as u16 {
-            let char = getchar()?;
-            if char != 0 {
-                self.memory[address as usize] = 1 << 15; // Set the ready bit
-                self.memory[MemoryMappedRegister::KBDR as usize] = char;
-            } else {
-                self.memory[address as usize] = 0; // Clear the ready bit
-            }
+        if let Some(value) = self.bus.read(address)? {
+            return Ok(value);
         }
+        let initialized = self.is_initialized(address);
+        if !initialized {
+            self.uninitialized_reads.push(address);
+        }
+        self.record(address, false, initialized);
         Ok(self.memory[address as usize])
     }
 
+    /// Returns the word stored in RAM at the specified address, without side effects.
+    ///
+    /// Devices are not accessed, so for addresses mapped to a device the RAM
+    /// behind it is returned instead of the device register.
+    pub fn peek(&self, address: u16) -> u16 {
+        self.memory[address as usize]
+    }
+
     /// Writes a value to the specified memory address.
     ///
+    /// If the address is mapped to a device, the write is forwarded to it.
+    ///
     /// # Parameters
     ///
     /// - `address`: The memory address to write to.
     /// - `value`: The value to write to memory.
     pub fn write(&mut self, address: u16, value: u16) {
-        self.memory[address as usize] = value;
+        if !self.bus.write(address, value) {
+            self.memory[address as usize] = value;
+            self.mark_written(address);
+        }
+    }
+
+    /// Records a write to RAM and marks the word initialized.
+    fn mark_written(&mut self, address: u16) {
+        self.record(address, true, self.is_initialized(address));
+        if let Some(shadow) = &mut self.initialized {
+            shadow.set(address);
+        }
+    }
+
+    /// Fills every word of RAM with values produced by `value`, without marking them initialized.
+    pub fn fill(&mut self, mut value: impl FnMut() -> u16) {
+        self.memory.iter_mut().for_each(|word| *word = value());
+    }
+
+    /// Starts tracking which words are loaded or written, so reads of the others are recorded.
+    ///
+    /// Words written before tracking starts are considered uninitialized.
+    pub fn track_initialization(&mut self) {
+        self.initialized = Some(ShadowMemory::new());
+    }
+
+    /// Returns `false` if initialization is tracked and the word was never loaded or written.
+    pub fn is_initialized(&self, address: u16) -> bool {
+        self.initialized
+            .as_ref()
+            .is_none_or(|shadow| shadow.get(address))
+    }
+
+    /// Returns and forgets the addresses of the uninitialized words read so far.
+    pub fn take_uninitialized_reads(&mut self) -> Vec<u16> {
+        std::mem::take(&mut self.uninitialized_reads)
+    }
+
+    /// Starts recording every access to RAM; accesses to devices are not recorded.
+    pub fn record_accesses(&mut self) {
+        self.accesses.get_or_insert_with(Vec::new);
+    }
+
+    /// Returns and forgets the accesses recorded so far.
+    pub fn take_accesses(&mut self) -> Vec<Access> {
+        self.accesses
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Records an access, if accesses are recorded.
+    fn record(&mut self, address: u16, write: bool, initialized: bool) {
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(Access {
+                address,
+                write,
+                initialized,
+            });
+        }
+    }
+
+    /// Advances the memory-mapped devices by one instruction.
+    ///
+    /// Words written by direct memory access are recorded and marked initialized, like stores.
+    ///
+    /// # Returns
+    ///
+    /// The pending interrupt with the highest priority, if any.
+    pub fn tick(&mut self) -> Option<Interrupt> {
+        let mut dma = Dma::new(&mut self.memory);
+        let irq = self.bus.tick(&mut dma);
+        let written = dma.written().to_vec();
+        for address in written {
+            self.mark_written(address);
+        }
+        irq
+    }
+
+    /// Returns the bus of the memory-mapped devices.
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    /// Returns a mutable reference to the bus of the memory-mapped devices.
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hardware::machine_control::MachineControl;
 
     #[test]
     fn create_new_memory() {
@@ -92,6 +265,71 @@ mod tests {
         assert_eq!(memory.read(100).unwrap(), 1234);
     }
 
+    #[test]
+    fn reads_of_unwritten_words_are_recorded() {
+        let mut memory = Memory::new();
+        memory.fill(|| 0xDEAD);
+        memory.track_initialization();
+        memory.write(0x3000, 1);
+        assert_eq!(memory.read(0x3000).unwrap(), 1);
+        assert_eq!(memory.read(0x3001).unwrap(), 0xDEAD);
+        assert!(!memory.is_initialized(0x3001));
+        assert_eq!(memory.take_uninitialized_reads(), [0x3001]);
+        assert!(memory.take_uninitialized_reads().is_empty());
+    }
+
+    #[test]
+    fn ram_accesses_are_recorded() {
+        let mut memory = Memory::new();
+        memory
+            .bus_mut()
+            .register(
+                MemoryMappedRegister::MCR as u16..=MemoryMappedRegister::MCR as u16,
+                Box::new(MachineControl::new()),
+            )
+            .unwrap();
+        memory.track_initialization();
+        memory.record_accesses();
+        memory.write(0x3000, 1);
+        memory.read(0x3001).unwrap();
+        memory.read(MemoryMappedRegister::MCR as u16).unwrap();
+        assert_eq!(
+            memory.take_accesses(),
+            [
+                Access {
+                    address: 0x3000,
+                    write: true,
+                    initialized: false
+                },
+                Access {
+                    address: 0x3001,
+                    write: false,
+                    initialized: false
+                },
+            ]
+        );
+        assert!(memory.take_accesses().is_empty());
+    }
+
+    #[test]
+    fn mapped_address_is_forwarded_to_device() {
+        let mut memory = Memory::new();
+        memory
+            .bus_mut()
+            .register(
+                MemoryMappedRegister::MCR as u16..=MemoryMappedRegister::MCR as u16,
+                Box::new(MachineControl::new()),
+            )
+            .unwrap();
+        memory.write(MemoryMappedRegister::MCR as u16, 0);
+        assert_eq!(memory.memory[MemoryMappedRegister::MCR as usize], 0);
+        assert!(!memory
+            .bus()
+            .device::<MachineControl>()
+            .unwrap()
+            .clock_enabled());
+    }
+
     #[test]
     fn test_memory_write() {
         let mut memory = Memory::new();
@@ -6,12 +6,60 @@
 /// - Negative (NEG)
 pub mod flags;
 
+/// Module implementing the tone generator device, whose output is rendered to a WAV file.
+pub mod audio;
+
+/// Module defining the bus that maps address ranges to memory-mapped devices.
+pub mod bus;
+
+/// Module defining the `Device` trait implemented by memory-mapped devices.
+pub mod device;
+
+/// Module implementing the disk controller device backed by a host file.
+pub mod disk;
+
+/// Module implementing the console display device (DSR/DDR).
+pub mod display;
+
+/// Module implementing the console keyboard device (KBSR/KBDR).
+pub mod keyboard;
+
+/// Module implementing the machine control register (MCR).
+pub mod machine_control;
+
 /// Module for memory management in the LC-3 Virtual Machine.
 ///
 /// This module provides the `Memory` struct and related functionality
-/// for reading from and writing to memory. It also includes support for
-/// memory-mapped registers such as the keyboard status and data registers.
+/// for reading from and writing to memory. Accesses to addresses mapped
+/// to a device are forwarded through the device bus.
 pub mod memory;
 
 /// Module for managing the registers in the LC-3 Virtual Machine.
 pub mod registers;
+
+/// Module implementing the seedable hardware random number generator device.
+pub mod rng;
+
+/// Module implementing the read-only real-time clock device.
+pub mod rtc;
+
+/// Module implementing the shadow bitmap that tracks which memory words were initialized.
+pub mod shadow;
+
+/// Module implementing the serial port device, connected to a Unix domain socket,
+/// a pseudo-terminal or a pair of files.
+pub mod serial;
+
+/// Module implementing the memory-mapped text-mode framebuffer, rendered with ANSI
+/// escape sequences or kept headless.
+pub mod text_display;
+
+/// Module implementing the programmable interval timer device.
+///
+/// The timer is mapped into the device page and can either be polled or raise
+/// an interrupt every N instructions or milliseconds.
+pub mod timer;
+
+/// Module implementing the PennSim-compatible pixel framebuffer at xC000, which
+/// can be saved as PNG or PPM images.
+pub mod video;
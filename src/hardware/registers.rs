@@ -3,7 +3,14 @@ use crate::hardware::flags::Flag;
 /// Default starting position for the program counter (PC).
 pub const PC_START: u16 = 0x3000;
 
+/// Initial value of the supervisor stack pointer; the supervisor stack grows down from x2FFF.
+pub const SSP_START: u16 = 0x3000;
+
+/// Bit of the processor status register (PSR) that is set while running in user mode.
+pub const PSR_USER_MODE: u16 = 1 << 15;
+
 /// Enumeration of the 10 LC-3 registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Register {
     R0 = 0,
     R1,
@@ -41,11 +48,38 @@ impl From<u16> for Register {
     }
 }
 
+impl Register {
+    /// Parses a register name: `R0` to `R7` or `PC`, in any case.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error for any other name.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_ascii_uppercase().as_str() {
+            "PC" => Ok(Register::PC),
+            upper => match upper.strip_prefix('R').and_then(|n| n.parse::<u16>().ok()) {
+                Some(n @ 0..=7) => Ok(Register::from(n)),
+                _ => Err(format!("Unknown register '{}', expected R0-R7 or PC", name)),
+            },
+        }
+    }
+}
+
 /// Structure representing the registers of the LC-3 VM.
-#[derive(Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Registers {
     /// Array storing the registers contents.
     registers: [u16; Register::COUNT as usize],
+
+    /// Privilege (bit 15) and priority level (bits 10-8) of the processor status register.
+    /// The condition codes are kept in `Register::COND`.
+    status: u16,
+
+    /// Supervisor stack pointer, saved while running in user mode.
+    saved_ssp: u16,
+
+    /// User stack pointer, saved while running in supervisor mode.
+    saved_usp: u16,
 }
 
 impl Registers {
@@ -53,6 +87,7 @@ impl Registers {
     ///
     /// The program counter (PC) is initialized to `PC_START`,
     /// and the condition register (COND) is set to `Flag::ZRO`.
+    /// The processor starts in user mode at priority level 0.
     ///
     /// # Returns
     ///
@@ -62,7 +97,12 @@ impl Registers {
         registers[Register::PC as usize] = PC_START;
         registers[Register::COND as usize] = Flag::ZRO as u16;
 
-        Self { registers }
+        Self {
+            registers,
+            status: PSR_USER_MODE,
+            saved_ssp: SSP_START,
+            saved_usp: 0,
+        }
     }
 
     /// Reads the value from the specified register.
@@ -104,6 +144,54 @@ impl Registers {
             self.write(Register::COND, Flag::POS as u16);
         }
     }
+
+    /// Returns the processor status register (PSR).
+    ///
+    /// # Returns
+    ///
+    /// The privilege mode in bit 15, the priority level in bits 10-8 and the condition codes in bits 2-0.
+    pub fn psr(&self) -> u16 {
+        self.status | self.read(Register::COND)
+    }
+
+    /// Sets the processor status register (PSR).
+    ///
+    /// # Arguments
+    ///
+    /// * `psr` - The new privilege mode, priority level and condition codes.
+    pub fn set_psr(&mut self, psr: u16) {
+        self.status = psr & (PSR_USER_MODE | 0x0700);
+        self.write(Register::COND, psr & 0x7);
+    }
+
+    /// Returns the current priority level (0 to 7).
+    pub fn priority(&self) -> u8 {
+        ((self.status >> 8) & 0x7) as u8
+    }
+
+    /// Returns `true` if the processor is running in user mode.
+    pub fn is_user_mode(&self) -> bool {
+        self.status & PSR_USER_MODE != 0
+    }
+
+    /// Swaps R6 with the saved stack pointer of the other privilege mode.
+    ///
+    /// Must be called while switching privilege modes: when entering supervisor mode the user stack
+    /// pointer is saved and the supervisor one is restored, and vice versa.
+    ///
+    /// # Arguments
+    ///
+    /// * `to_supervisor` - `true` when switching from user to supervisor mode.
+    pub fn swap_stack_pointers(&mut self, to_supervisor: bool) {
+        let r6 = self.read(Register::R6);
+        if to_supervisor {
+            self.saved_usp = r6;
+            self.write(Register::R6, self.saved_ssp);
+        } else {
+            self.saved_ssp = r6;
+            self.write(Register::R6, self.saved_usp);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -161,6 +249,31 @@ mod tests {
         assert_eq!(registers.read(Register::COND), Flag::POS as u16);
     }
 
+    #[test]
+    fn psr_combines_status_and_condition_codes() {
+        let mut registers = Registers::new();
+        assert_eq!(registers.psr(), PSR_USER_MODE | Flag::ZRO as u16);
+
+        registers.set_psr(0x0400 | Flag::NEG as u16);
+        assert!(!registers.is_user_mode());
+        assert_eq!(registers.priority(), 4);
+        assert_eq!(registers.read(Register::COND), Flag::NEG as u16);
+    }
+
+    #[test]
+    fn swap_stack_pointers_switches_between_user_and_supervisor_stacks() {
+        let mut registers = Registers::new();
+        registers.write(Register::R6, 0xF000);
+
+        registers.swap_stack_pointers(true);
+        assert_eq!(registers.read(Register::R6), SSP_START);
+
+        registers.write(Register::R6, SSP_START - 2);
+        registers.swap_stack_pointers(false);
+        assert_eq!(registers.read(Register::R6), 0xF000);
+        assert_eq!(registers.saved_ssp, SSP_START - 2);
+    }
+
     #[test]
     #[should_panic(expected = "Invalid register value")]
     fn from_invalid_register_value() {
@@ -0,0 +1,266 @@
+use crate::hardware::device::Device;
+use crate::hardware::memory::MemoryMappedRegister;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the time reported by the real-time clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// Host time shifted by the given number of seconds.
+    Host { offset: i64 },
+    /// A fixed time, in seconds since the Unix epoch.
+    Frozen(i64),
+}
+
+/// Calendar time broken down into the fields exposed by the real-time clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DateTime {
+    /// Year, e.g. 2024.
+    pub year: u16,
+    /// Month, from 1 to 12.
+    pub month: u16,
+    /// Day of the month, from 1 to 31.
+    pub day: u16,
+    /// Hours, from 0 to 23.
+    pub hours: u16,
+    /// Minutes, from 0 to 59.
+    pub minutes: u16,
+    /// Seconds, from 0 to 59.
+    pub seconds: u16,
+}
+
+impl DateTime {
+    /// Converts seconds since the Unix epoch to a UTC calendar time.
+    pub fn from_unix(timestamp: i64) -> Self {
+        let days = timestamp.div_euclid(86_400);
+        let secs = timestamp.rem_euclid(86_400);
+        // Civil-from-days algorithm by Howard Hinnant
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        Self {
+            year: year as u16,
+            month: month as u16,
+            day: day as u16,
+            hours: (secs / 3600) as u16,
+            minutes: (secs / 60 % 60) as u16,
+            seconds: (secs % 60) as u16,
+        }
+    }
+
+    /// Converts a UTC calendar time to seconds since the Unix epoch.
+    pub fn to_unix(self) -> i64 {
+        // Days-from-civil algorithm by Howard Hinnant
+        let (month, day) = (self.month as i64, self.day as i64);
+        let year = self.year as i64 - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        days * 86_400 + self.hours as i64 * 3600 + self.minutes as i64 * 60 + self.seconds as i64
+    }
+
+    /// Parses a time given as `YYYY-MM-DDTHH:MM:SS` (UTC).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the text is not a valid time.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid time '{}', expected YYYY-MM-DDTHH:MM:SS", text);
+        let (date, time) = text.split_once('T').ok_or_else(invalid)?;
+        let date: Vec<u16> = date
+            .split('-')
+            .map(|f| f.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        let time: Vec<u16> = time
+            .split(':')
+            .map(|f| f.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        let (&[year, month, day], &[hours, minutes, seconds]) = (&date[..], &time[..]) else {
+            return Err(invalid());
+        };
+        if !(1..=12).contains(&month)
+            || !(1..=days_in_month(year, month)).contains(&day)
+            || hours > 23
+            || minutes > 59
+            || seconds > 59
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            year,
+            month,
+            day,
+            hours,
+            minutes,
+            seconds,
+        })
+    }
+}
+
+/// Number of days in a month of the proleptic Gregorian calendar.
+fn days_in_month(year: u16, month: u16) -> u16 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Read-only real-time clock mapped at `RTSEC`-`RTYR`.
+///
+/// Reading `RTSEC` latches the current time, so the remaining registers (minutes, hours,
+/// day, month and year) consistently describe the same instant. Times are reported in UTC.
+/// The clock can be frozen or offset from the host time for deterministic test runs.
+pub struct RealTimeClock {
+    /// Source of the reported time.
+    clock: Clock,
+    /// Time latched by the last read of `RTSEC`.
+    latched: DateTime,
+}
+
+impl Default for RealTimeClock {
+    fn default() -> Self {
+        Self::new(Clock::Host { offset: 0 })
+    }
+}
+
+impl RealTimeClock {
+    /// Creates a new real-time clock.
+    ///
+    /// # Parameters
+    ///
+    /// - `clock`: Source of the reported time.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `RealTimeClock`, with the current time already latched.
+    pub fn new(clock: Clock) -> Self {
+        let mut rtc = Self {
+            clock,
+            latched: DateTime::default(),
+        };
+        rtc.latch();
+        rtc
+    }
+
+    /// Changes the source of the reported time and latches it.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+        self.latch();
+    }
+
+    /// Returns the current time of the clock, in seconds since the Unix epoch.
+    pub fn now(&self) -> i64 {
+        match self.clock {
+            Clock::Host { offset } => {
+                let host = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs() as i64);
+                host + offset
+            }
+            Clock::Frozen(timestamp) => timestamp,
+        }
+    }
+
+    /// Latches the current time into the registers.
+    fn latch(&mut self) {
+        self.latched = DateTime::from_unix(self.now());
+    }
+}
+
+impl Device for RealTimeClock {
+    fn read(&mut self, address: u16) -> Result<u16, String> {
+        use MemoryMappedRegister::*;
+        let time = &self.latched;
+        match address {
+            a if a == RTSEC as u16 => {
+                self.latch();
+                Ok(self.latched.seconds)
+            }
+            a if a == RTMIN as u16 => Ok(time.minutes),
+            a if a == RTHR as u16 => Ok(time.hours),
+            a if a == RTDAY as u16 => Ok(time.day),
+            a if a == RTMON as u16 => Ok(time.month),
+            a if a == RTYR as u16 => Ok(time.year),
+            _ => Ok(0),
+        }
+    }
+
+    /// The clock is read-only, writes are ignored.
+    fn write(&mut self, _address: u16, _value: u16) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_unix_converts_to_calendar_time() {
+        let time = DateTime::from_unix(951_827_696); // 2000-02-29T12:34:56
+        assert_eq!(
+            time,
+            DateTime {
+                year: 2000,
+                month: 2,
+                day: 29,
+                hours: 12,
+                minutes: 34,
+                seconds: 56
+            }
+        );
+        assert_eq!(time.to_unix(), 951_827_696);
+    }
+
+    #[test]
+    fn parse_accepts_iso_time() {
+        let time = DateTime::parse("2024-12-31T23:59:59").unwrap();
+        assert_eq!(DateTime::from_unix(time.to_unix()), time);
+        assert!(DateTime::parse("2024-13-01T00:00:00").is_err());
+        assert!(DateTime::parse("2024-01-01").is_err());
+    }
+
+    #[test]
+    fn parse_checks_the_day_against_the_month() {
+        assert!(DateTime::parse("2024-02-29T00:00:00").is_ok());
+        assert!(DateTime::parse("2000-02-29T00:00:00").is_ok());
+        assert!(DateTime::parse("2024-02-30T00:00:00").is_err());
+        assert!(DateTime::parse("2024-02-31T00:00:00").is_err());
+        assert!(DateTime::parse("2023-02-29T00:00:00").is_err());
+        assert!(DateTime::parse("1900-02-29T00:00:00").is_err());
+        assert!(DateTime::parse("2024-04-31T00:00:00").is_err());
+        assert!(DateTime::parse("2024-04-30T00:00:00").is_ok());
+    }
+
+    #[test]
+    fn frozen_clock_reports_fixed_time() {
+        let mut rtc = RealTimeClock::new(Clock::Frozen(0));
+        assert_eq!(rtc.read(MemoryMappedRegister::RTSEC as u16).unwrap(), 0);
+        assert_eq!(rtc.read(MemoryMappedRegister::RTDAY as u16).unwrap(), 1);
+        assert_eq!(rtc.read(MemoryMappedRegister::RTMON as u16).unwrap(), 1);
+        assert_eq!(rtc.read(MemoryMappedRegister::RTYR as u16).unwrap(), 1970);
+    }
+
+    #[test]
+    fn offset_clock_is_shifted_from_host_time() {
+        let rtc = RealTimeClock::new(Clock::Host { offset: 86_400 });
+        let host = RealTimeClock::new(Clock::Host { offset: 0 });
+        assert!((rtc.now() - host.now() - 86_400).abs() <= 1);
+    }
+
+    #[test]
+    fn writes_are_ignored() {
+        let mut rtc = RealTimeClock::new(Clock::Frozen(3600));
+        rtc.write(MemoryMappedRegister::RTHR as u16, 7);
+        assert_eq!(rtc.read(MemoryMappedRegister::RTHR as u16).unwrap(), 1);
+    }
+}
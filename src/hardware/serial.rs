@@ -0,0 +1,392 @@
+use crate::hardware::device::{Device, Dma};
+use crate::hardware::memory::MemoryMappedRegister;
+use crate::isa::interrupts::Interrupt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Interrupt vector used by the serial port receiver.
+pub const SERIAL_VECTOR: u8 = 0x83;
+
+/// Priority of the serial port receiver interrupt.
+pub const SERIAL_PRIORITY: u8 = 4;
+
+/// Status register bit set when a byte was received or the transmitter is ready.
+pub const SERIAL_READY: u16 = 1 << 15;
+
+/// Receiver status register bit that enables the receive interrupt.
+pub const SERIAL_INTERRUPT_ENABLE: u16 = 1 << 14;
+
+/// Host endpoint a serial port is connected to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialEndpoint {
+    /// Connect to a listening Unix domain socket.
+    Connect(String),
+    /// Listen on a Unix domain socket and wait for a peer to connect.
+    Listen(String),
+    /// Read received bytes from one file and write transmitted bytes to another. The same
+    /// path must be a character device, e.g. a terminal, and is opened once for both without
+    /// being truncated.
+    Files { input: String, output: String },
+    /// Create a new pseudo-terminal and print the path of its slave side.
+    Pty,
+}
+
+impl SerialEndpoint {
+    /// Parses an endpoint given as `connect:PATH`, `listen:PATH`, `file:IN,OUT` or `pty`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the endpoint is not recognized.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.split_once(':') {
+            Some(("connect", path)) => Ok(SerialEndpoint::Connect(path.to_string())),
+            Some(("listen", path)) => Ok(SerialEndpoint::Listen(path.to_string())),
+            Some(("file", files)) => {
+                let (input, output) = files.split_once(',').unwrap_or((files, files));
+                Ok(SerialEndpoint::Files {
+                    input: input.to_string(),
+                    output: output.to_string(),
+                })
+            }
+            None if text == "pty" => Ok(SerialEndpoint::Pty),
+            _ => Err(format!(
+                "Invalid serial endpoint '{}', expected connect:PATH, listen:PATH, file:IN,OUT or pty",
+                text
+            )),
+        }
+    }
+}
+
+/// Serial port (UART) mapped at `SRSR`-`STDR`, separate from the console keyboard and display.
+///
+/// Received bytes are read by a background thread and become available in `SRDR`, with the
+/// ready bit of `SRSR` set until `SRDR` is read. Bytes written to `STDR` are sent immediately,
+/// so the transmitter status register `STSR` is always ready. If enabled, the receive interrupt
+/// is requested while a received byte is waiting.
+pub struct Serial {
+    /// Bytes received by the reader thread.
+    received: Receiver<u8>,
+    /// Destination of the transmitted bytes.
+    output: Box<dyn Write>,
+    /// Receiver status register contents.
+    status: u16,
+    /// Receiver data register contents.
+    data: u16,
+    /// Message of the last failed transmission.
+    last_error: Option<String>,
+}
+
+impl Serial {
+    /// Opens a serial port connected to a host endpoint.
+    ///
+    /// Listening waits until a peer connects, and creating a pseudo-terminal prints the path
+    /// of its slave side to standard error.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the endpoint cannot be opened.
+    pub fn open(endpoint: &SerialEndpoint) -> Result<Self, String> {
+        match endpoint {
+            SerialEndpoint::Connect(path) => {
+                let stream = UnixStream::connect(path).map_err(|e| e.to_string())?;
+                Self::from_stream(stream)
+            }
+            SerialEndpoint::Listen(path) => {
+                // Remove a socket left behind by a previous run
+                let _ = std::fs::remove_file(path);
+                let listener = UnixListener::bind(path).map_err(|e| e.to_string())?;
+                let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+                Self::from_stream(stream)
+            }
+            // A single path, e.g. a terminal device, is opened once for reading and writing
+            // and must not be truncated. A regular file would share one offset between both.
+            SerialEndpoint::Files { input, output } if input == output => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(input)
+                    .map_err(|e| e.to_string())?;
+                let metadata = file.metadata().map_err(|e| e.to_string())?;
+                if !metadata.file_type().is_char_device() {
+                    return Err(format!(
+                        "Serial file '{}' is used for input and output but is not a character device",
+                        input
+                    ));
+                }
+                let output = file.try_clone().map_err(|e| e.to_string())?;
+                Ok(Self::new(Box::new(file), Box::new(output)))
+            }
+            SerialEndpoint::Files { input, output } => {
+                let input = File::open(input).map_err(|e| e.to_string())?;
+                let output = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(output)
+                    .map_err(|e| e.to_string())?;
+                Ok(Self::new(Box::new(input), Box::new(output)))
+            }
+            SerialEndpoint::Pty => {
+                let (master, slave) = open_pty()?;
+                eprintln!("Serial port connected to {}", slave);
+                let output = master.try_clone().map_err(|e| e.to_string())?;
+                Ok(Self::new(Box::new(master), Box::new(output)))
+            }
+        }
+    }
+
+    /// Creates a serial port connected to a Unix domain socket stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the stream cannot be cloned.
+    pub fn from_stream(stream: UnixStream) -> Result<Self, String> {
+        let output = stream.try_clone().map_err(|e| e.to_string())?;
+        Ok(Self::new(Box::new(stream), Box::new(output)))
+    }
+
+    /// Creates a serial port from an input and an output.
+    ///
+    /// The input is read by a background thread until it reaches its end or fails.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Serial`.
+    pub fn new(mut input: Box<dyn Read + Send>, output: Box<dyn Write>) -> Self {
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || {
+            let mut byte = [0u8; 1];
+            while let Ok(1) = input.read(&mut byte) {
+                if sender.send(byte[0]).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            received,
+            output,
+            status: 0,
+            data: 0,
+            last_error: None,
+        }
+    }
+
+    /// Returns the message of the last failed transmission, if any.
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Moves the next received byte into the data register, if the previous one was read.
+    fn poll(&mut self) {
+        if self.status & SERIAL_READY != 0 {
+            return;
+        }
+        match self.received.try_recv() {
+            Ok(byte) => {
+                self.data = byte as u16;
+                self.status |= SERIAL_READY;
+            }
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {}
+        }
+    }
+}
+
+impl Device for Serial {
+    /// Reading the receiver data register clears the ready bit of the receiver status register.
+    fn read(&mut self, address: u16) -> Result<u16, String> {
+        match address {
+            a if a == MemoryMappedRegister::SRSR as u16 => {
+                self.poll();
+                Ok(self.status)
+            }
+            a if a == MemoryMappedRegister::SRDR as u16 => {
+                self.status &= !SERIAL_READY;
+                Ok(self.data)
+            }
+            a if a == MemoryMappedRegister::STSR as u16 => Ok(SERIAL_READY),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        match address {
+            a if a == MemoryMappedRegister::SRSR as u16 => {
+                self.status =
+                    (self.status & !SERIAL_INTERRUPT_ENABLE) | (value & SERIAL_INTERRUPT_ENABLE)
+            }
+            a if a == MemoryMappedRegister::STDR as u16 => {
+                let result = self
+                    .output
+                    .write_all(&[(value & 0xFF) as u8])
+                    .and_then(|_| self.output.flush());
+                if let Err(e) = result {
+                    self.last_error = Some(e.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Requests the receive interrupt while a received byte is waiting, if enabled.
+    fn tick(&mut self, _memory: &mut Dma) -> Option<Interrupt> {
+        if self.status & SERIAL_INTERRUPT_ENABLE == 0 {
+            return None;
+        }
+        self.poll();
+        (self.status & SERIAL_READY != 0).then_some(Interrupt {
+            vector: SERIAL_VECTOR,
+            priority: SERIAL_PRIORITY,
+        })
+    }
+}
+
+/// Creates a new pseudo-terminal in raw mode.
+///
+/// # Returns
+///
+/// The master side of the pseudo-terminal and the path of its slave side.
+fn open_pty() -> Result<(File, String), String> {
+    let master = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open("/dev/ptmx")
+        .map_err(|e| e.to_string())?;
+    let fd = master.as_raw_fd();
+    let mut name = [0 as libc::c_char; 128];
+    // SAFETY: `fd` is a valid pseudo-terminal master and `name` outlives the calls.
+    let failed = unsafe {
+        libc::grantpt(fd) != 0
+            || libc::unlockpt(fd) != 0
+            || libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0
+    };
+    if failed {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    // SAFETY: `ptsname_r` succeeded, so `name` holds a NUL-terminated string.
+    let slave = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+
+    // Disable echo and line buffering so bytes are exchanged unchanged
+    let mut tio = termios::Termios::from_fd(fd).map_err(|e| e.to_string())?;
+    termios::cfmakeraw(&mut tio);
+    termios::tcsetattr(fd, termios::TCSANOW, &tio).map_err(|e| e.to_string())?;
+    Ok((master, slave))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_path;
+    use std::time::{Duration, Instant};
+
+    /// Polls the receiver status register until a byte is available.
+    fn wait_for_byte(serial: &mut Serial) -> u16 {
+        let start = Instant::now();
+        while serial.read(MemoryMappedRegister::SRSR as u16).unwrap() & SERIAL_READY == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5), "no byte received");
+            thread::sleep(Duration::from_millis(1));
+        }
+        serial.read(MemoryMappedRegister::SRDR as u16).unwrap()
+    }
+
+    #[test]
+    fn parse_recognizes_endpoints() {
+        assert_eq!(
+            SerialEndpoint::parse("connect:/tmp/a.sock").unwrap(),
+            SerialEndpoint::Connect("/tmp/a.sock".to_string())
+        );
+        assert_eq!(
+            SerialEndpoint::parse("file:in.txt,out.txt").unwrap(),
+            SerialEndpoint::Files {
+                input: "in.txt".to_string(),
+                output: "out.txt".to_string()
+            }
+        );
+        assert_eq!(SerialEndpoint::parse("pty").unwrap(), SerialEndpoint::Pty);
+        assert!(SerialEndpoint::parse("tcp:localhost").is_err());
+    }
+
+    #[test]
+    fn bytes_are_exchanged_over_socket() {
+        let (local, mut remote) = UnixStream::pair().unwrap();
+        let mut serial = Serial::from_stream(local).unwrap();
+
+        remote.write_all(b"A").unwrap();
+        assert_eq!(wait_for_byte(&mut serial), 'A' as u16);
+        assert_eq!(serial.read(MemoryMappedRegister::SRSR as u16).unwrap(), 0);
+
+        assert_eq!(
+            serial.read(MemoryMappedRegister::STSR as u16).unwrap(),
+            SERIAL_READY
+        );
+        serial.write(MemoryMappedRegister::STDR as u16, 'z' as u16);
+        let mut byte = [0u8; 1];
+        remote.read_exact(&mut byte).unwrap();
+        assert_eq!(&byte, b"z");
+    }
+
+    #[test]
+    fn receive_interrupt_is_requested_until_data_is_read() {
+        let (local, mut remote) = UnixStream::pair().unwrap();
+        let mut serial = Serial::from_stream(local).unwrap();
+        serial.write(MemoryMappedRegister::SRSR as u16, SERIAL_INTERRUPT_ENABLE);
+        assert!(serial.tick(&mut Dma::new(&mut [])).is_none());
+
+        remote.write_all(b"x").unwrap();
+        let start = Instant::now();
+        while serial.tick(&mut Dma::new(&mut [])).is_none() {
+            assert!(start.elapsed() < Duration::from_secs(5), "no interrupt");
+            thread::sleep(Duration::from_millis(1));
+        }
+        serial.read(MemoryMappedRegister::SRDR as u16).unwrap();
+        assert!(serial.tick(&mut Dma::new(&mut [])).is_none());
+    }
+
+    #[test]
+    fn file_pair_reads_input_and_writes_output() {
+        let input = temp_path("serial-in");
+        let output = temp_path("serial-out");
+        std::fs::write(&input, b"hi").unwrap();
+        let endpoint = SerialEndpoint::Files {
+            input: input.to_str().unwrap().to_string(),
+            output: output.to_str().unwrap().to_string(),
+        };
+        let mut serial = Serial::open(&endpoint).unwrap();
+
+        assert_eq!(wait_for_byte(&mut serial), 'h' as u16);
+        assert_eq!(wait_for_byte(&mut serial), 'i' as u16);
+        serial.write(MemoryMappedRegister::STDR as u16, 'o' as u16);
+        drop(serial);
+        assert_eq!(std::fs::read(&output).unwrap(), b"o");
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn single_path_must_be_a_character_device() {
+        let path = temp_path("serial");
+        std::fs::write(&path, b"hi").unwrap();
+        let endpoint = SerialEndpoint::parse(&format!("file:{}", path.display())).unwrap();
+        assert_eq!(
+            Serial::open(&endpoint).err(),
+            Some(format!(
+                "Serial file '{}' is used for input and output but is not a character device",
+                path.display()
+            ))
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"hi");
+        std::fs::remove_file(path).unwrap();
+
+        let endpoint = SerialEndpoint::parse("file:/dev/null").unwrap();
+        let mut serial = Serial::open(&endpoint).unwrap();
+        serial.write(MemoryMappedRegister::STDR as u16, 'o' as u16);
+        assert_eq!(serial.last_error(), None);
+    }
+}
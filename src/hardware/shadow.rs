@@ -0,0 +1,51 @@
+/// Bitmap with one bit per word of memory, used to track which words were initialized.
+#[derive(Debug, Clone)]
+pub struct ShadowMemory {
+    /// Bits of the words, 64 per entry.
+    bits: Vec<u64>,
+}
+
+impl Default for ShadowMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShadowMemory {
+    /// Creates a bitmap with all bits cleared.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `ShadowMemory`.
+    pub fn new() -> Self {
+        Self {
+            bits: vec![0; 0x10000 / 64],
+        }
+    }
+
+    /// Sets the bit of an address.
+    pub fn set(&mut self, address: u16) {
+        self.bits[address as usize / 64] |= 1 << (address % 64);
+    }
+
+    /// Returns the bit of an address.
+    pub fn get(&self, address: u16) -> bool {
+        self.bits[address as usize / 64] & (1 << (address % 64)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_are_independent() {
+        let mut shadow = ShadowMemory::new();
+        shadow.set(0x3000);
+        shadow.set(0xFFFF);
+        assert!(shadow.get(0x3000));
+        assert!(shadow.get(0xFFFF));
+        assert!(!shadow.get(0x3001));
+        assert!(!shadow.get(0x2FFF));
+    }
+}
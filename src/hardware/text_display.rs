@@ -0,0 +1,224 @@
+use crate::hardware::device::{Device, Dma};
+use crate::isa::interrupts::Interrupt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+/// Default base address of the text framebuffer.
+pub const TEXT_BASE: u16 = 0xB000;
+
+/// Default number of columns of the text framebuffer.
+pub const TEXT_COLUMNS: u16 = 80;
+
+/// Default number of rows of the text framebuffer.
+pub const TEXT_ROWS: u16 = 24;
+
+/// Minimum time between two terminal refreshes.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(33);
+
+/// How the contents of the framebuffer are presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextRenderer {
+    /// Draw the framebuffer on the terminal using ANSI escape sequences.
+    Ansi,
+    /// Keep the framebuffer in memory only; its contents are available through `TextDisplay::lines`.
+    Headless,
+}
+
+/// Memory-mapped character framebuffer.
+///
+/// Each cell is a word mapped at `base + row * columns + column`:
+/// - bits 7-0: the ASCII character (0 is displayed as a space),
+/// - bits 11-8: the foreground color,
+/// - bits 15-12: the background color.
+///
+/// Color 0 is the terminal default, colors 1-8 are the standard ANSI colors
+/// (black, red, green, yellow, blue, magenta, cyan, white) and 9-15 their bright variants
+/// (from red to white).
+pub struct TextDisplay {
+    /// Address of the first cell.
+    base: u16,
+    /// Number of columns.
+    columns: u16,
+    /// Number of rows.
+    rows: u16,
+    /// Cell contents.
+    cells: Vec<u16>,
+    /// Cell contents as last drawn on the terminal.
+    drawn: Vec<Option<u16>>,
+    /// Presentation of the framebuffer.
+    renderer: TextRenderer,
+    /// Host time of the last terminal refresh.
+    last_refresh: Instant,
+}
+
+impl TextDisplay {
+    /// Creates a new, blank text framebuffer.
+    ///
+    /// # Parameters
+    ///
+    /// - `base`: Address of the first cell.
+    /// - `columns`: Number of columns.
+    /// - `rows`: Number of rows.
+    /// - `renderer`: Presentation of the framebuffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the framebuffer is empty or does not fit in memory.
+    pub fn new(base: u16, columns: u16, rows: u16, renderer: TextRenderer) -> Result<Self, String> {
+        let size = columns as u32 * rows as u32;
+        if size == 0 || base as u32 + size > 0x10000 {
+            return Err(format!(
+                "A {}x{} framebuffer does not fit in memory at x{:04X}",
+                columns, rows, base
+            ));
+        }
+        Ok(Self {
+            base,
+            columns,
+            rows,
+            cells: vec![0; size as usize],
+            drawn: vec![None; size as usize],
+            renderer,
+            last_refresh: Instant::now(),
+        })
+    }
+
+    /// Returns the range of addresses occupied by the framebuffer.
+    pub fn range(&self) -> RangeInclusive<u16> {
+        self.base..=self.base + (self.cells.len() - 1) as u16
+    }
+
+    /// Returns the contents of the framebuffer as one string per row, without colors.
+    pub fn lines(&self) -> Vec<String> {
+        self.cells
+            .chunks(self.columns as usize)
+            .map(|row| row.iter().map(|&cell| cell_char(cell)).collect())
+            .collect()
+    }
+
+    /// Draws every cell that changed since the last refresh.
+    ///
+    /// Does nothing with the headless renderer. After the final refresh the cursor is
+    /// left below the framebuffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if writing to standard output fails.
+    pub fn refresh(&mut self) -> Result<(), String> {
+        if self.renderer == TextRenderer::Headless {
+            return Ok(());
+        }
+        let mut out = io::stdout().lock();
+        self.draw(&mut out).map_err(|e| e.to_string())?;
+        write!(out, "\x1b[{};1H", self.rows + 1).map_err(|e| e.to_string())?;
+        out.flush().map_err(|e| e.to_string())
+    }
+
+    /// Writes the escape sequences that bring the terminal up to date with the framebuffer.
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.drawn.iter().all(Option::is_none) {
+            write!(out, "\x1b[2J")?;
+        }
+        for (i, &cell) in self.cells.iter().enumerate() {
+            if self.drawn[i] == Some(cell) {
+                continue;
+            }
+            let row = i / self.columns as usize + 1;
+            let column = i % self.columns as usize + 1;
+            write!(
+                out,
+                "\x1b[{};{}H\x1b[0;{};{}m{}",
+                row,
+                column,
+                ansi_color((cell >> 8) & 0xF, 30, 90),
+                ansi_color(cell >> 12, 40, 100),
+                cell_char(cell)
+            )?;
+            self.drawn[i] = Some(cell);
+        }
+        write!(out, "\x1b[0m")
+    }
+}
+
+impl Device for TextDisplay {
+    fn read(&mut self, address: u16) -> Result<u16, String> {
+        Ok(self.cells[address.wrapping_sub(self.base) as usize])
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        self.cells[address.wrapping_sub(self.base) as usize] = value;
+    }
+
+    /// Refreshes the terminal at most every `REFRESH_INTERVAL`.
+    fn tick(&mut self, _memory: &mut Dma) -> Option<Interrupt> {
+        if self.renderer == TextRenderer::Ansi && self.last_refresh.elapsed() >= REFRESH_INTERVAL {
+            self.last_refresh = Instant::now();
+            let mut out = io::stdout().lock();
+            // A failed refresh is retried on the next one
+            if self.draw(&mut out).is_ok() {
+                let _ = out.flush();
+            }
+        }
+        None
+    }
+}
+
+/// Returns the character displayed by a cell.
+fn cell_char(cell: u16) -> char {
+    match (cell & 0xFF) as u8 {
+        0 => ' ',
+        c => c as char,
+    }
+}
+
+/// Converts a 4-bit color index to an ANSI SGR parameter.
+fn ansi_color(color: u16, normal: u16, bright: u16) -> u16 {
+    match color {
+        0 => normal + 9, // default color
+        1..=8 => normal + color - 1,
+        _ => bright + color - 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framebuffer_must_fit_in_memory() {
+        assert!(TextDisplay::new(0xFF00, 80, 24, TextRenderer::Headless).is_err());
+        assert!(TextDisplay::new(TEXT_BASE, 0, 24, TextRenderer::Headless).is_err());
+    }
+
+    #[test]
+    fn range_covers_every_cell() {
+        let display = TextDisplay::new(TEXT_BASE, 80, 24, TextRenderer::Headless).unwrap();
+        assert_eq!(display.range(), 0xB000..=0xB77F);
+    }
+
+    #[test]
+    fn headless_lines_show_written_characters() {
+        let mut display = TextDisplay::new(TEXT_BASE, 4, 2, TextRenderer::Headless).unwrap();
+        display.write(TEXT_BASE + 1, 'h' as u16 | 0x0200);
+        display.write(TEXT_BASE + 6, 'i' as u16);
+        assert_eq!(display.read(TEXT_BASE + 1).unwrap(), 0x0268);
+        assert_eq!(display.lines(), vec![" h  ", "  i "]);
+    }
+
+    #[test]
+    fn draw_only_emits_changed_cells() {
+        let mut display = TextDisplay::new(TEXT_BASE, 2, 1, TextRenderer::Ansi).unwrap();
+        let mut out = Vec::new();
+        display.draw(&mut out).unwrap();
+        assert!(out.starts_with(b"\x1b[2J"));
+
+        display.write(TEXT_BASE + 1, 'x' as u16 | 0x1200);
+        let mut out = Vec::new();
+        display.draw(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[1;2H\x1b[0;31;40mx\x1b[0m"
+        );
+    }
+}
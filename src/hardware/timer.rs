@@ -0,0 +1,210 @@
+use crate::hardware::device::{Device, Dma};
+use crate::hardware::memory::MemoryMappedRegister;
+use crate::isa::interrupts::Interrupt;
+use std::time::{Duration, Instant};
+
+/// Interrupt vector used by the timer (the handler address is stored at `x0100 + TIMER_VECTOR`).
+pub const TIMER_VECTOR: u8 = 0x81;
+
+/// Status register bit set when the interval has elapsed. Cleared when the status register is read.
+pub const TIMER_READY: u16 = 1 << 15;
+
+/// Control register bit that enables the timer.
+pub const TIMER_ENABLE: u16 = 1 << 15;
+
+/// Control register bit that enables the timer interrupt.
+pub const TIMER_INTERRUPT_ENABLE: u16 = 1 << 14;
+
+/// Control register bit that selects the interval unit: milliseconds when set, instructions otherwise.
+pub const TIMER_MILLISECONDS: u16 = 1 << 13;
+
+/// Programmable interval timer mapped into the device page.
+///
+/// The timer is configured through three registers:
+/// - `TMSR` (status): bit 15 is set every time the interval elapses and cleared on read.
+/// - `TMCR` (control): bit 15 enables the timer, bit 14 enables interrupts, bit 13 selects
+///   milliseconds instead of instructions and bits 10-8 hold the interrupt priority.
+/// - `TMIR` (interval): the number of instructions or milliseconds between two expirations.
+pub struct Timer {
+    /// Status register contents.
+    status: u16,
+    /// Control register contents.
+    control: u16,
+    /// Interval register contents.
+    interval: u16,
+    /// Instructions executed since the last expiration.
+    instructions: u16,
+    /// Host time of the last expiration.
+    last_expiration: Instant,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    /// Creates a new, disabled `Timer`.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Timer`.
+    pub fn new() -> Self {
+        Self {
+            status: 0,
+            control: 0,
+            interval: 0,
+            instructions: 0,
+            last_expiration: Instant::now(),
+        }
+    }
+
+    /// Checks whether the interval has elapsed, starting a new one if it has.
+    fn expired(&mut self) -> bool {
+        if self.control & TIMER_MILLISECONDS != 0 {
+            let interval = Duration::from_millis(self.interval as u64);
+            if self.last_expiration.elapsed() >= interval {
+                self.last_expiration += interval;
+                return true;
+            }
+        } else {
+            self.instructions = self.instructions.saturating_add(1);
+            if self.instructions >= self.interval {
+                self.instructions = 0;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Restarts the current interval.
+    fn restart(&mut self) {
+        self.instructions = 0;
+        self.last_expiration = Instant::now();
+    }
+}
+
+impl Device for Timer {
+    /// Reading the status register acknowledges the expiration by clearing its ready bit.
+    fn read(&mut self, address: u16) -> Result<u16, String> {
+        match address {
+            a if a == MemoryMappedRegister::TMSR as u16 => {
+                let status = self.status;
+                self.status &= !TIMER_READY;
+                Ok(status)
+            }
+            a if a == MemoryMappedRegister::TMCR as u16 => Ok(self.control),
+            a if a == MemoryMappedRegister::TMIR as u16 => Ok(self.interval),
+            _ => Ok(0),
+        }
+    }
+
+    /// Writing the control or interval register restarts the current interval.
+    fn write(&mut self, address: u16, value: u16) {
+        match address {
+            a if a == MemoryMappedRegister::TMSR as u16 => self.status = value,
+            a if a == MemoryMappedRegister::TMCR as u16 => {
+                self.control = value;
+                self.restart();
+            }
+            a if a == MemoryMappedRegister::TMIR as u16 => {
+                self.interval = value;
+                self.restart();
+            }
+            _ => {}
+        }
+    }
+
+    /// The timer keeps requesting an interrupt, if enabled, until the expiration is acknowledged.
+    fn tick(&mut self, _memory: &mut Dma) -> Option<Interrupt> {
+        if self.control & TIMER_ENABLE != 0 && self.interval != 0 && self.expired() {
+            self.status |= TIMER_READY;
+        }
+
+        if self.status & TIMER_READY != 0 && self.control & TIMER_INTERRUPT_ENABLE != 0 {
+            Some(Interrupt {
+                vector: TIMER_VECTOR,
+                priority: ((self.control >> 8) & 0x7) as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick_times(timer: &mut Timer, n: usize) -> Option<Interrupt> {
+        let mut irq = None;
+        for _ in 0..n {
+            irq = timer.tick(&mut Dma::new(&mut []));
+        }
+        irq
+    }
+
+    #[test]
+    fn disabled_timer_never_expires() {
+        let mut timer = Timer::new();
+        timer.write(MemoryMappedRegister::TMIR as u16, 1);
+        tick_times(&mut timer, 10);
+        assert_eq!(timer.read(MemoryMappedRegister::TMSR as u16).unwrap(), 0);
+    }
+
+    #[test]
+    fn polled_timer_sets_ready_bit_after_interval() {
+        let mut timer = Timer::new();
+        timer.write(MemoryMappedRegister::TMIR as u16, 3);
+        timer.write(MemoryMappedRegister::TMCR as u16, TIMER_ENABLE);
+
+        assert!(tick_times(&mut timer, 2).is_none());
+        assert_eq!(timer.read(MemoryMappedRegister::TMSR as u16).unwrap(), 0);
+
+        assert!(timer.tick(&mut Dma::new(&mut [])).is_none());
+        assert_eq!(
+            timer.read(MemoryMappedRegister::TMSR as u16).unwrap(),
+            TIMER_READY
+        );
+        // Reading the status register acknowledges the expiration
+        assert_eq!(timer.read(MemoryMappedRegister::TMSR as u16).unwrap(), 0);
+    }
+
+    #[test]
+    fn timer_requests_interrupt_until_acknowledged() {
+        let mut timer = Timer::new();
+        timer.write(MemoryMappedRegister::TMIR as u16, 3);
+        timer.write(
+            MemoryMappedRegister::TMCR as u16,
+            TIMER_ENABLE | TIMER_INTERRUPT_ENABLE | (4 << 8),
+        );
+
+        let expected = Some(Interrupt {
+            vector: TIMER_VECTOR,
+            priority: 4,
+        });
+        assert!(tick_times(&mut timer, 2).is_none());
+        assert_eq!(timer.tick(&mut Dma::new(&mut [])), expected);
+        assert_eq!(timer.tick(&mut Dma::new(&mut [])), expected);
+
+        timer.read(MemoryMappedRegister::TMSR as u16).unwrap();
+        assert!(timer.tick(&mut Dma::new(&mut [])).is_none());
+    }
+
+    #[test]
+    fn millisecond_timer_expires_after_interval() {
+        let mut timer = Timer::new();
+        timer.write(MemoryMappedRegister::TMIR as u16, 1);
+        timer.write(
+            MemoryMappedRegister::TMCR as u16,
+            TIMER_ENABLE | TIMER_MILLISECONDS,
+        );
+        std::thread::sleep(Duration::from_millis(2));
+        timer.tick(&mut Dma::new(&mut []));
+        assert_eq!(
+            timer.read(MemoryMappedRegister::TMSR as u16).unwrap(),
+            TIMER_READY
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use crate::hardware::device::{Device, Dma};
+use crate::isa::interrupts::Interrupt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// Address of the first pixel of video memory.
+pub const VIDEO_BASE: u16 = 0xC000;
+
+/// Width of the screen in pixels.
+pub const VIDEO_WIDTH: usize = 128;
+
+/// Height of the screen in pixels.
+pub const VIDEO_HEIGHT: usize = 124;
+
+/// Image formats frames can be saved as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameFormat {
+    /// Portable Network Graphics.
+    Png,
+    /// Binary portable pixmap (P6).
+    Ppm,
+}
+
+impl FrameFormat {
+    /// Selects the format from the extension of `path` (`.png` or `.ppm`).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the extension is missing or not supported.
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("png") => Ok(FrameFormat::Png),
+            Some(ext) if ext.eq_ignore_ascii_case("ppm") => Ok(FrameFormat::Ppm),
+            _ => Err(format!(
+                "Unsupported image format for '{}', expected .png or .ppm",
+                path.display()
+            )),
+        }
+    }
+
+    /// Returns the file extension of the format.
+    fn extension(self) -> &'static str {
+        match self {
+            FrameFormat::Png => "png",
+            FrameFormat::Ppm => "ppm",
+        }
+    }
+}
+
+/// Periodic dumping of frames to a directory.
+struct FrameDump {
+    /// Directory the frames are written to.
+    directory: PathBuf,
+    /// Format of the written frames.
+    format: FrameFormat,
+    /// Number of instructions between two frames.
+    interval: u32,
+    /// Instructions executed since the last frame.
+    elapsed: u32,
+    /// Number of frames written so far.
+    count: u32,
+}
+
+/// Memory-mapped pixel framebuffer, compatible with the PennSim video memory.
+///
+/// The screen is `VIDEO_WIDTH` x `VIDEO_HEIGHT` pixels mapped row by row starting at
+/// `VIDEO_BASE` (`xC000`-`xFDFF`). Each pixel is a 15-bit RGB word: bits 14-10 red,
+/// bits 9-5 green and bits 4-0 blue. The display is headless: its contents can be saved
+/// as PNG or PPM images, either on demand or periodically while the program runs.
+pub struct VideoDisplay {
+    /// Pixel contents.
+    pixels: Vec<u16>,
+    /// Whether the pixels changed since the last dumped frame.
+    dirty: bool,
+    /// Periodic frame dumping, if enabled.
+    frame_dump: Option<FrameDump>,
+    /// Message of the last failed frame dump.
+    last_error: Option<String>,
+}
+
+impl Default for VideoDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VideoDisplay {
+    /// Creates a new, black video display.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `VideoDisplay`.
+    pub fn new() -> Self {
+        Self {
+            pixels: vec![0; VIDEO_WIDTH * VIDEO_HEIGHT],
+            dirty: false,
+            frame_dump: None,
+            last_error: None,
+        }
+    }
+
+    /// Returns the range of addresses occupied by video memory.
+    pub fn range() -> RangeInclusive<u16> {
+        VIDEO_BASE..=VIDEO_BASE + (VIDEO_WIDTH * VIDEO_HEIGHT - 1) as u16
+    }
+
+    /// Enables periodic frame dumping.
+    ///
+    /// Every `interval` instructions, if the screen changed, a frame is written to
+    /// `directory` as `frame-NNNNNN.<ext>`.
+    ///
+    /// # Parameters
+    ///
+    /// - `directory`: Directory the frames are written to. It is created if missing.
+    /// - `format`: Format of the written frames.
+    /// - `interval`: Number of instructions between two frames.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the directory cannot be created or the interval is zero.
+    pub fn dump_frames(
+        &mut self,
+        directory: &Path,
+        format: FrameFormat,
+        interval: u32,
+    ) -> Result<(), String> {
+        if interval == 0 {
+            return Err("The frame interval must be at least one instruction".to_string());
+        }
+        std::fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+        self.frame_dump = Some(FrameDump {
+            directory: directory.to_path_buf(),
+            format,
+            interval,
+            elapsed: 0,
+            count: 0,
+        });
+        Ok(())
+    }
+
+    /// Returns the message of the last failed frame dump, if any.
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Returns the 15-bit color of the pixel at (`x`, `y`).
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * VIDEO_WIDTH + x]
+    }
+
+    /// Converts the screen to 8-bit RGB triplets, row by row.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&pixel| {
+                [pixel >> 10, pixel >> 5, pixel].map(|c| {
+                    let c = (c & 0x1F) as u8;
+                    (c << 3) | (c >> 2)
+                })
+            })
+            .collect()
+    }
+
+    /// Saves the screen to `path`, in the format given by its extension.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the format is not supported or the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        self.save_as(path, FrameFormat::from_path(path)?)
+    }
+
+    /// Saves the screen to `path` in the given format.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the file cannot be written.
+    pub fn save_as(&self, path: &Path, format: FrameFormat) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut writer = BufWriter::new(file);
+        match format {
+            FrameFormat::Ppm => {
+                write!(writer, "P6\n{} {}\n255\n", VIDEO_WIDTH, VIDEO_HEIGHT)
+                    .map_err(|e| e.to_string())?;
+                writer
+                    .write_all(&self.to_rgb())
+                    .map_err(|e| e.to_string())?;
+                writer.flush().map_err(|e| e.to_string())
+            }
+            FrameFormat::Png => {
+                let mut encoder =
+                    png::Encoder::new(writer, VIDEO_WIDTH as u32, VIDEO_HEIGHT as u32);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                let mut png_writer = encoder.write_header().map_err(|e| e.to_string())?;
+                png_writer
+                    .write_image_data(&self.to_rgb())
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+impl Device for VideoDisplay {
+    fn read(&mut self, address: u16) -> Result<u16, String> {
+        Ok(self.pixels[address.wrapping_sub(VIDEO_BASE) as usize])
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        self.pixels[address.wrapping_sub(VIDEO_BASE) as usize] = value & 0x7FFF;
+        self.dirty = true;
+    }
+
+    /// Dumps a frame every interval, if the screen changed.
+    fn tick(&mut self, _memory: &mut Dma) -> Option<Interrupt> {
+        let dump = self.frame_dump.as_mut()?;
+        dump.elapsed += 1;
+        if dump.elapsed < dump.interval || !self.dirty {
+            return None;
+        }
+        dump.elapsed = 0;
+        let path = dump.directory.join(format!(
+            "frame-{:06}.{}",
+            dump.count,
+            dump.format.extension()
+        ));
+        let format = dump.format;
+        dump.count += 1;
+        self.dirty = false;
+        if let Err(e) = self.save_as(&path, format) {
+            self.last_error = Some(format!("{}: {}", path.display(), e));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_path;
+
+    #[test]
+    fn range_matches_pennsim_video_memory() {
+        assert_eq!(VideoDisplay::range(), 0xC000..=0xFDFF);
+    }
+
+    #[test]
+    fn pixels_are_converted_to_rgb() {
+        let mut video = VideoDisplay::new();
+        video.write(VIDEO_BASE, 0x7C00); // red
+        video.write(VIDEO_BASE + 1, 0x001F); // blue
+        video.write(VIDEO_BASE + VIDEO_WIDTH as u16, 0xFFFF); // white, bit 15 ignored
+        assert_eq!(video.pixel(0, 1), 0x7FFF);
+
+        let rgb = video.to_rgb();
+        assert_eq!(rgb[0..6], [255, 0, 0, 0, 0, 255]);
+        let row = VIDEO_WIDTH * 3;
+        assert_eq!(rgb[row..row + 3], [255, 255, 255]);
+    }
+
+    #[test]
+    fn save_ppm_writes_header_and_pixels() {
+        let mut video = VideoDisplay::new();
+        video.write(VIDEO_BASE, 0x03E0); // green
+        let path = temp_path("frame.ppm");
+        video.save(&path).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let header = b"P6\n128 124\n255\n";
+        assert!(bytes.starts_with(header));
+        assert_eq!(bytes.len(), header.len() + VIDEO_WIDTH * VIDEO_HEIGHT * 3);
+        assert_eq!(bytes[header.len()..header.len() + 3], [0, 255, 0]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_png_writes_valid_image() {
+        let mut video = VideoDisplay::new();
+        video.write(VIDEO_BASE + 5, 0x7FFF);
+        let path = temp_path("frame.png");
+        video.save(&path).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        assert_eq!((info.width, info.height), (128, 124));
+        assert_eq!(buffer[15..18], [255, 255, 255]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unsupported_extension_is_rejected() {
+        assert!(FrameFormat::from_path(Path::new("frame.bmp")).is_err());
+    }
+
+    #[test]
+    fn frames_are_dumped_only_when_screen_changes() {
+        let mut video = VideoDisplay::new();
+        let directory = temp_path("frames");
+        video.dump_frames(&directory, FrameFormat::Ppm, 2).unwrap();
+
+        video.write(VIDEO_BASE, 1);
+        for _ in 0..6 {
+            video.tick(&mut Dma::new(&mut []));
+        }
+        assert!(video.last_error().is_none());
+        assert!(directory.join("frame-000000.ppm").exists());
+        assert!(!directory.join("frame-000001.ppm").exists());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,691 @@
+use crate::hardware::display::Display;
+use crate::hardware::keyboard::Keyboard;
+use crate::hardware::registers::Register;
+use crate::loader::Image;
+use crate::symbols::SymbolTable;
+use crate::utils::parse_word;
+use crate::vm::VM;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use toml::{Table, Value};
+
+/// Number of instructions a test case may execute unless its spec sets a budget.
+pub const DEFAULT_BUDGET: u64 = 1_000_000;
+
+/// Words at consecutive addresses, written before a test case runs or expected after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryBlock {
+    /// Address of the first word.
+    pub address: u16,
+    /// The words, in order.
+    pub words: Vec<u16>,
+}
+
+/// What a test case checks once the program stops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expectations {
+    /// Everything the program writes to the console, if checked.
+    pub stdout: Option<String>,
+    /// Whether the program halts within the budget, `true` by default.
+    pub halted: bool,
+    /// Expected contents of registers.
+    pub registers: Vec<(Register, u16)>,
+    /// Expected contents of memory.
+    pub memory: Vec<MemoryBlock>,
+}
+
+impl Default for Expectations {
+    fn default() -> Self {
+        Self {
+            stdout: None,
+            halted: true,
+            registers: Vec::new(),
+            memory: Vec::new(),
+        }
+    }
+}
+
+/// A test case: the initial state of the VM and what to check once the program stops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    /// Name of the test case.
+    pub name: String,
+    /// Characters read from the keyboard, instead of standard input.
+    pub stdin: String,
+    /// Maximum number of instructions executed.
+    pub budget: u64,
+    /// Registers set before running, once the images are loaded.
+    pub registers: Vec<(Register, u16)>,
+    /// Words written to memory before running, once the images are loaded.
+    pub memory: Vec<MemoryBlock>,
+    /// What to check once the program stops.
+    pub expect: Expectations,
+}
+
+/// Outcome of a test case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every expectation was met.
+    Passed,
+    /// Some expectations were not met, described one per message.
+    Failed(Vec<String>),
+    /// The VM stopped with an error, followed by the backtrace of the faulting instruction.
+    Error(String),
+}
+
+/// Result of running a test case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseResult {
+    /// Name of the test case.
+    pub name: String,
+    /// Whether the test case passed.
+    pub outcome: Outcome,
+    /// Everything the program wrote to the console.
+    pub stdout: String,
+    /// Time taken to run the test case.
+    pub time: Duration,
+}
+
+/// A test spec: the program under test and its test cases, read from a TOML file.
+///
+/// ```toml
+/// images = ["sum.obj"]
+/// budget = 10000
+///
+/// [[test]]
+/// name = "adds two numbers"
+/// stdin = "y"
+/// registers = { R1 = 5, R2 = "x0010" }
+/// memory = [{ address = "x4000", words = [1, -1] }]
+/// expect = { stdout = "Sum: 21\n", registers = { R0 = 21 } }
+/// ```
+///
+/// Expected memory is given as `words` or as a `string`, checked with its terminating zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestSpec {
+    /// Name of the spec, used as the test suite name of JUnit reports.
+    pub name: String,
+    /// Image files of the program under test.
+    pub images: Vec<PathBuf>,
+    /// The test cases, in order.
+    pub cases: Vec<TestCase>,
+}
+
+impl TestSpec {
+    /// Reads a spec named after its file, whose images are relative to the spec.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the file cannot be read or is not a valid spec.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let error = |e: String| format!("{}: {}", path.display(), e);
+        let text = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        let name = path
+            .file_stem()
+            .map_or_else(|| "tests".to_string(), |s| s.to_string_lossy().into_owned());
+        let mut spec = Self::parse(&name, &text).map_err(error)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        spec.images = spec.images.iter().map(|image| dir.join(image)).collect();
+        Ok(spec)
+    }
+
+    /// Parses a spec.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the spec, unless it sets one.
+    /// * `text` - Contents of the spec.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error naming the invalid key, and its test case if any.
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let spec: Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        check_keys(&spec, &["name", "images", "budget", "test"])?;
+        let name = match spec.get("name") {
+            Some(value) => string(value, "name")?,
+            None => name.to_string(),
+        };
+        let images = match spec.get("images") {
+            Some(value) => array(value, "images")?
+                .iter()
+                .map(|image| string(image, "images").map(PathBuf::from))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        let budget = match spec.get("budget") {
+            Some(value) => integer(value, "budget")?,
+            None => DEFAULT_BUDGET,
+        };
+        let cases = match spec.get("test") {
+            Some(value) => array(value, "test")?
+                .iter()
+                .enumerate()
+                .map(|(index, case)| TestCase::parse(case, index, budget))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+        if cases.is_empty() {
+            return Err("No test cases, expected [[test]] tables".to_string());
+        }
+        Ok(Self {
+            name,
+            images,
+            cases,
+        })
+    }
+}
+
+impl TestCase {
+    /// Parses the `index`th `[[test]]` table of a spec.
+    fn parse(value: &Value, index: usize, budget: u64) -> Result<Self, String> {
+        let case = table(value, "test")?;
+        let name = match case.get("name") {
+            Some(value) => string(value, "name")?,
+            None => format!("test {}", index + 1),
+        };
+        let parse = || -> Result<Self, String> {
+            check_keys(
+                case,
+                &["name", "stdin", "budget", "registers", "memory", "expect"],
+            )?;
+            let mut parsed = Self {
+                name: name.clone(),
+                stdin: String::new(),
+                budget,
+                registers: Vec::new(),
+                memory: Vec::new(),
+                expect: Expectations::default(),
+            };
+            if let Some(value) = case.get("stdin") {
+                parsed.stdin = string(value, "stdin")?;
+            }
+            if let Some(value) = case.get("budget") {
+                parsed.budget = integer(value, "budget")?;
+            }
+            if let Some(value) = case.get("registers") {
+                parsed.registers = registers(value, "registers")?;
+            }
+            if let Some(value) = case.get("memory") {
+                parsed.memory = memory(value, "memory")?;
+            }
+            if let Some(value) = case.get("expect") {
+                parsed.expect = Expectations::parse(value)?;
+            }
+            Ok(parsed)
+        };
+        parse().map_err(|e| format!("test '{}': {}", name, e))
+    }
+
+    /// Runs the test case on a new VM whose keyboard reads `stdin` and whose display
+    /// output is captured.
+    ///
+    /// # Arguments
+    ///
+    /// * `images` - The program under test.
+    /// * `symbols` - Labels used to describe addresses in failures.
+    ///
+    /// # Returns
+    ///
+    /// The outcome of the test case and the output of the program.
+    pub fn run(&self, images: &[Image], symbols: &SymbolTable) -> CaseResult {
+        let start = Instant::now();
+        let mut vm = VM::new();
+        vm.symbols_mut().extend(symbols);
+        if let Some(keyboard) = vm.device_mut::<Keyboard>() {
+            keyboard.set_input(self.stdin.as_bytes());
+        }
+        if let Some(display) = vm.device_mut::<Display>() {
+            display.capture();
+        }
+        for image in images {
+            vm.load_image(image);
+        }
+        for block in &self.memory {
+            vm.load_image(&Image::new("memory", block.address, block.words.clone()));
+        }
+        for (register, value) in &self.registers {
+            vm.registers_mut().write(*register, *value);
+        }
+        let result = vm.run_for(self.budget);
+        let stdout = vm
+            .device::<Display>()
+            .map(|display| display.output().to_string())
+            .unwrap_or_default();
+        let outcome = match result {
+            Ok(halted) => {
+                let failures = self.check(&vm, halted, &stdout);
+                if failures.is_empty() {
+                    Outcome::Passed
+                } else {
+                    Outcome::Failed(failures)
+                }
+            }
+            Err(e) => Outcome::Error(format!("{}\n{}", e, vm.backtrace())),
+        };
+        CaseResult {
+            name: self.name.clone(),
+            outcome,
+            stdout,
+            time: start.elapsed(),
+        }
+    }
+
+    /// Checks the expectations once the program stopped, describing the unmet ones.
+    fn check(&self, vm: &VM, halted: bool, stdout: &str) -> Vec<String> {
+        let mut failures = Vec::new();
+        let expect = &self.expect;
+        if halted && !expect.halted {
+            failures.push(format!(
+                "halted, expected to still run after {} instructions",
+                self.budget
+            ));
+        } else if !halted && expect.halted {
+            failures.push(format!(
+                "did not halt within {} instructions\n{}",
+                self.budget,
+                vm.backtrace()
+            ));
+        }
+        if let Some(expected) = &expect.stdout {
+            if stdout != expected {
+                failures.push(format!("stdout: expected {:?}, got {:?}", expected, stdout));
+            }
+        }
+        for (register, expected) in &expect.registers {
+            let actual = vm.registers().read(*register);
+            if actual != *expected {
+                failures.push(format!(
+                    "{:?}: expected x{:04X}, got x{:04X}",
+                    register, expected, actual
+                ));
+            }
+        }
+        for block in &expect.memory {
+            for (offset, expected) in block.words.iter().enumerate() {
+                let address = block.address.wrapping_add(offset as u16);
+                let actual = vm.peek(address);
+                if actual != *expected {
+                    failures.push(format!(
+                        "memory at {}: expected x{:04X}, got x{:04X}",
+                        vm.symbols().describe(address),
+                        expected,
+                        actual
+                    ));
+                }
+            }
+        }
+        failures
+    }
+}
+
+impl Expectations {
+    /// Parses the `expect` table of a test case.
+    fn parse(value: &Value) -> Result<Self, String> {
+        let expect = table(value, "expect")?;
+        check_keys(expect, &["stdout", "halted", "registers", "memory"])?;
+        let mut parsed = Self::default();
+        if let Some(value) = expect.get("stdout") {
+            parsed.stdout = Some(string(value, "stdout")?);
+        }
+        if let Some(value) = expect.get("halted") {
+            parsed.halted = value.as_bool().ok_or("'halted' must be true or false")?;
+        }
+        if let Some(value) = expect.get("registers") {
+            parsed.registers = registers(value, "registers")?;
+        }
+        if let Some(value) = expect.get("memory") {
+            parsed.memory = memory(value, "memory")?;
+        }
+        Ok(parsed)
+    }
+}
+
+/// Writes a line per test case, the messages of the failed ones and a summary.
+///
+/// # Errors
+///
+/// Returns a `String` error if writing fails.
+pub fn write_report(results: &[CaseResult], writer: &mut dyn Write) -> Result<(), String> {
+    let mut text = String::new();
+    let mut details = String::new();
+    let (mut failed, mut errors) = (0, 0);
+    for result in results {
+        let status = match &result.outcome {
+            Outcome::Passed => "ok",
+            Outcome::Failed(failures) => {
+                failed += 1;
+                details.push_str(&format!("\n---- {} ----\n", result.name));
+                for failure in failures {
+                    details.push_str(&format!("{}\n", failure.trim_end()));
+                }
+                "FAILED"
+            }
+            Outcome::Error(error) => {
+                errors += 1;
+                details.push_str(&format!(
+                    "\n---- {} ----\n{}\n",
+                    result.name,
+                    error.trim_end()
+                ));
+                "ERROR"
+            }
+        };
+        text.push_str(&format!("test {} ... {}\n", result.name, status));
+    }
+    if !details.is_empty() {
+        text.push_str(&format!("\nfailures:\n{}", details));
+    }
+    text.push_str(&format!(
+        "\ntest result: {}. {} passed; {} failed; {} errors\n",
+        if failed + errors == 0 { "ok" } else { "FAILED" },
+        results.len() - failed - errors,
+        failed,
+        errors
+    ));
+    writer.write_all(text.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// Writes the results as a JUnit XML report with a single test suite.
+///
+/// Unmet expectations are reported as failures and VM errors as errors; the output of
+/// each program is kept as the `system-out` of its test case.
+///
+/// # Arguments
+///
+/// * `suite` - Name of the test suite, e.g. the name of the spec.
+/// * `results` - The results of the test cases.
+/// * `writer` - Destination of the report.
+///
+/// # Errors
+///
+/// Returns a `String` error if writing fails.
+pub fn write_junit(
+    suite: &str,
+    results: &[CaseResult],
+    writer: &mut dyn Write,
+) -> Result<(), String> {
+    let count = |error: bool| {
+        results
+            .iter()
+            .filter(|r| match r.outcome {
+                Outcome::Passed => false,
+                Outcome::Failed(_) => !error,
+                Outcome::Error(_) => error,
+            })
+            .count()
+    };
+    let total: Duration = results.iter().map(|r| r.time).sum();
+    let suite = escape_xml(suite);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+        suite,
+        results.len(),
+        count(false),
+        count(true),
+        total.as_secs_f64()
+    ));
+    for result in results {
+        xml.push_str(&format!(
+            "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">\n",
+            escape_xml(&result.name),
+            suite,
+            result.time.as_secs_f64()
+        ));
+        let (element, messages) = match &result.outcome {
+            Outcome::Passed => ("", Vec::new()),
+            Outcome::Failed(failures) => ("failure", failures.clone()),
+            Outcome::Error(error) => ("error", vec![error.clone()]),
+        };
+        if let Some(first) = messages.first() {
+            let message = first.lines().next().unwrap_or_default();
+            xml.push_str(&format!(
+                "    <{} message=\"{}\">{}</{}>\n",
+                element,
+                escape_xml(message),
+                escape_xml(&messages.join("\n")),
+                element
+            ));
+        }
+        if !result.stdout.is_empty() {
+            xml.push_str(&format!(
+                "    <system-out>{}</system-out>\n",
+                escape_xml(&result.stdout)
+            ));
+        }
+        xml.push_str("  </testcase>\n");
+    }
+    xml.push_str("</testsuite>\n");
+    writer.write_all(xml.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// Escapes text for XML content and attributes, replacing the control characters XML
+/// cannot hold, e.g. a stray byte printed by the program.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => escaped.push(char::REPLACEMENT_CHARACTER),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Rejects the keys of a table not in `allowed`, e.g. misspelled ones.
+fn check_keys(table: &Table, allowed: &[&str]) -> Result<(), String> {
+    match table.keys().find(|key| !allowed.contains(&key.as_str())) {
+        Some(key) => Err(format!(
+            "unknown key '{}', expected one of {}",
+            key,
+            allowed.join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Reads a table.
+fn table<'a>(value: &'a Value, key: &str) -> Result<&'a Table, String> {
+    value
+        .as_table()
+        .ok_or_else(|| format!("'{}' must be a table", key))
+}
+
+/// Reads an array.
+fn array<'a>(value: &'a Value, key: &str) -> Result<&'a Vec<Value>, String> {
+    value
+        .as_array()
+        .ok_or_else(|| format!("'{}' must be an array", key))
+}
+
+/// Reads a string.
+fn string(value: &Value, key: &str) -> Result<String, String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("'{}' must be a string", key))
+}
+
+/// Reads a non-negative integer.
+fn integer(value: &Value, key: &str) -> Result<u64, String> {
+    value
+        .as_integer()
+        .and_then(|integer| u64::try_from(integer).ok())
+        .ok_or_else(|| format!("'{}' must be a non-negative integer", key))
+}
+
+/// Reads a word, given as an integer from -32768 to 65535 or as a string such as `"x3000"`.
+fn word(value: &Value, key: &str) -> Result<u16, String> {
+    match value {
+        Value::Integer(integer) if (-0x8000..=0xFFFF).contains(integer) => Ok(*integer as u16),
+        Value::String(text) => parse_word(text).map_err(|e| format!("'{}': {}", key, e)),
+        _ => Err(format!("'{}' must be a 16-bit word", key)),
+    }
+}
+
+/// Reads a table of register values, such as `{ R0 = 1, PC = "x3000" }`.
+fn registers(value: &Value, key: &str) -> Result<Vec<(Register, u16)>, String> {
+    table(value, key)?
+        .iter()
+        .map(|(register, value)| Ok((Register::parse(register)?, word(value, register)?)))
+        .collect()
+}
+
+/// Reads an array of memory blocks, each an address and its `words` or a zero-terminated
+/// `string`.
+fn memory(value: &Value, key: &str) -> Result<Vec<MemoryBlock>, String> {
+    let mut blocks = Vec::new();
+    for block in array(value, key)? {
+        let block = table(block, key)?;
+        check_keys(block, &["address", "words", "string"])?;
+        let address = word(
+            block.get("address").ok_or("memory needs an 'address'")?,
+            "address",
+        )?;
+        let words = match (block.get("words"), block.get("string")) {
+            (Some(words), None) => array(words, "words")?
+                .iter()
+                .map(|w| word(w, "words"))
+                .collect::<Result<_, _>>()?,
+            (None, Some(text)) => string(text, "string")?
+                .chars()
+                .map(|c| c as u16)
+                .chain([0])
+                .collect(),
+            _ => return Err("memory needs either 'words' or a 'string'".to_string()),
+        };
+        blocks.push(MemoryBlock { address, words });
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r#"
+        images = ["echo.obj"]
+        budget = 100
+
+        [[test]]
+        name = "echoes and prints"
+        stdin = "a"
+        registers = { R3 = "x0010" }
+        memory = [{ address = "x4000", words = [1, -1] }]
+        expect = { stdout = "aok", registers = { R0 = "x3005", R3 = 16 }, memory = [{ address = "x3005", string = "ok" }] }
+
+        [[test]]
+        name = "wrong output"
+        stdin = "b"
+        expect = { stdout = "aok", registers = { R1 = 1 } }
+
+        [[test]]
+        stdin = ""
+        budget = 3
+
+        [[test]]
+        name = "loops"
+        registers = { PC = "x3008" }
+        budget = 10
+        expect = { halted = false }
+    "#;
+
+    fn program() -> Vec<Image> {
+        let words = vec![
+            0xF020, // x3000 GETC
+            0xF021, // x3001 OUT
+            0xE002, // x3002 LEA R0, MSG
+            0xF022, // x3003 PUTS
+            0xF025, // x3004 HALT
+            0x006F, // x3005 MSG: "ok"
+            0x006B, // x3006
+            0x0000, // x3007
+            0x0FFF, // x3008 LOOP: BRnzp LOOP
+        ];
+        vec![Image::new("echo", 0x3000, words)]
+    }
+
+    fn run() -> Vec<CaseResult> {
+        let spec = TestSpec::parse("echo", SPEC).unwrap();
+        spec.cases
+            .iter()
+            .map(|case| case.run(&program(), &SymbolTable::new()))
+            .collect()
+    }
+
+    #[test]
+    fn spec_sets_initial_state_and_expectations() {
+        let spec = TestSpec::parse("echo", SPEC).unwrap();
+        assert_eq!(spec.name, "echo");
+        assert_eq!(spec.images, [PathBuf::from("echo.obj")]);
+        let case = &spec.cases[0];
+        assert_eq!(case.budget, 100);
+        assert_eq!(case.registers, [(Register::R3, 0x10)]);
+        assert_eq!(
+            case.memory,
+            [MemoryBlock {
+                address: 0x4000,
+                words: vec![1, 0xFFFF]
+            }]
+        );
+        assert_eq!(case.expect.memory[0].words, [0x6F, 0x6B, 0]);
+        assert_eq!(spec.cases[2].name, "test 3");
+        assert!(!spec.cases[3].expect.halted);
+    }
+
+    #[test]
+    fn invalid_specs_are_rejected() {
+        let error = TestSpec::parse("bad", "[[test]]\nname = \"x\"\nregisters = { R9 = 1 }\n");
+        assert!(error.unwrap_err().starts_with("test 'x': "));
+        let error = TestSpec::parse("bad", "[[test]]\nexpect = { stdot = \"\" }\n");
+        assert!(error.unwrap_err().contains("unknown key 'stdot'"));
+        assert!(TestSpec::parse("bad", "budget = 10\n").is_err());
+    }
+
+    #[test]
+    fn cases_check_output_registers_memory_and_halting() {
+        let results = run();
+        assert_eq!(results[0].outcome, Outcome::Passed);
+        assert_eq!(results[0].stdout, "aok");
+        assert_eq!(
+            results[1].outcome,
+            Outcome::Failed(vec![
+                "stdout: expected \"aok\", got \"bok\"".to_string(),
+                "R1: expected x0001, got x0000".to_string(),
+            ])
+        );
+        assert_eq!(results[3].outcome, Outcome::Passed);
+        assert!(matches!(&results[2].outcome, Outcome::Error(e) if e.starts_with("End of input")));
+    }
+
+    #[test]
+    fn results_are_reported_as_junit() {
+        let mut xml = Vec::new();
+        write_junit("echo", &run(), &mut xml).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.contains("<testsuite name=\"echo\" tests=\"4\" failures=\"1\" errors=\"1\""));
+        assert!(xml.contains(
+            "<failure message=\"stdout: expected &quot;aok&quot;, got &quot;bok&quot;\">"
+        ));
+        assert!(xml.contains("<system-out>bok</system-out>"));
+        assert_eq!(escape_xml("<\u{7}>"), "&lt;\u{FFFD}&gt;");
+    }
+
+    #[test]
+    fn report_summarizes_results() {
+        let mut report = Vec::new();
+        write_report(&run(), &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("test echoes and prints ... ok\ntest wrong output ... FAILED\n"));
+        assert!(report.ends_with("test result: FAILED. 2 passed; 1 failed; 1 errors\n"));
+    }
+}
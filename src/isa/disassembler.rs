@@ -0,0 +1,120 @@
+use crate::symbols::SymbolTable;
+use crate::utils::sign_extend;
+
+/// Disassembles a single instruction into LC-3 assembly.
+///
+/// PC-relative operands are shown as the label defined at the target address, if any,
+/// and as the target address (e.g. `x3005`) otherwise. Words that are not valid
+/// instructions are shown as `.FILL` directives.
+///
+/// # Parameters
+///
+/// - `instr`: The instruction to disassemble.
+/// - `address`: The address the instruction is stored at.
+/// - `symbols`: Labels used to name the targets of PC-relative operands.
+///
+/// # Returns
+///
+/// The instruction in assembly syntax, e.g. `ADD R1, R2, #-3` or `BRnz LOOP`.
+pub fn disassemble(instr: u16, address: u16, symbols: &SymbolTable) -> String {
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    let target = |bits: usize| {
+        let target = address
+            .wrapping_add(1)
+            .wrapping_add(sign_extend(instr & ((1 << bits) - 1), bits));
+        symbols
+            .label(target)
+            .map_or_else(|| format!("x{:04X}", target), str::to_string)
+    };
+    let imm = |bits: usize| sign_extend(instr & ((1 << bits) - 1), bits) as i16;
+    let fill = || format!(".FILL x{:04X}", instr);
+    match instr >> 12 {
+        0x0 => {
+            let flags: String = [(0x800, 'n'), (0x400, 'z'), (0x200, 'p')]
+                .iter()
+                .filter(|(bit, _)| instr & bit != 0)
+                .map(|(_, flag)| *flag)
+                .collect();
+            if flags.is_empty() {
+                "NOP".to_string()
+            } else if flags == "nzp" {
+                format!("BR {}", target(9))
+            } else {
+                format!("BR{} {}", flags, target(9))
+            }
+        }
+        0x1 | 0x5 => {
+            let name = if instr >> 12 == 0x1 { "ADD" } else { "AND" };
+            if instr & 0x20 != 0 {
+                format!("{} R{}, R{}, #{}", name, dr, sr1, imm(5))
+            } else if instr & 0x18 == 0 {
+                format!("{} R{}, R{}, R{}", name, dr, sr1, instr & 0x7)
+            } else {
+                fill()
+            }
+        }
+        0x2 => format!("LD R{}, {}", dr, target(9)),
+        0x3 => format!("ST R{}, {}", dr, target(9)),
+        0x4 if instr & 0x800 != 0 => format!("JSR {}", target(11)),
+        0x4 if instr & 0x63F == 0 => format!("JSRR R{}", sr1),
+        0x6 => format!("LDR R{}, R{}, #{}", dr, sr1, imm(6)),
+        0x7 => format!("STR R{}, R{}, #{}", dr, sr1, imm(6)),
+        0x8 if instr & 0xFFF == 0 => "RTI".to_string(),
+        0x9 if instr & 0x3F == 0x3F => format!("NOT R{}, R{}", dr, sr1),
+        0xA => format!("LDI R{}, {}", dr, target(9)),
+        0xB => format!("STI R{}, {}", dr, target(9)),
+        0xC if instr == 0xC1C0 => "RET".to_string(),
+        0xC if instr & 0xE3F == 0 => format!("JMP R{}", sr1),
+        0xE => format!("LEA R{}, {}", dr, target(9)),
+        0xF if instr & 0xF00 == 0 => match instr & 0xFF {
+            0x20 => "GETC".to_string(),
+            0x21 => "OUT".to_string(),
+            0x22 => "PUTS".to_string(),
+            0x23 => "IN".to_string(),
+            0x24 => "PUTSP".to_string(),
+            0x25 => "HALT".to_string(),
+            vector => format!("TRAP x{:02X}", vector),
+        },
+        _ => fill(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operate_instructions() {
+        let symbols = SymbolTable::new();
+        assert_eq!(disassemble(0x1283, 0x3000, &symbols), "ADD R1, R2, R3");
+        assert_eq!(disassemble(0x12BD, 0x3000, &symbols), "ADD R1, R2, #-3");
+        assert_eq!(disassemble(0x5020, 0x3000, &symbols), "AND R0, R0, #0");
+        assert_eq!(disassemble(0x927F, 0x3000, &symbols), "NOT R1, R1");
+        assert_eq!(disassemble(0x1288, 0x3000, &symbols), ".FILL x1288");
+    }
+
+    #[test]
+    fn pc_relative_operands_use_labels() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("LOOP", 0x3000);
+        assert_eq!(disassemble(0x0BFD, 0x3002, &symbols), "BRnp LOOP");
+        assert_eq!(disassemble(0x0FFD, 0x3002, &symbols), "BR LOOP");
+        assert_eq!(disassemble(0x2002, 0x3002, &symbols), "LD R0, x3005");
+        assert_eq!(disassemble(0x4FFD, 0x3002, &symbols), "JSR LOOP");
+        assert_eq!(disassemble(0x0000, 0x3002, &symbols), "NOP");
+    }
+
+    #[test]
+    fn control_instructions() {
+        let symbols = SymbolTable::new();
+        assert_eq!(disassemble(0xC1C0, 0x3000, &symbols), "RET");
+        assert_eq!(disassemble(0xC080, 0x3000, &symbols), "JMP R2");
+        assert_eq!(disassemble(0x4080, 0x3000, &symbols), "JSRR R2");
+        assert_eq!(disassemble(0x6283, 0x3000, &symbols), "LDR R1, R2, #3");
+        assert_eq!(disassemble(0x8000, 0x3000, &symbols), "RTI");
+        assert_eq!(disassemble(0xF025, 0x3000, &symbols), "HALT");
+        assert_eq!(disassemble(0xF026, 0x3000, &symbols), "TRAP x26");
+        assert_eq!(disassemble(0xD000, 0x3000, &symbols), ".FILL xD000");
+    }
+}
@@ -72,6 +72,13 @@ pub fn branch(registers: &mut Registers, instr: u16) {
     }
 }
 
+/// Checks whether an instruction is a conditional branch: `BR` with one or two of the
+/// n, z and p conditions. `BRnzp` always jumps and a `BR` without conditions never does,
+/// so they have a single outcome.
+pub fn is_conditional_branch(instr: u16) -> bool {
+    instr >> 12 == 0 && !matches!((instr >> 9) & 0x7, 0b000 | 0b111)
+}
+
 /// Executes the ADD instruction.
 ///
 /// This function performs integer addition. It can either add an immediate value or the value of a register.
@@ -156,7 +163,7 @@ pub fn store(registers: &mut Registers, memory: &mut Memory, instr: u16) {
 /// Executes the JSR (jump to subroutine) instruction.
 ///
 /// This function saves the current PC to R7 and then jumps to a new address. It can either use a PC-relative
-/// offset or a register-indirect jump.
+/// offset or a register-indirect jump; `JSRR R7` jumps to the address R7 held before the call.
 ///
 /// # Parameters
 /// - `registers`: A mutable reference to the `Registers` object.
@@ -168,20 +175,19 @@ pub fn store(registers: &mut Registers, memory: &mut Memory, instr: u16) {
 /// - **Base Register (JSRR)**: Bits 6-8 (if `long_flag` is 0)
 pub fn jump_to_subroutine(registers: &mut Registers, instr: u16) {
     let current_pc = registers.read(Register::PC);
-    registers.write(Register::R7, current_pc);
 
     let long_flag = (instr >> 11) & 0x1;
-    if long_flag == 1 {
+    let new_pc = if long_flag == 1 {
         // JSR: Use PC-relative offset
         let long_pc_offset = sign_extend(instr & 0x7FF, 11);
-        let new_pc = current_pc.wrapping_add(long_pc_offset);
-        registers.write(Register::PC, new_pc);
+        current_pc.wrapping_add(long_pc_offset)
     } else {
-        // JSRR: Use register-indirect jump
+        // JSRR: Use register-indirect jump, reading the base register before R7 is written
         let r1 = (instr >> 6) & 0x7;
-        let new_pc = registers.read(Register::from(r1));
-        registers.write(Register::PC, new_pc);
-    }
+        registers.read(Register::from(r1))
+    };
+    registers.write(Register::R7, current_pc);
+    registers.write(Register::PC, new_pc);
 }
 
 /// Executes the AND instruction.
@@ -598,6 +604,20 @@ mod tests {
         assert_eq!(registers.read(Register::R7), PC_START);
     }
 
+    #[test]
+    fn jump_to_subroutine_register_indirect_through_r7() {
+        let mut registers = Registers::new();
+        registers.write(Register::R7, 0x4000);
+
+        // Full instruction: JSRR (Opcode = 0b0100), Long Flag = 0, Base Register = R7
+        let instr = 0b0100_000_111_000000;
+        jump_to_subroutine(&mut registers, instr);
+
+        // PC should be updated to the value R7 held before the call
+        assert_eq!(registers.read(Register::PC), 0x4000);
+        assert_eq!(registers.read(Register::R7), PC_START);
+    }
+
     #[test]
     fn jump_to_subroutine_register_indirect() {
         let mut registers = Registers::new();
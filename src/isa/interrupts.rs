@@ -0,0 +1,142 @@
+use crate::hardware::{memory::Memory, registers::*};
+
+/// Base address of the interrupt vector table. The handler for vector `v` is stored at `x0100 + v`.
+pub const INTERRUPT_VECTOR_TABLE: u16 = 0x0100;
+
+/// An interrupt request raised by a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupt {
+    /// Index into the interrupt vector table.
+    pub vector: u8,
+
+    /// Priority level of the request (0 to 7). The request is only serviced if its
+    /// priority is higher than the priority of the running program.
+    pub priority: u8,
+}
+
+/// Initiates an interrupt.
+///
+/// This function switches to the supervisor stack (if running in user mode), pushes the PSR and PC,
+/// raises the priority level to that of the request and jumps to the handler found in the
+/// interrupt vector table.
+///
+/// # Parameters
+/// - `registers`: A mutable reference to the `Registers` object.
+/// - `memory`: A mutable reference to the `Memory` object.
+/// - `interrupt`: The interrupt request to service.
+pub fn interrupt(
+    registers: &mut Registers,
+    memory: &mut Memory,
+    interrupt: Interrupt,
+) -> Result<(), String> {
+    let psr = registers.psr();
+    let pc = registers.read(Register::PC);
+    if registers.is_user_mode() {
+        registers.swap_stack_pointers(true);
+    }
+    push(registers, memory, psr);
+    push(registers, memory, pc);
+
+    registers.set_psr(((interrupt.priority as u16 & 0x7) << 8) | (psr & 0x7));
+    let handler = memory.read(INTERRUPT_VECTOR_TABLE + interrupt.vector as u16)?;
+    registers.write(Register::PC, handler);
+    Ok(())
+}
+
+/// Executes the RTI (return from interrupt) instruction.
+///
+/// This function pops the PC and PSR saved by `interrupt` and switches back to the user stack
+/// if the restored PSR is in user mode.
+///
+/// # Parameters
+/// - `registers`: A mutable reference to the `Registers` object.
+/// - `memory`: A mutable reference to the `Memory` object.
+///
+/// # Errors
+///
+/// Returns a `String` error if RTI is executed in user mode.
+pub fn return_from_interrupt(registers: &mut Registers, memory: &mut Memory) -> Result<(), String> {
+    if registers.is_user_mode() {
+        return Err("Privilege mode violation: RTI executed in user mode".to_string());
+    }
+    let pc = pop(registers, memory)?;
+    let psr = pop(registers, memory)?;
+    registers.write(Register::PC, pc);
+    registers.set_psr(psr);
+    if registers.is_user_mode() {
+        registers.swap_stack_pointers(false);
+    }
+    Ok(())
+}
+
+/// Pushes a value onto the stack pointed to by R6.
+fn push(registers: &mut Registers, memory: &mut Memory, value: u16) {
+    let sp = registers.read(Register::R6).wrapping_sub(1);
+    registers.write(Register::R6, sp);
+    memory.write(sp, value);
+}
+
+/// Pops a value from the stack pointed to by R6.
+fn pop(registers: &mut Registers, memory: &mut Memory) -> Result<u16, String> {
+    let sp = registers.read(Register::R6);
+    let value = memory.read(sp)?;
+    registers.write(Register::R6, sp.wrapping_add(1));
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::flags::Flag;
+
+    #[test]
+    fn interrupt_switches_to_supervisor_stack_and_jumps_to_handler() {
+        let mut registers = Registers::new();
+        let mut memory = Memory::new();
+        registers.write(Register::R6, 0xF000);
+        registers.write(Register::COND, Flag::POS as u16);
+        memory.write(INTERRUPT_VECTOR_TABLE + 0x81, 0x1000);
+
+        let irq = Interrupt {
+            vector: 0x81,
+            priority: 4,
+        };
+        interrupt(&mut registers, &mut memory, irq).unwrap();
+
+        assert_eq!(registers.read(Register::PC), 0x1000);
+        assert_eq!(registers.read(Register::R6), SSP_START - 2);
+        assert_eq!(memory.read(SSP_START - 1).unwrap(), PSR_USER_MODE | 1);
+        assert_eq!(memory.read(SSP_START - 2).unwrap(), PC_START);
+        assert!(!registers.is_user_mode());
+        assert_eq!(registers.priority(), 4);
+    }
+
+    #[test]
+    fn return_from_interrupt_restores_user_state() {
+        let mut registers = Registers::new();
+        let mut memory = Memory::new();
+        registers.write(Register::R6, 0xF000);
+        registers.write(Register::COND, Flag::NEG as u16);
+        let irq = Interrupt {
+            vector: 0x80,
+            priority: 1,
+        };
+        interrupt(&mut registers, &mut memory, irq).unwrap();
+        registers.write(Register::COND, Flag::ZRO as u16);
+
+        return_from_interrupt(&mut registers, &mut memory).unwrap();
+
+        assert_eq!(registers.read(Register::PC), PC_START);
+        assert_eq!(registers.read(Register::R6), 0xF000);
+        assert_eq!(registers.read(Register::COND), Flag::NEG as u16);
+        assert!(registers.is_user_mode());
+        assert_eq!(registers.priority(), 0);
+    }
+
+    #[test]
+    fn return_from_interrupt_in_user_mode_fails() {
+        let mut registers = Registers::new();
+        let mut memory = Memory::new();
+        assert!(return_from_interrupt(&mut registers, &mut memory).is_err());
+    }
+}
@@ -1,8 +1,21 @@
+/// This module translates machine words back into LC-3 assembly, naming the targets of PC-relative
+/// operands with the labels of a symbol table.
+pub mod disassembler;
+
 /// This module contains the definition of LC-3 opcodes and functions that implement the various instructions
 /// supported by the LC-3 architecture. It provides functionality for executing each instruction, including
 /// branching, arithmetic operations, memory access, and control flow instructions.
 pub mod instructions;
 
+/// This module contains the interrupt mechanism of the LC-3 architecture: raising interrupts from devices,
+/// switching to the supervisor stack and returning from interrupt handlers with RTI.
+pub mod interrupts;
+
+/// This module contains property-based tests of every opcode, checking invariants such as one-hot
+/// condition codes, sign extension of the offsets and wraparound addressing over random states.
+#[cfg(test)]
+mod properties;
+
 /// This module contains definitions and implementations for the LC-3 trap codes and related functionality,
 /// including input/output operations and program control.
 pub mod traps;
@@ -0,0 +1,393 @@
+use crate::hardware::display::Display;
+use crate::hardware::flags::Flag;
+use crate::hardware::keyboard::Keyboard;
+use crate::hardware::registers::*;
+use crate::hardware::rng::Rng;
+use crate::loader::Image;
+use crate::utils::sign_extend;
+use crate::vm::{Fill, VM};
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+
+/// Opcodes of the instructions that set the condition codes: ADD, LD, AND, LDR, NOT, LDI, LEA.
+const FLAG_SETTING: [u16; 7] = [0x1, 0x2, 0x5, 0x6, 0x9, 0xA, 0xE];
+
+/// Opcodes of the instructions that keep the condition codes: BR, ST, JSR, STR, STI, JMP.
+const FLAG_KEEPING: [u16; 6] = [0x0, 0x3, 0x4, 0x7, 0xB, 0xC];
+
+/// Registers and memory an instruction is executed on.
+#[derive(Debug, Clone)]
+struct State {
+    /// R0 to R7.
+    registers: [u16; 8],
+    /// One-hot condition codes.
+    cond: u16,
+    /// Address the instruction is fetched from.
+    pc: u16,
+    /// Seed of the random memory contents.
+    seed: u64,
+}
+
+impl State {
+    /// Builds a VM in supervisor mode, with random memory, the registers and `instr` at the PC.
+    /// Console output is captured.
+    fn machine(&self, instr: u16) -> VM {
+        let mut vm = VM::new();
+        vm.fill_memory(Fill::Random, &mut Rng::new(self.seed));
+        vm.load_image(&Image::new("property", self.pc, vec![instr]));
+        let registers = vm.registers_mut();
+        registers.set_psr(self.cond);
+        for (register, value) in self.registers.iter().enumerate() {
+            registers.write(Register::from(register as u16), *value);
+        }
+        registers.write(Register::PC, self.pc);
+        if let Some(display) = vm.device_mut::<Display>() {
+            display.capture();
+        }
+        vm
+    }
+
+    /// Addresses an instruction reads or writes data at, once loaded into `vm`.
+    fn data_addresses(&self, vm: &VM, instr: u16) -> Vec<u16> {
+        let next = self.pc.wrapping_add(1);
+        let base = self.registers[((instr >> 6) & 0x7) as usize];
+        let sp = self.registers[6];
+        match instr >> 12 {
+            0x2 | 0x3 => vec![next.wrapping_add(sext(instr, 9))],
+            0x6 | 0x7 => vec![base.wrapping_add(sext(instr, 6))],
+            0x8 => vec![sp, sp.wrapping_add(1)],
+            0xA | 0xB => {
+                let pointer = next.wrapping_add(sext(instr, 9));
+                vec![pointer, vm.peek(pointer)]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Executes `instr` at the PC as the first and only step of the VM.
+    ///
+    /// Cases where the instruction is fetched from or accesses data at a device register are
+    /// rejected, so that the expected values can be computed from RAM.
+    ///
+    /// # Returns
+    ///
+    /// The VM and the result of `VM::run_for`: whether the VM halted, or the fault.
+    fn step(&self, instr: u16) -> Result<(VM, Result<bool, String>), TestCaseError> {
+        let mut vm = self.machine(instr);
+        let mut addresses = self.data_addresses(&vm, instr);
+        addresses.push(self.pc);
+        prop_assume!(!addresses.iter().any(|a| vm.is_mapped(*a)));
+        let result = vm.run_for(1);
+        Ok((vm, result))
+    }
+}
+
+/// Addresses and register values, often close to either end of memory so that addressing
+/// wraps around.
+fn word() -> impl Strategy<Value = u16> {
+    prop_oneof![any::<u16>(), 0xFF00u16..=0xFFFF, 0x0000u16..0x0100]
+}
+
+fn state() -> impl Strategy<Value = State> {
+    let cond = prop::sample::select(vec![Flag::NEG as u16, Flag::ZRO as u16, Flag::POS as u16]);
+    (prop::array::uniform8(word()), cond, word(), any::<u64>()).prop_map(
+        |(registers, cond, pc, seed)| State {
+            registers,
+            cond,
+            pc,
+            seed,
+        },
+    )
+}
+
+/// Instructions with one of the given opcodes and random operands.
+fn instruction(opcodes: &[u16]) -> impl Strategy<Value = u16> {
+    (prop::sample::select(opcodes.to_vec()), 0u16..0x1000)
+        .prop_map(|(opcode, operands)| opcode << 12 | operands)
+}
+
+/// Sign-extends the low `bits` bits of an instruction with signed arithmetic.
+fn sext(instr: u16, bits: u32) -> u16 {
+    let shift = 16 - bits;
+    (((instr << shift) as i16) >> shift) as u16
+}
+
+/// Condition codes set by a value.
+fn flags(value: u16) -> u16 {
+    match value as i16 {
+        0 => Flag::ZRO as u16,
+        v if v < 0 => Flag::NEG as u16,
+        _ => Flag::POS as u16,
+    }
+}
+
+/// R0 to R7.
+fn general(registers: &Registers) -> [u16; 8] {
+    std::array::from_fn(|r| registers.read(Register::from(r as u16)))
+}
+
+/// Addresses whose contents differ between two machines.
+fn written(before: &VM, after: &VM) -> Vec<u16> {
+    (0..=u16::MAX)
+        .filter(|a| before.peek(*a) != after.peek(*a))
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn sign_extension_keeps_the_signed_value(bits in 1usize..16, raw: u16) {
+        let value = raw & ((1 << bits) - 1);
+        let expected = if value >> (bits - 1) == 1 {
+            value as i32 - (1 << bits)
+        } else {
+            value as i32
+        };
+        prop_assert_eq!(sign_extend(value, bits) as i16 as i32, expected);
+    }
+
+    #[test]
+    fn flag_setting_instructions_leave_one_hot_condition_codes(
+        state in state(),
+        instr in instruction(&FLAG_SETTING),
+    ) {
+        let (vm, result) = state.step(instr)?;
+        prop_assert_eq!(result, Ok(false));
+        let cond = vm.registers().read(Register::COND);
+        prop_assert_eq!(cond.count_ones(), 1);
+        let dr = vm.registers().read(Register::from((instr >> 9) & 0x7));
+        prop_assert_eq!(cond, flags(dr));
+    }
+
+    #[test]
+    fn other_instructions_keep_condition_codes(
+        state in state(),
+        instr in instruction(&FLAG_KEEPING),
+    ) {
+        let (vm, result) = state.step(instr)?;
+        prop_assert_eq!(result, Ok(false));
+        prop_assert_eq!(vm.registers().read(Register::COND), state.cond);
+    }
+
+    #[test]
+    fn operate_instructions_use_twos_complement(
+        state in state(),
+        instr in instruction(&[0x1, 0x5, 0x9]),
+    ) {
+        let (vm, result) = state.step(instr)?;
+        prop_assert_eq!(result, Ok(false));
+        let sr1 = state.registers[((instr >> 6) & 0x7) as usize];
+        let sr2 = if instr & (1 << 5) != 0 {
+            sext(instr, 5)
+        } else {
+            state.registers[(instr & 0x7) as usize]
+        };
+        let expected = match instr >> 12 {
+            0x1 => (sr1 as i16).wrapping_add(sr2 as i16) as u16,
+            0x5 => sr1 & sr2,
+            _ => !sr1,
+        };
+        prop_assert_eq!(vm.registers().read(Register::from((instr >> 9) & 0x7)), expected);
+        prop_assert_eq!(vm.registers().read(Register::PC), state.pc.wrapping_add(1));
+    }
+
+    #[test]
+    fn pc_relative_addresses_wrap_around(
+        state in state(),
+        instr in instruction(&[0x0, 0x2, 0x3, 0x4, 0xA, 0xB, 0xE]),
+    ) {
+        // JSR with an 11-bit offset rather than JSRR
+        let instr = if instr >> 12 == 0x4 { instr | 1 << 11 } else { instr };
+        let initial = state.machine(instr);
+        let (vm, result) = state.step(instr)?;
+        prop_assert_eq!(result, Ok(false));
+
+        let next = state.pc.wrapping_add(1);
+        let offset = if instr >> 12 == 0x4 { sext(instr, 11) } else { sext(instr, 9) };
+        let target = next.wrapping_add(offset);
+        let r = ((instr >> 9) & 0x7) as usize;
+        let dr = vm.registers().read(Register::from(r as u16));
+        match instr >> 12 {
+            0x0 => {
+                let taken = (instr >> 9) & state.cond != 0;
+                let expected = if taken { target } else { next };
+                prop_assert_eq!(vm.registers().read(Register::PC), expected);
+            }
+            0x2 => prop_assert_eq!(dr, initial.peek(target)),
+            0x3 => prop_assert_eq!(vm.peek(target), state.registers[r]),
+            0x4 => {
+                prop_assert_eq!(vm.registers().read(Register::PC), target);
+                prop_assert_eq!(vm.registers().read(Register::R7), next);
+            }
+            0xA => prop_assert_eq!(dr, initial.peek(initial.peek(target))),
+            0xB => prop_assert_eq!(vm.peek(initial.peek(target)), state.registers[r]),
+            _ => prop_assert_eq!(dr, target),
+        }
+    }
+
+    #[test]
+    fn base_offset_addresses_wrap_around(state in state(), instr in instruction(&[0x6, 0x7])) {
+        let initial = state.machine(instr);
+        let (vm, result) = state.step(instr)?;
+        prop_assert_eq!(result, Ok(false));
+
+        let base = state.registers[((instr >> 6) & 0x7) as usize];
+        let address = base.wrapping_add(sext(instr, 6));
+        let r = ((instr >> 9) & 0x7) as usize;
+        if instr >> 12 == 0x6 {
+            prop_assert_eq!(vm.registers().read(Register::from(r as u16)), initial.peek(address));
+        } else {
+            prop_assert_eq!(vm.peek(address), state.registers[r]);
+        }
+    }
+
+    #[test]
+    fn jumps_read_the_base_register_before_writing_r7(
+        state in state(),
+        instr in instruction(&[0x4, 0xC]),
+    ) {
+        // JSRR rather than JSR
+        let instr = instr & !(1 << 11);
+        let (vm, result) = state.step(instr)?;
+        prop_assert_eq!(result, Ok(false));
+
+        let base = state.registers[((instr >> 6) & 0x7) as usize];
+        prop_assert_eq!(vm.registers().read(Register::PC), base);
+        let r7 = if instr >> 12 == 0x4 { state.pc.wrapping_add(1) } else { state.registers[7] };
+        prop_assert_eq!(vm.registers().read(Register::R7), r7);
+    }
+
+    #[test]
+    fn subroutine_calls_push_a_frame_and_returns_pop_it(
+        state in state(),
+        instr in instruction(&[0x4, 0xC]),
+    ) {
+        let (vm, result) = state.step(instr)?;
+        prop_assert_eq!(result, Ok(false));
+        let frames = vm.call_stack().frames();
+        if instr >> 12 == 0x4 {
+            prop_assert_eq!(frames.len(), 1);
+            prop_assert_eq!(frames[0].call_site, state.pc);
+            prop_assert_eq!(frames[0].subroutine, vm.registers().read(Register::PC));
+        } else {
+            prop_assert!(frames.is_empty());
+        }
+    }
+
+    #[test]
+    fn instructions_write_nothing_but_their_destination(
+        state in state(),
+        instr in instruction(&[0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x9, 0xA, 0xB, 0xC, 0xE]),
+    ) {
+        let initial = state.machine(instr);
+        let (vm, result) = state.step(instr)?;
+        prop_assert_eq!(result, Ok(false));
+
+        let writes = written(&initial, &vm);
+        if matches!(instr >> 12, 0x3 | 0x7 | 0xB) {
+            prop_assert!(writes.len() <= 1, "{:04X?}", writes);
+        } else {
+            prop_assert!(writes.is_empty(), "{:04X?}", writes);
+        }
+        let changed: Vec<usize> = (0..8)
+            .filter(|r| general(vm.registers())[*r] != state.registers[*r])
+            .collect();
+        let allowed = match instr >> 12 {
+            0x1 | 0x2 | 0x5 | 0x6 | 0x9 | 0xA | 0xE => Some(((instr >> 9) & 0x7) as usize),
+            0x4 => Some(7),
+            _ => None,
+        };
+        prop_assert!(changed.iter().all(|r| Some(*r) == allowed), "{:?}", changed);
+    }
+
+    #[test]
+    fn rti_pops_pc_and_psr_in_supervisor_mode(
+        state in state(),
+        pc: u16,
+        priority in 0u16..8,
+        user: bool,
+    ) {
+        let sp = state.registers[6];
+        let psr = priority << 8 | state.cond;
+        let mut vm = state.machine(0x8000);
+        prop_assume!(sp != state.pc && sp.wrapping_add(1) != state.pc);
+        prop_assume!(!vm.is_mapped(state.pc) && !vm.is_mapped(sp) && !vm.is_mapped(sp.wrapping_add(1)));
+        vm.load_image(&Image::new("stack", sp, vec![pc, psr]));
+        if user {
+            vm.registers_mut().set_psr(PSR_USER_MODE | state.cond);
+            prop_assert!(vm.run_for(1).is_err());
+            prop_assert_eq!(general(vm.registers()), state.registers);
+            prop_assert_eq!(vm.registers().read(Register::COND), state.cond);
+        } else {
+            prop_assert_eq!(vm.run_for(1), Ok(false));
+            prop_assert_eq!(vm.registers().read(Register::PC), pc);
+            prop_assert_eq!(vm.registers().psr(), psr);
+            prop_assert_eq!(vm.registers().read(Register::R6), sp.wrapping_add(2));
+        }
+    }
+
+    #[test]
+    fn traps_return_to_the_next_instruction(
+        state in state(),
+        string in 0u16..0xFDF8,
+        vector in 0x20u16..=0x25,
+        input in prop::collection::vec(any::<u8>(), 1..4),
+    ) {
+        // The strings of PUTS and PUTSP start at R0 and are terminated within RAM
+        let mut state = state;
+        state.registers[0] = string;
+        let terminator = string + 8;
+        prop_assume!(state.pc != terminator);
+        let mut vm = state.machine(0xF000 | vector);
+        prop_assume!(!vm.is_mapped(state.pc));
+        vm.load_image(&Image::new("terminator", terminator, vec![0]));
+        if let Some(keyboard) = vm.device_mut::<Keyboard>() {
+            keyboard.set_input(&input);
+        }
+
+        prop_assert_eq!(vm.run_for(1), Ok(vector == 0x25));
+        let next = state.pc.wrapping_add(1);
+        prop_assert_eq!(vm.registers().read(Register::PC), next);
+        prop_assert_eq!(vm.registers().read(Register::R7), next);
+        prop_assert!(vm.call_stack().frames().is_empty());
+        let r0 = vm.registers().read(Register::R0);
+        if vector == 0x20 || vector == 0x23 {
+            prop_assert_eq!(r0, input[0] as u16);
+            prop_assert_eq!(vm.registers().read(Register::COND), flags(r0));
+        } else {
+            prop_assert_eq!(r0, string);
+            prop_assert_eq!(vm.registers().read(Register::COND), state.cond);
+        }
+        let output = vm.device::<Display>().unwrap().output().to_string();
+        if vector == 0x21 {
+            prop_assert_eq!(output, char::from(r0 as u8).to_string());
+        }
+    }
+
+    #[test]
+    fn unknown_trap_vectors_fault_without_side_effects(
+        state in state(),
+        vector in any::<u8>().prop_filter("valid trap vector", |v| !(0x20..=0x25).contains(v)),
+    ) {
+        let (vm, result) = state.step(0xF000 | vector as u16)?;
+        prop_assert_eq!(
+            result,
+            Err(format!("Unknown trap vector x{:02X} at x{:04X}", vector, state.pc))
+        );
+        prop_assert_eq!(general(vm.registers()), state.registers);
+        prop_assert_eq!(vm.registers().read(Register::COND), state.cond);
+    }
+
+    #[test]
+    fn reserved_opcode_faults_without_side_effects(
+        state in state(),
+        instr in instruction(&[0xD]),
+    ) {
+        let (vm, result) = state.step(instr)?;
+        prop_assert_eq!(
+            result,
+            Err(format!("Illegal opcode x{:04X} at x{:04X}", instr, state.pc))
+        );
+        prop_assert_eq!(general(vm.registers()), state.registers);
+        prop_assert_eq!(vm.registers().read(Register::COND), state.cond);
+    }
+}
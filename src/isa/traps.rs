@@ -1,5 +1,8 @@
+use crate::hardware::display::Display;
+use crate::hardware::keyboard::Keyboard;
 use crate::hardware::{memory::Memory, registers::*};
-use std::io::{self, Read, Write};
+use crate::utils;
+use std::io::{self, Write};
 
 /// Represents LC-3 trap codes.
 pub enum Trapcode {
@@ -22,21 +25,24 @@ pub enum Trapcode {
     HALT = 0x25,
 }
 
-impl From<u16> for Trapcode {
+impl TryFrom<u16> for Trapcode {
+    type Error = String;
+
     /// Converts a 16-bit unsigned integer into a `Trapcode` enum variant.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the value is not a valid trap code (0x20 to 0x25).
-    fn from(value: u16) -> Self {
+    /// Returns a `String` error if the value is not a valid trap code (0x20 to 0x25), as
+    /// the VM has no trap routines in memory to jump to.
+    fn try_from(value: u16) -> Result<Self, String> {
         match value {
-            0x20 => Trapcode::GETC,
-            0x21 => Trapcode::OUT,
-            0x22 => Trapcode::PUTS,
-            0x23 => Trapcode::IN,
-            0x24 => Trapcode::PUTSP,
-            0x25 => Trapcode::HALT,
-            _ => panic!("Invalid trap code value"),
+            0x20 => Ok(Trapcode::GETC),
+            0x21 => Ok(Trapcode::OUT),
+            0x22 => Ok(Trapcode::PUTS),
+            0x23 => Ok(Trapcode::IN),
+            0x24 => Ok(Trapcode::PUTSP),
+            0x25 => Ok(Trapcode::HALT),
+            _ => Err(format!("Unknown trap vector x{:02X}", value)),
         }
     }
 }
@@ -50,38 +56,66 @@ impl From<u16> for Trapcode {
 /// - `memory`: A mutable reference to the `Memory` object.
 /// - `instr`: The full instruction including the trap code.
 /// - `running`: A mutable reference to the running status of the program.
+///
+/// # Errors
+///
+/// Returns a `String` error for an unknown trap vector or if the trap routine fails.
 pub fn execute(
     registers: &mut Registers,
     memory: &mut Memory,
     instr: u16,
     running: &mut bool,
 ) -> Result<(), String> {
+    let trapcode = Trapcode::try_from(instr & 0xFF)?;
     let pc = registers.read(Register::PC);
     registers.write(Register::R7, pc);
 
-    match Trapcode::from(instr & 0xFF) {
-        Trapcode::GETC => getc(registers)?,
-        Trapcode::OUT => out(registers)?,
+    match trapcode {
+        Trapcode::GETC => getc(registers, memory)?,
+        Trapcode::OUT => out(registers, memory)?,
         Trapcode::PUTS => puts(registers, memory)?,
-        Trapcode::IN => in_(registers)?,
+        Trapcode::IN => in_(registers, memory)?,
         Trapcode::PUTSP => putsp(registers, memory)?,
-        Trapcode::HALT => halt(running)?,
+        Trapcode::HALT => halt(memory, running)?,
     }
     Ok(())
 }
 
+/// Reads a character through the keyboard device, or from standard input if there is none.
+///
+/// # Parameters
+/// - `memory`: A mutable reference to the `Memory` object, whose bus holds the devices.
+fn getchar(memory: &mut Memory) -> Result<u16, String> {
+    match memory.bus_mut().device_mut::<Keyboard>() {
+        Some(keyboard) => keyboard.getchar(),
+        None => utils::getchar(),
+    }
+}
+
+/// Writes text through the display device, or to standard output if there is none.
+///
+/// # Parameters
+/// - `memory`: A mutable reference to the `Memory` object, whose bus holds the devices.
+/// - `text`: The text to write.
+fn write(memory: &mut Memory, text: &str) -> Result<(), String> {
+    match memory.bus_mut().device_mut::<Display>() {
+        Some(display) => display.write_str(text),
+        None => {
+            print!("{}", text);
+            io::stdout().flush().map_err(|e| e.to_string())
+        }
+    }
+}
+
 /// Executes the GETC trap code.
 ///
 /// This function reads a character from the keyboard (not echoed) and stores it in register R0.
 ///
 /// # Parameters
 /// - `registers`: A mutable reference to the `Registers` object.
-fn getc(registers: &mut Registers) -> Result<(), String> {
-    let ch = io::stdin()
-        .bytes()
-        .next()
-        .ok_or("Failed to read byte")?
-        .map_err(|e| e.to_string())? as u16;
+/// - `memory`: A mutable reference to the `Memory` object.
+fn getc(registers: &mut Registers, memory: &mut Memory) -> Result<(), String> {
+    let ch = getchar(memory)?;
     registers.write(Register::R0, ch);
     registers.update_flags(Register::R0);
     Ok(())
@@ -93,10 +127,10 @@ fn getc(registers: &mut Registers) -> Result<(), String> {
 ///
 /// # Parameters
 /// - `registers`: A reference to the `Registers` object.
-fn out(registers: &Registers) -> Result<(), String> {
+/// - `memory`: A mutable reference to the `Memory` object.
+fn out(registers: &Registers, memory: &mut Memory) -> Result<(), String> {
     let ch = char::from((registers.read(Register::R0) & 0xFF) as u8);
-    print!("{}", ch);
-    io::stdout().flush().map_err(|e| e.to_string())
+    write(memory, &ch.to_string())
 }
 
 /// Executes the PUTS trap code.
@@ -106,17 +140,21 @@ fn out(registers: &Registers) -> Result<(), String> {
 /// # Parameters
 /// - `registers`: A reference to the `Registers` object.
 /// - `memory`: A mutable reference to the `Memory` object.
+///
+/// # Errors
+///
+/// Returns a `String` error if no word of memory terminates the string.
 fn puts(registers: &Registers, memory: &mut Memory) -> Result<(), String> {
-    let mut address = registers.read(Register::R0);
-    loop {
-        let word = memory.read(address)?;
+    let start = registers.read(Register::R0);
+    let mut text = String::new();
+    for offset in 0..=u16::MAX {
+        let word = memory.read(start.wrapping_add(offset))?;
         if word == 0 {
-            break;
+            return write(memory, &text);
         }
-        print!("{}", char::from((word & 0xFF) as u8));
-        address = address.wrapping_add(1);
+        text.push(char::from((word & 0xFF) as u8));
     }
-    io::stdout().flush().map_err(|e| e.to_string())
+    Err(unterminated("PUTS", start))
 }
 
 /// Executes the IN trap code.
@@ -125,16 +163,11 @@ fn puts(registers: &Registers, memory: &mut Memory) -> Result<(), String> {
 ///
 /// # Parameters
 /// - `registers`: A mutable reference to the `Registers` object.
-fn in_(registers: &mut Registers) -> Result<(), String> {
-    print!("Enter a character: ");
-    io::stdout().flush().map_err(|e| e.to_string())?;
-    let ch = io::stdin()
-        .bytes()
-        .next()
-        .ok_or("Failed to read byte")?
-        .map_err(|e| e.to_string())? as u16;
-    print!("{}", ch as u8 as char);
-    io::stdout().flush().map_err(|e| e.to_string())?;
+/// - `memory`: A mutable reference to the `Memory` object.
+fn in_(registers: &mut Registers, memory: &mut Memory) -> Result<(), String> {
+    write(memory, "Enter a character: ")?;
+    let ch = getchar(memory)?;
+    write(memory, &(ch as u8 as char).to_string())?;
     registers.write(Register::R0, ch);
     registers.update_flags(Register::R0);
     Ok(())
@@ -148,35 +181,72 @@ fn in_(registers: &mut Registers) -> Result<(), String> {
 /// # Parameters
 /// - `registers`: A reference to the `Registers` object.
 /// - `memory`: A mutable reference to the `Memory` object.
+///
+/// # Errors
+///
+/// Returns a `String` error if no word of memory terminates the string.
 fn putsp(registers: &Registers, memory: &mut Memory) -> Result<(), String> {
-    let mut address = registers.read(Register::R0);
-    loop {
-        let word = memory.read(address)?;
+    let start = registers.read(Register::R0);
+    let mut text = String::new();
+    for offset in 0..=u16::MAX {
+        let word = memory.read(start.wrapping_add(offset))?;
         let char1 = (word & 0xFF) as u8 as char;
         let char2 = (word >> 8) as u8 as char;
 
         if char1 == '\0' {
-            break;
+            return write(memory, &text);
         }
-        print!("{}", char1);
+        text.push(char1);
         if char2 != '\0' {
-            print!("{}", char2);
+            text.push(char2);
         }
-
-        address = address.wrapping_add(1);
     }
-    io::stdout().flush().map_err(|e| e.to_string())
+    Err(unterminated("PUTSP", start))
+}
+
+/// Describes a string that runs through the whole memory without a terminating zero.
+fn unterminated(trap: &str, start: u16) -> String {
+    format!("{} string at x{:04X} is not terminated", trap, start)
 }
 
 /// Executes the HALT trap code.
 ///
-/// This function halts the execution of the program and prints a message.
+/// This function halts the execution of the program and prints a message. The message
+/// comes from the VM rather than the program, so it is left out of captured output.
 ///
 /// # Parameters
+/// - `memory`: A reference to the `Memory` object.
 /// - `running`: A mutable reference to the running status of the program.
-fn halt(running: &mut bool) -> Result<(), String> {
-    println!("Program halted");
-    io::stdout().flush().map_err(|e| e.to_string())?;
+fn halt(memory: &Memory, running: &mut bool) -> Result<(), String> {
+    let captured = memory
+        .bus()
+        .device::<Display>()
+        .is_some_and(Display::is_capturing);
+    if !captured {
+        println!("Program halted");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+    }
     *running = false;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_without_terminator_fault_instead_of_looping() {
+        // No devices, so the whole memory is scanned
+        let mut registers = Registers::new();
+        let mut memory = Memory::new();
+        memory.fill(|| 0xDEAD);
+        registers.write(Register::R0, 0x4000);
+        let mut running = true;
+        for (vector, trap) in [(0x22, "PUTS"), (0x24, "PUTSP")] {
+            assert_eq!(
+                execute(&mut registers, &mut memory, 0xF000 | vector, &mut running),
+                Err(format!("{} string at x4000 is not terminated", trap))
+            );
+        }
+    }
+}
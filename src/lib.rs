@@ -4,15 +4,54 @@
 //! It includes modules for handling the hardware components, instruction set architecture (ISA),
 //! utility functions, and the virtual machine itself.
 
+/// Module for analyses run alongside the VM, observing every executed instruction.
+pub mod analysis;
+
+/// Module implementing the randomized differential tester that runs instruction sequences on
+/// the VM and on the reference model, reporting the first divergence.
+pub mod differential;
+
+/// Module for dumping memory regions as hexdumps, disassembly or program images.
+pub mod dump;
+
+/// Module for reading and writing program images in the object, ASCII hex and binary,
+/// Intel HEX and raw formats.
+pub mod formats;
+
+/// Module implementing the fuzz targets for image loading, instruction execution and assembly
+/// parsing, and a deterministic corpus runner that needs no fuzzing engine.
+pub mod fuzz;
+
 /// Hardware module for the LC-3 Virtual Machine.
 ///
 /// This module contains the submodules for different hardware components
 /// of the LC-3 VM, including flags, memory, and registers.
 pub mod hardware;
 
+/// Module for running test specs against a program, checking its output, registers and
+/// memory, and reporting the results as text or JUnit XML.
+pub mod harness;
+
 /// Module for handling the instruction set architecture (ISA) of the LC-3 VM.
 pub mod isa;
 
+/// Module implementing the relocatable object format and the linker that combines
+/// relocatable objects into a loadable image.
+pub mod linker;
+
+/// Module for loading several program images, detecting the ones that overlap.
+pub mod loader;
+
+/// Module implementing a deliberately simple reference model of the LC-3 instruction
+/// semantics, used to check the VM.
+pub mod reference;
+
+/// Module mapping memory addresses back to the lines of the assembly source they came from.
+pub mod source_map;
+
+/// Module for symbol tables read from `.sym` files, used to describe addresses by label.
+pub mod symbols;
+
 /// Module containing utility functions for handling input and bit manipulations used by the LC-3 VM.
 pub mod utils;
 
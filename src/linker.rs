@@ -0,0 +1,476 @@
+use crate::loader::Image;
+use crate::symbols::SymbolTable;
+use crate::utils::parse_word;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// First line of a relocatable object file.
+pub const RELOCATABLE_MAGIC: &str = "LC3REL 1";
+
+/// How a relocation patches its word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+    /// Bits 8-0 hold the signed offset from the incremented PC (BR, LD, LDI, LEA, ST, STI).
+    PcOffset9,
+    /// Bits 10-0 hold the signed offset from the incremented PC (JSR).
+    PcOffset11,
+    /// The whole word holds the address (`.FILL LABEL`).
+    Fill,
+}
+
+impl RelocationKind {
+    /// Parses `PCOFFSET9`, `PCOFFSET11` or `FILL`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error for any other text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.to_ascii_uppercase().as_str() {
+            "PCOFFSET9" => Ok(RelocationKind::PcOffset9),
+            "PCOFFSET11" => Ok(RelocationKind::PcOffset11),
+            "FILL" => Ok(RelocationKind::Fill),
+            _ => Err(format!(
+                "Unknown relocation '{}', expected PCOFFSET9, PCOFFSET11 or FILL",
+                text
+            )),
+        }
+    }
+}
+
+/// A word to be patched with the address of a symbol once sections are placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// Offset of the word in its section.
+    pub offset: u16,
+    /// How the word is patched.
+    pub kind: RelocationKind,
+    /// Symbol the word refers to.
+    pub symbol: String,
+    /// Constant added to the address of the symbol.
+    pub addend: i32,
+}
+
+/// A block of words, placed at a fixed origin or wherever the linker chooses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Name of the section.
+    pub name: String,
+    /// Fixed load address, or `None` if the section is relocatable.
+    pub origin: Option<u16>,
+    /// Contents of the section.
+    pub words: Vec<u16>,
+    /// Labels defined in the section, with their offset and whether they are exported.
+    pub symbols: Vec<(String, u16, bool)>,
+    /// Words to patch.
+    pub relocations: Vec<Relocation>,
+}
+
+/// A relocatable object: sections that refer to their own labels and to symbols
+/// exported by other objects.
+///
+/// The text format has one directive per line, `;` starting a comment:
+///
+/// ```text
+/// LC3REL 1
+/// IMPORT PRINT                 ; symbol exported by another object
+/// SECTION main ORIGIN x3000    ; ORIGIN is optional, the linker places sections without one
+/// WORDS x4800 xF025 x0000      ; contents, may be repeated
+/// EXPORT MAIN 0                ; label at offset 0, visible to other objects
+/// SYMBOL DATA 2                ; label at offset 2, local to this object
+/// RELOC 0 PCOFFSET11 PRINT     ; patch the JSR at offset 0
+/// RELOC 2 FILL DATA+1          ; store the address of DATA+1 at offset 2
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocatableObject {
+    /// Name of the object, used in error messages.
+    pub name: String,
+    /// Symbols the object expects other objects to export.
+    pub imports: Vec<String>,
+    /// Sections, in order.
+    pub sections: Vec<Section>,
+}
+
+impl RelocatableObject {
+    /// Reads a relocatable object file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the file cannot be read or is not a valid object.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&path.display().to_string(), &text)
+    }
+
+    /// Parses a relocatable object.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the text is not a valid object.
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut object = Self {
+            name: name.to_string(),
+            imports: Vec::new(),
+            sections: Vec::new(),
+        };
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(number, line)| (number + 1, line.split(';').next().unwrap_or_default()))
+            .filter(|(_, line)| !line.trim().is_empty());
+        match lines.next() {
+            Some((_, magic)) if magic.trim() == RELOCATABLE_MAGIC => {}
+            _ => return Err(format!("{}: not a relocatable object", name)),
+        }
+        for (number, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let error = |message: String| format!("{}:{}: {}", name, number, message);
+            object.parse_directive(&fields).map_err(error)?;
+        }
+        Ok(object)
+    }
+
+    /// Applies one directive of the text format.
+    fn parse_directive(&mut self, fields: &[&str]) -> Result<(), String> {
+        let offset = |text: &str, section: &Section| {
+            let offset = parse_word(text)?;
+            if offset as usize >= section.words.len() {
+                return Err(format!("Offset {} is outside of the section", offset));
+            }
+            Ok(offset)
+        };
+        match fields {
+            ["IMPORT", symbol] => self.imports.push(symbol.to_string()),
+            ["SECTION", name] | ["SECTION", name, "ORIGIN", _] => {
+                let origin = fields.get(3).map(|origin| parse_word(origin)).transpose()?;
+                self.sections.push(Section {
+                    name: name.to_string(),
+                    origin,
+                    words: Vec::new(),
+                    symbols: Vec::new(),
+                    relocations: Vec::new(),
+                });
+            }
+            [directive, ..] if ["WORDS", "EXPORT", "SYMBOL", "RELOC"].contains(directive) => {
+                let section = self
+                    .sections
+                    .last_mut()
+                    .ok_or(format!("{} outside of a section", directive))?;
+                match fields {
+                    ["WORDS", words @ ..] => {
+                        for word in words {
+                            section.words.push(parse_word(word)?);
+                        }
+                    }
+                    [kind @ ("EXPORT" | "SYMBOL"), symbol, at] => {
+                        let at = offset(at, section)?;
+                        section
+                            .symbols
+                            .push((symbol.to_string(), at, *kind == "EXPORT"));
+                    }
+                    ["RELOC", at, kind, target] => {
+                        let (symbol, addend) = parse_target(target)?;
+                        section.relocations.push(Relocation {
+                            offset: offset(at, section)?,
+                            kind: RelocationKind::parse(kind)?,
+                            symbol,
+                            addend,
+                        });
+                    }
+                    _ => return Err(format!("Wrong number of fields for {}", directive)),
+                }
+            }
+            _ => return Err(format!("Unknown directive '{}'", fields.join(" "))),
+        }
+        Ok(())
+    }
+}
+
+/// Parses a relocation target: a symbol optionally followed by `+N` or `-N`.
+fn parse_target(text: &str) -> Result<(String, i32), String> {
+    match text.find(['+', '-']) {
+        Some(split) => {
+            let addend = text[split..]
+                .parse()
+                .map_err(|_| format!("Invalid relocation target '{}'", text))?;
+            Ok((text[..split].to_string(), addend))
+        }
+        None => Ok((text.to_string(), 0)),
+    }
+}
+
+/// Result of linking: a loadable image and the addresses of all the labels.
+#[derive(Debug, Clone)]
+pub struct LinkedProgram {
+    /// Contents of the program, gaps between sections filled with zeros.
+    pub image: Image,
+    /// Labels of all the sections, exported or not.
+    pub symbols: SymbolTable,
+}
+
+/// Links relocatable objects into a single image.
+///
+/// Sections with an origin stay at it; the others are placed one after the other from `base`,
+/// in order, each moved past the fixed sections it would overlap. Relocations are then resolved
+/// against the labels of their own object first, and against the symbols exported by any object
+/// otherwise.
+///
+/// # Arguments
+///
+/// * `objects` - The objects to link.
+/// * `name` - Name of the resulting image.
+/// * `base` - Address of the first relocatable section.
+///
+/// # Errors
+///
+/// Returns a `String` error if sections overlap or do not fit in memory, a symbol is exported
+/// twice or undefined, or a resolved offset does not fit in its field.
+pub fn link(objects: &[RelocatableObject], name: &str, base: u16) -> Result<LinkedProgram, String> {
+    // Place the sections, relocatable ones in the first gap between fixed ones
+    let mut fixed: Vec<(u32, u32)> = objects
+        .iter()
+        .flat_map(|object| &object.sections)
+        .filter_map(|s| {
+            s.origin
+                .map(|o| (o as u32, o as u32 + s.words.len() as u32))
+        })
+        .filter(|(start, end)| start < end)
+        .collect();
+    fixed.sort();
+    let mut next = base as u32;
+    let mut placed: Vec<(u32, &RelocatableObject, &Section)> = Vec::new();
+    for object in objects {
+        for section in &object.sections {
+            let address = match section.origin {
+                Some(origin) => origin as u32,
+                None => {
+                    let len = section.words.len() as u32;
+                    let mut address = next;
+                    for &(start, end) in &fixed {
+                        if len > 0 && address < end && start < address + len {
+                            address = end;
+                        }
+                    }
+                    next = address + len;
+                    address
+                }
+            };
+            if address + section.words.len() as u32 > 0x10000 {
+                return Err(format!(
+                    "{}: section '{}' does not fit in memory",
+                    object.name, section.name
+                ));
+            }
+            placed.push((address, object, section));
+        }
+    }
+    let mut by_address: Vec<&(u32, &RelocatableObject, &Section)> = placed
+        .iter()
+        .filter(|(_, _, s)| !s.words.is_empty())
+        .collect();
+    by_address.sort_by_key(|(address, _, _)| *address);
+    for pair in by_address.windows(2) {
+        let (a, a_object, a_section) = pair[0];
+        let (b, b_object, b_section) = pair[1];
+        if a + a_section.words.len() as u32 > *b {
+            return Err(format!(
+                "Section '{}' of {} overlaps section '{}' of {} at x{:04X}",
+                b_section.name, b_object.name, a_section.name, a_object.name, b
+            ));
+        }
+    }
+
+    // Collect the symbols
+    let mut exports: HashMap<&str, (u16, &str)> = HashMap::new();
+    let mut locals: HashMap<(&str, &str), u16> = HashMap::new();
+    let mut symbols = SymbolTable::new();
+    for (address, object, section) in &placed {
+        for (symbol, offset, exported) in &section.symbols {
+            let value = (*address + *offset as u32) as u16;
+            if locals
+                .insert((object.name.as_str(), symbol.as_str()), value)
+                .is_some()
+            {
+                return Err(format!(
+                    "{}: symbol '{}' is defined twice",
+                    object.name, symbol
+                ));
+            }
+            if *exported {
+                if let Some((_, other)) =
+                    exports.insert(symbol.as_str(), (value, object.name.as_str()))
+                {
+                    return Err(format!(
+                        "Symbol '{}' is exported by both {} and {}",
+                        symbol, other, object.name
+                    ));
+                }
+            }
+            symbols.insert(symbol, value);
+        }
+    }
+
+    // Lay out the image and apply the relocations
+    let start = by_address.first().map_or(base as u32, |(a, _, _)| *a);
+    let end = by_address
+        .last()
+        .map_or(start, |(a, _, s)| *a + s.words.len() as u32);
+    let mut words = vec![0u16; (end - start) as usize];
+    for (address, object, section) in &placed {
+        let at = (*address - start) as usize;
+        words[at..at + section.words.len()].copy_from_slice(&section.words);
+        for relocation in &section.relocations {
+            let resolved = locals
+                .get(&(object.name.as_str(), relocation.symbol.as_str()))
+                .or_else(|| {
+                    object
+                        .imports
+                        .contains(&relocation.symbol)
+                        .then(|| exports.get(relocation.symbol.as_str()).map(|(v, _)| v))
+                        .flatten()
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "{}: undefined symbol '{}' in section '{}'",
+                        object.name, relocation.symbol, section.name
+                    )
+                })?;
+            let target = *resolved as i32 + relocation.addend;
+            let location = *address + relocation.offset as u32;
+            let word = &mut words[(location - start) as usize];
+            *word = relocate(*word, relocation.kind, location as u16, target).map_err(|e| {
+                format!(
+                    "{}: relocation of '{}' at x{:04X}: {}",
+                    object.name, relocation.symbol, location, e
+                )
+            })?;
+        }
+    }
+    Ok(LinkedProgram {
+        image: Image::new(name, start as u16, words),
+        symbols,
+    })
+}
+
+/// Patches `word`, stored at `location`, so that it refers to `target`.
+fn relocate(word: u16, kind: RelocationKind, location: u16, target: i32) -> Result<u16, String> {
+    let bits = match kind {
+        RelocationKind::Fill => return Ok(target as u16),
+        RelocationKind::PcOffset9 => 9,
+        RelocationKind::PcOffset11 => 11,
+    };
+    let offset = target - (location as i32 + 1);
+    let (min, max) = (-(1 << (bits - 1)), (1 << (bits - 1)) - 1);
+    if !(min..=max).contains(&offset) {
+        return Err(format!(
+            "offset {} does not fit in {} bits ({}..{})",
+            offset, bits, min, max
+        ));
+    }
+    let mask = (1u16 << bits) - 1;
+    Ok((word & !mask) | (offset as u16 & mask))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: &str = "\
+LC3REL 1
+IMPORT PRINT
+SECTION main ORIGIN x3000
+WORDS xE000 x4800 x0000         ; LEA R0 MESSAGE, JSR PRINT, pointer
+EXPORT MAIN 0
+RELOC 0 PCOFFSET9 MESSAGE
+RELOC 1 PCOFFSET11 PRINT
+RELOC 2 FILL MESSAGE+1
+SECTION data
+WORDS x0048 x0069 x0000
+SYMBOL MESSAGE 0
+";
+
+    const PRINT: &str = "\
+LC3REL 1
+SECTION print
+WORDS xF022 xF025               ; PUTS, HALT
+EXPORT PRINT 0
+";
+
+    fn objects() -> Vec<RelocatableObject> {
+        vec![
+            RelocatableObject::parse("main.rel", MAIN).unwrap(),
+            RelocatableObject::parse("print.rel", PRINT).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn link_places_sections_and_resolves_relocations() {
+        let linked = link(&objects(), "prog", 0x3003).unwrap();
+        assert_eq!(linked.image.origin, 0x3000);
+        assert_eq!(
+            linked.image.words,
+            [
+                0xE002, // LEA R0, MESSAGE (x3003)
+                0x4804, // JSR PRINT (x3006)
+                0x3004, // MESSAGE+1
+                0x0048, 0x0069, 0x0000, // data
+                0xF022, 0xF025, // print
+            ]
+        );
+        assert_eq!(linked.symbols.address("MESSAGE"), Some(0x3003));
+        assert_eq!(linked.symbols.address("PRINT"), Some(0x3006));
+    }
+
+    #[test]
+    fn relocatable_sections_skip_fixed_sections() {
+        // The default base of `lc3-vm link` falls on the fixed main section
+        let linked = link(&objects(), "prog", crate::hardware::registers::PC_START).unwrap();
+        assert_eq!(linked.image.origin, 0x3000);
+        assert_eq!(linked.symbols.address("MESSAGE"), Some(0x3003));
+        assert_eq!(linked.symbols.address("PRINT"), Some(0x3006));
+        assert_eq!(linked.image.words[..3], [0xE002, 0x4804, 0x3004]);
+    }
+
+    #[test]
+    fn gaps_between_sections_are_filled_with_zeros() {
+        let linked = link(&objects(), "prog", 0x3010).unwrap();
+        assert_eq!(linked.image.words.len(), 0x15);
+        assert_eq!(linked.image.words[3..0x10], [0; 13]);
+    }
+
+    #[test]
+    fn offsets_that_do_not_fit_are_rejected() {
+        let error = link(&objects(), "prog", 0x3200).unwrap_err();
+        assert!(error.contains("does not fit in 9 bits"), "{}", error);
+    }
+
+    #[test]
+    fn undefined_and_duplicate_symbols_are_rejected() {
+        let main = objects().remove(0);
+        assert!(link(std::slice::from_ref(&main), "prog", 0x3003)
+            .unwrap_err()
+            .contains("undefined symbol 'PRINT'"));
+        let print = RelocatableObject::parse("print.rel", PRINT).unwrap();
+        let copy = RelocatableObject::parse("copy.rel", PRINT).unwrap();
+        assert!(link(&[main, print, copy], "prog", 0x3003)
+            .unwrap_err()
+            .contains("exported by both"));
+    }
+
+    #[test]
+    fn overlapping_sections_are_rejected() {
+        let text = "LC3REL 1\nSECTION a ORIGIN x3000\nWORDS 1 2\nSECTION b ORIGIN x3001\nWORDS 3\n";
+        let object = RelocatableObject::parse("a.rel", text).unwrap();
+        assert!(link(&[object], "prog", 0x4000).is_err());
+    }
+
+    #[test]
+    fn parse_reports_line_of_invalid_directive() {
+        let text = "LC3REL 1\nSECTION a\nWORDS 1\nRELOC 5 FILL X\n";
+        assert_eq!(
+            RelocatableObject::parse("a.rel", text).unwrap_err(),
+            "a.rel:4: Offset 5 is outside of the section"
+        );
+        assert!(RelocatableObject::parse("a.rel", "WORDS 1\n").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use crate::utils::parse_word;
+use crate::vm::VM;
+use byteorder::{BigEndian, ByteOrder};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// A program image: a block of words to be loaded at an origin address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// Name of the image, the file name without extension when read with `formats::read`.
+    pub name: String,
+    /// Address of the first word.
+    pub origin: u16,
+    /// Contents of the image.
+    pub words: Vec<u16>,
+}
+
+impl Image {
+    /// Creates an image from its contents.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Image`.
+    pub fn new(name: &str, origin: u16, words: Vec<u16>) -> Self {
+        Self {
+            name: name.to_string(),
+            origin,
+            words,
+        }
+    }
+
+    /// Decodes the contents of an object file. A trailing odd byte is ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if there is no origin or the words do not fit in memory.
+    pub fn from_bytes(name: &str, bytes: &[u8]) -> Result<Self, String> {
+        let mut words = bytes.chunks_exact(2).map(BigEndian::read_u16);
+        let origin = words
+            .next()
+            .ok_or("Missing origin, object file is too short")?;
+        let words: Vec<u16> = words.collect();
+        if origin as usize + words.len() > 0x10000 {
+            return Err("Memory overflow, object file is too large".to_string());
+        }
+        Ok(Self::new(name, origin, words))
+    }
+
+    /// Returns the addresses occupied by the image, or `None` if it is empty.
+    pub fn range(&self) -> Option<RangeInclusive<u16>> {
+        // Computed before narrowing: the length of a 65536-word image does not fit in a u16
+        let end = self.origin as usize + self.words.len();
+        (!self.words.is_empty()).then(|| self.origin..=(end - 1) as u16)
+    }
+}
+
+/// What to do when an image overwrites words of a previously loaded one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlapPolicy {
+    /// Load silently, later images win.
+    Allow,
+    /// Load and report the overlap, later images win.
+    #[default]
+    Warn,
+    /// Refuse to load the image.
+    Error,
+}
+
+impl OverlapPolicy {
+    /// Parses `allow`, `warn` or `error`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error for any other text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "allow" => Ok(OverlapPolicy::Allow),
+            "warn" => Ok(OverlapPolicy::Warn),
+            "error" => Ok(OverlapPolicy::Error),
+            _ => Err(format!(
+                "Unknown overlap policy '{}', expected allow, warn or error",
+                text
+            )),
+        }
+    }
+}
+
+/// Addresses written by two different images.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    /// Image that was loaded first.
+    pub earlier: String,
+    /// Image that overwrote it.
+    pub later: String,
+    /// Addresses written by both images.
+    pub range: RangeInclusive<u16>,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' overwrites '{}' at x{:04X}",
+            self.later,
+            self.earlier,
+            self.range.start()
+        )?;
+        if self.range.end() != self.range.start() {
+            write!(f, "-x{:04X}", self.range.end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Where execution starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    /// A fixed address.
+    Address(u16),
+    /// The origin of the image with the given name.
+    Image(String),
+}
+
+impl Entry {
+    /// Parses an address (`x3000`, `0x3000` or decimal) or, failing that, an image name.
+    pub fn parse(text: &str) -> Self {
+        match parse_word(text) {
+            Ok(address) => Entry::Address(address),
+            Err(_) => Entry::Image(text.to_string()),
+        }
+    }
+}
+
+/// Loads several images into memory, keeping track of the addresses each one occupies.
+#[derive(Debug, Default)]
+pub struct Loader {
+    /// What to do when images overlap.
+    policy: OverlapPolicy,
+    /// Images added so far, in load order.
+    images: Vec<Image>,
+    /// Overlaps found so far.
+    overlaps: Vec<Overlap>,
+}
+
+impl Loader {
+    /// Creates an empty loader.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Loader`.
+    pub fn new(policy: OverlapPolicy) -> Self {
+        Self {
+            policy,
+            images: Vec::new(),
+            overlaps: Vec::new(),
+        }
+    }
+
+    /// Adds an image to be loaded after the previously added ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the image overlaps a previous one and the policy is `Error`.
+    pub fn add(&mut self, image: Image) -> Result<(), String> {
+        let overlaps: Vec<Overlap> = match image.range() {
+            Some(range) => self
+                .images
+                .iter()
+                .filter_map(|earlier| {
+                    let other = earlier.range()?;
+                    let start = *range.start().max(other.start());
+                    let end = *range.end().min(other.end());
+                    (start <= end).then(|| Overlap {
+                        earlier: earlier.name.clone(),
+                        later: image.name.clone(),
+                        range: start..=end,
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        if let (OverlapPolicy::Error, Some(overlap)) = (self.policy, overlaps.first()) {
+            return Err(format!("Image {}", overlap));
+        }
+        self.overlaps.extend(overlaps);
+        self.images.push(image);
+        Ok(())
+    }
+
+    /// Returns the images added so far, in load order.
+    pub fn images(&self) -> &[Image] {
+        &self.images
+    }
+
+    /// Returns the overlaps found so far.
+    pub fn overlaps(&self) -> &[Overlap] {
+        &self.overlaps
+    }
+
+    /// Resolves an entry point to an address.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if no image, or more than one, has the given name.
+    pub fn entry_point(&self, entry: &Entry) -> Result<u16, String> {
+        match entry {
+            Entry::Address(address) => Ok(*address),
+            Entry::Image(name) => {
+                let mut matches = self.images.iter().filter(|image| &image.name == name);
+                match (matches.next(), matches.next()) {
+                    (Some(image), None) => Ok(image.origin),
+                    (Some(_), Some(_)) => Err(format!("More than one image is named '{}'", name)),
+                    (None, _) => Err(format!("No image named '{}'", name)),
+                }
+            }
+        }
+    }
+
+    /// Writes the images into the VM's memory, in load order.
+    pub fn load(&self, vm: &mut VM) {
+        for image in &self.images {
+            vm.load_image(image);
+        }
+    }
+
+    /// Describes the addresses occupied by each image, sorted by address, followed by the overlaps.
+    pub fn memory_map(&self) -> String {
+        let mut images: Vec<&Image> = self.images.iter().collect();
+        images.sort_by_key(|image| image.origin);
+        let mut map = String::from("Memory map:\n");
+        for image in images {
+            match image.range() {
+                Some(range) => map.push_str(&format!(
+                    "  x{:04X}-x{:04X}  {:>5} words  {}\n",
+                    range.start(),
+                    range.end(),
+                    image.words.len(),
+                    image.name
+                )),
+                None => map.push_str(&format!(
+                    "  x{:04X}        {:>5} words  {}\n",
+                    image.origin, 0, image.name
+                )),
+            }
+        }
+        for overlap in &self.overlaps {
+            map.push_str(&format!("  overlap: {}\n", overlap));
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bytes_decodes_origin_and_words() {
+        let image = Image::from_bytes("prog", &[0x30, 0x00, 0x12, 0x34, 0xAB]).unwrap();
+        assert_eq!(image, Image::new("prog", 0x3000, vec![0x1234]));
+        assert_eq!(image.range(), Some(0x3000..=0x3000));
+        assert!(Image::from_bytes("prog", &[0x30]).is_err());
+        assert!(Image::from_bytes("prog", &[0xFF, 0xFF, 0, 1, 0, 2]).is_err());
+    }
+
+    #[test]
+    fn overlapping_images_are_reported() {
+        let mut loader = Loader::new(OverlapPolicy::Warn);
+        loader.add(Image::new("os", 0x0200, vec![0; 0x10])).unwrap();
+        loader.add(Image::new("prog", 0x3000, vec![0; 4])).unwrap();
+        loader.add(Image::new("patch", 0x3002, vec![0; 4])).unwrap();
+        assert_eq!(
+            loader.overlaps(),
+            [Overlap {
+                earlier: "prog".to_string(),
+                later: "patch".to_string(),
+                range: 0x3002..=0x3003,
+            }]
+        );
+        assert_eq!(
+            loader.overlaps()[0].to_string(),
+            "'patch' overwrites 'prog' at x3002-x3003"
+        );
+    }
+
+    #[test]
+    fn full_memory_image_overlaps_others() {
+        let full = Image::new("raw", 0x0000, vec![0; 0x10000]);
+        assert_eq!(full.range(), Some(0x0000..=0xFFFF));
+        let mut loader = Loader::new(OverlapPolicy::Warn);
+        loader.add(full).unwrap();
+        loader.add(Image::new("prog", 0x3000, vec![0; 2])).unwrap();
+        assert_eq!(loader.overlaps()[0].range, 0x3000..=0x3001);
+        assert!(
+            loader.memory_map().contains("x0000-xFFFF"),
+            "{}",
+            loader.memory_map()
+        );
+    }
+
+    #[test]
+    fn error_policy_rejects_overlap() {
+        let mut loader = Loader::new(OverlapPolicy::Error);
+        loader.add(Image::new("a", 0x3000, vec![0; 4])).unwrap();
+        assert!(loader.add(Image::new("b", 0x3003, vec![0])).is_err());
+        assert!(loader.add(Image::new("c", 0x3004, vec![0])).is_ok());
+        assert_eq!(loader.images().len(), 2);
+    }
+
+    #[test]
+    fn entry_point_resolves_image_names() {
+        let mut loader = Loader::new(OverlapPolicy::Allow);
+        loader.add(Image::new("os", 0x0200, vec![0])).unwrap();
+        loader.add(Image::new("prog", 0x4000, vec![0])).unwrap();
+        assert_eq!(loader.entry_point(&Entry::parse("prog")), Ok(0x4000));
+        assert_eq!(loader.entry_point(&Entry::parse("x3000")), Ok(0x3000));
+        assert!(loader.entry_point(&Entry::parse("missing")).is_err());
+    }
+
+    #[test]
+    fn memory_map_is_sorted_by_address() {
+        let mut loader = Loader::new(OverlapPolicy::Allow);
+        loader.add(Image::new("prog", 0x3000, vec![0; 2])).unwrap();
+        loader
+            .add(Image::new("os", 0x0200, vec![0; 0x100]))
+            .unwrap();
+        assert_eq!(
+            loader.memory_map(),
+            "Memory map:\n  x0200-x02FF    256 words  os\n  x3000-x3001      2 words  prog\n"
+        );
+    }
+}
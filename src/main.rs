@@ -3,45 +3,962 @@
 //! This module handles the initialization and execution of the LC-3 VM, including
 //! command-line argument parsing, input buffering, and error handling.
 
+use lc3_vm::analysis::coverage::Coverage;
+use lc3_vm::analysis::profiler::Profiler;
+use lc3_vm::analysis::sanitizer::{Sanitizer, SanitizerMode};
+use lc3_vm::analysis::stack::StackChecker;
+use lc3_vm::differential;
+use lc3_vm::dump::{parse_range, DumpFormat};
+use lc3_vm::formats::{self, ImageFormat};
+use lc3_vm::fuzz::{self, Target};
+use lc3_vm::hardware::audio::{ToneGenerator, DEFAULT_CLOCK_HZ};
+use lc3_vm::hardware::disk::Disk;
+use lc3_vm::hardware::memory::MemoryMappedRegister;
+use lc3_vm::hardware::registers::{Register, PC_START};
+use lc3_vm::hardware::rng::Rng;
+use lc3_vm::hardware::rtc::{Clock, DateTime, RealTimeClock};
+use lc3_vm::hardware::serial::{Serial, SerialEndpoint};
+use lc3_vm::hardware::text_display::{
+    TextDisplay, TextRenderer, TEXT_BASE, TEXT_COLUMNS, TEXT_ROWS,
+};
+use lc3_vm::hardware::video::{FrameFormat, VideoDisplay};
+use lc3_vm::harness::{self, CaseResult, Outcome, TestSpec};
+use lc3_vm::linker::{self, RelocatableObject};
+use lc3_vm::loader::{Entry, Image, Loader, OverlapPolicy};
+use lc3_vm::source_map::SourceMap;
+use lc3_vm::symbols::SymbolTable;
 use lc3_vm::utils::*;
-use lc3_vm::vm::VM;
+use lc3_vm::vm::{Fill, VM};
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use termios::Termios;
+
+const USAGE: &str = "\
+Usage: lc3-vm [options] [image-file1] ...
+       lc3-vm dump [--range <range>] [options] [image-file1] ...
+       lc3-vm profile [--top <n>] [--profile-file <file>] [--collapsed <file>] [options] [image-file1] ...
+       lc3-vm link -o <out.obj> [--base <addr>] <object.rel> ...
+       lc3-vm test <spec.toml> [--junit <file>] [image-file1] ...
+       lc3-vm difftest [--seed <n>] [--cases <n>] [--length <n>]
+       lc3-vm fuzz <image|instruction|assembly> [--runs <n>] [--seed <n>] [corpus] ...
+
+Options:
+  --format <format>           Format of the images: obj, hex, bin, ihex or raw (detected by default)
+  --origin <addr>             Load address of raw images
+  --entry <addr|image>        Start at an address or at the origin of the named image (x3000 by default)
+  --overlap <policy>          Policy for overlapping images: allow, warn (default) or error
+  --map                       Print the memory map of the loaded images
+  --init-memory <fill>        Fill memory before loading: zero (default), random or a word such as xDEAD
+  --init-registers <fill>     Fill R0-R7: zero (default), random or a word
+  --set <reg>=<value>         Set R0-R7 or PC before running, e.g. --set R6=xFE00
+  --flag-uninit               Report reads of memory words that were never loaded or written
+  --check-stack               Warn when subroutines return elsewhere than after their call or
+                              with a different R6
+  --sanitize [report|halt]    Report (default) or halt on reads of uninitialized memory and registers
+                              and execution of data words
+  --top <n>                   Number of hot spots in the profile report (20 by default)
+  --profile-file <file>       Write the profile report to a file instead of standard output
+  --collapsed <file>          Write the profiled call stacks in the collapsed flame graph format
+  --coverage <file>           Collect coverage, adding it to the runs already saved in the file
+  --lcov <file>               Write the coverage as an lcov tracefile, mapped to the .asm sources
+  --dump-on-exit <range>      Dump memory (START-END, addresses or labels) when the program stops
+  --dump-format <format>      Format of the dumps: hexdump (default), disasm, obj, hex, bin, ihex or raw
+  --dump-file <file>          Write the dumps to a file instead of standard output
+  --disk <image-file>         Attach the disk controller backed by a host file
+  --text-display [COLSxROWS]  Attach a text framebuffer (80x24 by default)
+  --video                     Attach the 128x124 pixel framebuffer at xC000
+  --screenshot <file>         Save the video display to a .png or .ppm file on exit
+  --frames <dir>              Dump video frames to a directory while running
+  --frame-format <png|ppm>    Format of the dumped frames (png by default)
+  --frame-interval <n>        Instructions between two dumped frames (100000 by default)
+  --audio <file.wav>          Attach the tone generator and write its output to a WAV file
+  --audio-clock <hz>          Instructions per second of audio (1000000 by default)
+  --headless                  Do not draw display devices on the terminal
+  --seed <n>                  Seed the random number generator for reproducible runs
+  --rtc-freeze <time>         Freeze the real-time clock at a Unix timestamp or YYYY-MM-DDTHH:MM:SS
+  --rtc-offset <seconds>      Shift the real-time clock from the host time
+  --serial <endpoint>         Attach the serial port to connect:PATH, listen:PATH, file:IN,OUT or pty";
+
+/// Default number of hot spots listed in the profile report.
+const PROFILE_TOP: usize = 20;
+
+/// Default number of random cases run by `lc3-vm difftest`.
+const DIFFTEST_CASES: usize = 1000;
+
+/// Default number of instructions generated per `lc3-vm difftest` case.
+const DIFFTEST_LENGTH: usize = 64;
+
+/// Default number of mutated inputs run by `lc3-vm fuzz`.
+const FUZZ_RUNS: usize = 10_000;
+
+/// Default number of instructions between two dumped video frames.
+const FRAME_INTERVAL: u32 = 100_000;
+
+/// Options parsed from the command line.
+#[derive(Default)]
+struct Options {
+    /// Image files to load, in order.
+    images: Vec<String>,
+    /// Format of the images, detected from each file if `None`.
+    format: Option<ImageFormat>,
+    /// Load address of raw images.
+    origin: Option<u16>,
+    /// Where execution starts, if not at `PC_START`.
+    entry: Option<Entry>,
+    /// What to do when images overlap.
+    overlap: OverlapPolicy,
+    /// Print the memory map of the loaded images.
+    map: bool,
+    /// Initial contents of memory, if not zero.
+    init_memory: Option<Fill>,
+    /// Initial contents of R0 to R7, if not zero.
+    init_registers: Option<Fill>,
+    /// Registers set from the command line, applied after the entry point.
+    set_registers: Vec<(Register, u16)>,
+    /// Report reads of memory words that were never loaded or written.
+    flag_uninit: bool,
+    /// Run the sanitizer, reporting or halting on violations.
+    sanitize: Option<SanitizerMode>,
+    /// Check that subroutine calls and returns follow the R6-based calling convention.
+    check_stack: bool,
+    /// Memory regions to dump once the program stops, as given on the command line.
+    dump_ranges: Vec<String>,
+    /// Format of the memory dumps.
+    dump_format: Option<DumpFormat>,
+    /// File the memory dumps are written to, standard output if `None`.
+    dump_file: Option<String>,
+    /// Dump memory without running the program (`lc3-vm dump`).
+    dump_only: bool,
+    /// Profile the program and report where its instructions were executed (`lc3-vm profile`).
+    profile: bool,
+    /// Number of hot spots in the profile report, if not the default.
+    profile_top: Option<usize>,
+    /// File the profile report is written to instead of standard output, if any.
+    profile_file: Option<String>,
+    /// File the profiled call stacks are written to in the collapsed format, if any.
+    collapsed: Option<String>,
+    /// File the coverage is accumulated in across runs, if any.
+    coverage: Option<String>,
+    /// File the coverage is exported to as an lcov tracefile, if any.
+    lcov: Option<String>,
+    /// Host file backing the disk controller, if any.
+    disk: Option<String>,
+    /// Columns and rows of the text framebuffer, if enabled.
+    text_display: Option<(u16, u16)>,
+    /// Render display devices headlessly instead of on the terminal.
+    headless: bool,
+    /// Attach the pixel framebuffer.
+    video: bool,
+    /// File the video display is saved to on exit, if any.
+    screenshot: Option<String>,
+    /// Directory video frames are dumped to while running, if any.
+    frames: Option<String>,
+    /// Format of the dumped video frames.
+    frame_format: Option<FrameFormat>,
+    /// Instructions between two dumped video frames.
+    frame_interval: Option<u32>,
+    /// Seed of the random number generator, if fixed.
+    seed: Option<u64>,
+    /// Source of the real-time clock, if not the host time.
+    clock: Option<Clock>,
+    /// Host endpoint of the serial port, if attached.
+    serial: Option<SerialEndpoint>,
+    /// WAV file the tone generator output is written to, if attached.
+    audio: Option<String>,
+    /// Instructions per second of audio.
+    audio_clock: Option<u64>,
+}
+
+/// Links relocatable objects into an object file, writing its symbol table next to it.
+///
+/// Takes the arguments following `link`: `-o <out.obj>`, an optional `--base <addr>` where
+/// relocatable sections are placed (`x3000` by default) and the relocatable objects.
+fn link_command(args: &[String]) -> Result<(), String> {
+    let mut output = None;
+    let mut base = PC_START;
+    let mut objects = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or("-o requires a file")?),
+            "--base" => base = parse_word(args.next().ok_or("--base requires an address")?)?,
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            path => objects.push(RelocatableObject::read(Path::new(path))?),
+        }
+    }
+    let output = Path::new(output.ok_or("No output file given, use -o <out.obj>")?);
+    if objects.is_empty() {
+        return Err("No relocatable objects given".to_string());
+    }
+    let name = output
+        .file_stem()
+        .map_or_else(|| output.to_string_lossy(), |stem| stem.to_string_lossy());
+    let linked = linker::link(&objects, &name, base)?;
+    formats::write(output, ImageFormat::Object, &linked.image)?;
+    std::fs::write(output.with_extension("sym"), linked.symbols.to_sym()).map_err(|e| e.to_string())
+}
+
+/// Runs the test cases of a spec, printing a line per test case and a summary.
+///
+/// Takes the arguments following `test`: the spec, an optional `--junit <file>` the results
+/// are also written to, and the image files of the program under test, which replace the
+/// images named by the spec, e.g. to grade each submission against the same spec.
+///
+/// # Returns
+///
+/// Whether every test case passed.
+fn test_command(args: &[String]) -> Result<bool, String> {
+    let mut spec = None;
+    let mut junit = None;
+    let mut images = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--junit" => junit = Some(args.next().ok_or("--junit requires a file")?),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            path if spec.is_none() => spec = Some(path),
+            path => images.push(PathBuf::from(path)),
+        }
+    }
+    let spec = TestSpec::read(Path::new(spec.ok_or("No test spec given")?))?;
+    if images.is_empty() {
+        images = spec.images.clone();
+    }
+    if images.is_empty() {
+        return Err("No image files given, in the spec or on the command line".to_string());
+    }
+    let mut program = Vec::new();
+    let mut symbols = SymbolTable::new();
+    for path in &images {
+        let loaded = formats::read(path, None, None)
+            .map_err(|e| format!("failed to load image file '{}': {}", path.display(), e))?;
+        program.extend(loaded);
+        let path = path.with_extension("sym");
+        if path.exists() {
+            match SymbolTable::read(&path) {
+                Ok(loaded) => symbols.extend(&loaded),
+                Err(e) => eprintln!("Warning: ignoring '{}': {}", path.display(), e),
+            }
+        }
+    }
+    let results: Vec<CaseResult> = spec
+        .cases
+        .iter()
+        .map(|case| case.run(&program, &symbols))
+        .collect();
+    harness::write_report(&results, &mut io::stdout())?;
+    if let Some(path) = junit {
+        let mut file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        harness::write_junit(&spec.name, &results, &mut file)?;
+    }
+    Ok(results.iter().all(|r| r.outcome == Outcome::Passed))
+}
+
+/// Runs random instruction sequences on the VM and on the reference model, printing the
+/// first divergence.
+///
+/// Takes the arguments following `difftest`: an optional `--seed <n>` (the host time by
+/// default), `--cases <n>` and `--length <n>` instructions per case.
+///
+/// # Returns
+///
+/// Whether the VM agreed with the model on every case.
+fn difftest_command(args: &[String]) -> Result<bool, String> {
+    let mut seed = Rng::from_time().next_u64();
+    let mut cases = DIFFTEST_CASES;
+    let mut length = DIFFTEST_LENGTH;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| {
+            let value = args.next().ok_or(format!("{} requires a number", arg))?;
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid {} '{}'", name, value))
+        };
+        match arg.as_str() {
+            "--seed" => seed = number("seed")?,
+            "--cases" => cases = number("number of cases")? as usize,
+            "--length" => length = number("length")? as usize,
+            flag => return Err(format!("Unknown option '{}'", flag)),
+        }
+    }
+    match differential::find_divergence(seed, cases, length) {
+        Some(divergence) => {
+            print!("{}", divergence);
+            Ok(false)
+        }
+        None => {
+            println!(
+                "Seed {}: {} cases of {} instructions agree with the reference model",
+                seed, cases, length
+            );
+            Ok(true)
+        }
+    }
+}
+
+/// Feeds a fuzz target its corpus and mutations of it, printing the inputs that panic.
+///
+/// Takes the arguments following `fuzz`: the target, an optional `--runs <n>` number of
+/// mutations, `--seed <n>` (the host time by default) and the corpus directories or files,
+/// `fuzz/corpus/<target>` by default.
+///
+/// # Returns
+///
+/// Whether no input made the target panic.
+fn fuzz_command(args: &[String]) -> Result<bool, String> {
+    let target = Target::parse(args.first().ok_or("Missing fuzz target")?)?;
+    let mut seed = Rng::from_time().next_u64();
+    let mut runs = FUZZ_RUNS;
+    let mut paths = Vec::new();
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| {
+            let value = args.next().ok_or(format!("{} requires a number", arg))?;
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid {} '{}'", name, value))
+        };
+        match arg.as_str() {
+            "--seed" => seed = number("seed")?,
+            "--runs" => runs = number("number of runs")? as usize,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+            path => paths.push(PathBuf::from(path)),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(Path::new("fuzz/corpus").join(target.name()));
+    }
+    let mut corpus = Vec::new();
+    for path in &paths {
+        if path.is_dir() {
+            corpus.extend(fuzz::read_corpus(path)?);
+        } else {
+            let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            corpus.push((path.display().to_string(), bytes));
+        }
+    }
+    // Panics are reported with their input instead
+    std::panic::set_hook(Box::new(|_| {}));
+    let crashes = fuzz::run_corpus(target, &corpus, runs, seed);
+    let _ = std::panic::take_hook();
+    for crash in &crashes {
+        let input: Vec<String> = crash.input.iter().map(|b| format!("{:02X}", b)).collect();
+        println!("Panic on {}: {}", crash.origin, crash.message);
+        println!("  input: {}", input.join(" "));
+    }
+    println!(
+        "Seed {}: ran {} corpus inputs and {} mutations on the {} target, {} panicked",
+        seed,
+        corpus.len(),
+        runs,
+        target.name(),
+        crashes.len()
+    );
+    Ok(crashes.is_empty())
+}
+
+/// Parses the command-line arguments (excluding the program name).
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let format = args.next().ok_or("--format requires a format")?;
+                options.format = Some(ImageFormat::parse(format)?);
+            }
+            "--origin" => {
+                let origin = args.next().ok_or("--origin requires an address")?;
+                options.origin = Some(parse_word(origin)?);
+            }
+            "--entry" => {
+                let entry = args.next().ok_or("--entry requires an address or image")?;
+                options.entry = Some(Entry::parse(entry));
+            }
+            "--overlap" => {
+                let policy = args.next().ok_or("--overlap requires a policy")?;
+                options.overlap = OverlapPolicy::parse(policy)?;
+            }
+            "--map" => options.map = true,
+            "--init-memory" => {
+                let fill = args.next().ok_or("--init-memory requires a fill")?;
+                options.init_memory = Some(Fill::parse(fill)?);
+            }
+            "--init-registers" => {
+                let fill = args.next().ok_or("--init-registers requires a fill")?;
+                options.init_registers = Some(Fill::parse(fill)?);
+            }
+            "--set" => {
+                let assignment = args.next().ok_or("--set requires REGISTER=VALUE")?;
+                let (register, value) = assignment.split_once('=').ok_or_else(|| {
+                    format!(
+                        "Invalid assignment '{}', expected REGISTER=VALUE",
+                        assignment
+                    )
+                })?;
+                options
+                    .set_registers
+                    .push((Register::parse(register)?, parse_word(value)?));
+            }
+            "--flag-uninit" => options.flag_uninit = true,
+            "--check-stack" => options.check_stack = true,
+            "--top" => {
+                let top = args.next().ok_or("--top requires a number")?;
+                let top = top
+                    .parse()
+                    .map_err(|_| format!("Invalid number of hot spots '{}'", top))?;
+                options.profile_top = Some(top);
+            }
+            "--profile-file" => {
+                let file = args.next().ok_or("--profile-file requires a file")?;
+                options.profile_file = Some(file.clone());
+            }
+            "--coverage" => {
+                let file = args.next().ok_or("--coverage requires a file")?;
+                options.coverage = Some(file.clone());
+            }
+            "--lcov" => {
+                let file = args.next().ok_or("--lcov requires a file")?;
+                options.lcov = Some(file.clone());
+            }
+            "--collapsed" => {
+                let file = args.next().ok_or("--collapsed requires a file")?;
+                options.collapsed = Some(file.clone());
+            }
+            "--sanitize" => {
+                // The mode is optional, only consume the next argument if it is one
+                let mode = args.next_if(|arg| SanitizerMode::parse(arg).is_ok());
+                options.sanitize = Some(match mode {
+                    Some(mode) => SanitizerMode::parse(mode)?,
+                    None => SanitizerMode::Report,
+                });
+            }
+            "--dump-on-exit" | "--range" => {
+                let range = args.next().ok_or(format!("{} requires a range", arg))?;
+                options.dump_ranges.push(range.clone());
+            }
+            "--dump-format" => {
+                let format = args.next().ok_or("--dump-format requires a format")?;
+                options.dump_format = Some(DumpFormat::parse(format)?);
+            }
+            "--dump-file" => {
+                let path = args.next().ok_or("--dump-file requires a file")?;
+                options.dump_file = Some(path.clone());
+            }
+            "--disk" => {
+                let path = args.next().ok_or("--disk requires an image file")?;
+                options.disk = Some(path.clone());
+            }
+            "--text-display" => {
+                // The size is optional, only consume the next argument if it is one
+                let size = args.next_if(|arg| parse_size(arg).is_ok());
+                options.text_display = Some(match size {
+                    Some(size) => parse_size(size)?,
+                    None => (TEXT_COLUMNS, TEXT_ROWS),
+                });
+            }
+            "--headless" => options.headless = true,
+            "--seed" => {
+                let seed = args.next().ok_or("--seed requires a number")?;
+                let seed = seed
+                    .parse()
+                    .map_err(|_| format!("Invalid seed '{}'", seed))?;
+                options.seed = Some(seed);
+            }
+            "--rtc-freeze" => {
+                let time = args.next().ok_or("--rtc-freeze requires a time")?;
+                let timestamp = match time.parse() {
+                    Ok(timestamp) => timestamp,
+                    Err(_) => DateTime::parse(time)?.to_unix(),
+                };
+                options.clock = Some(Clock::Frozen(timestamp));
+            }
+            "--serial" => {
+                let endpoint = args.next().ok_or("--serial requires an endpoint")?;
+                options.serial = Some(SerialEndpoint::parse(endpoint)?);
+            }
+            "--audio" => {
+                let path = args.next().ok_or("--audio requires a file")?;
+                options.audio = Some(path.clone());
+            }
+            "--audio-clock" => {
+                let clock = args.next().ok_or("--audio-clock requires a frequency")?;
+                let clock = clock
+                    .parse()
+                    .ok()
+                    .filter(|&hz| hz > 0)
+                    .ok_or_else(|| format!("Invalid audio clock '{}'", clock))?;
+                options.audio_clock = Some(clock);
+            }
+            "--rtc-offset" => {
+                let offset = args
+                    .next()
+                    .ok_or("--rtc-offset requires a number of seconds")?;
+                let offset = offset
+                    .parse()
+                    .map_err(|_| format!("Invalid clock offset '{}'", offset))?;
+                options.clock = Some(Clock::Host { offset });
+            }
+            "--video" => options.video = true,
+            "--screenshot" => {
+                let path = args.next().ok_or("--screenshot requires a file")?;
+                FrameFormat::from_path(Path::new(path))?;
+                options.screenshot = Some(path.clone());
+            }
+            "--frames" => {
+                let dir = args.next().ok_or("--frames requires a directory")?;
+                options.frames = Some(dir.clone());
+            }
+            "--frame-format" => {
+                let format = args.next().ok_or("--frame-format requires a format")?;
+                options.frame_format = Some(match format.as_str() {
+                    "png" => FrameFormat::Png,
+                    "ppm" => FrameFormat::Ppm,
+                    _ => return Err(format!("Unknown frame format '{}'", format)),
+                });
+            }
+            "--frame-interval" => {
+                let interval = args.next().ok_or("--frame-interval requires a number")?;
+                let interval = interval
+                    .parse()
+                    .map_err(|_| format!("Invalid frame interval '{}'", interval))?;
+                options.frame_interval = Some(interval);
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+            path => options.images.push(path.to_string()),
+        }
+    }
+    if options.images.is_empty() {
+        return Err("No image files given".to_string());
+    }
+    Ok(options)
+}
+
+/// Parses a size given as `COLSxROWS`.
+fn parse_size(size: &str) -> Result<(u16, u16), String> {
+    let invalid = || format!("Invalid size '{}', expected COLSxROWS", size);
+    let (columns, rows) = size.split_once('x').ok_or_else(invalid)?;
+    let columns = columns.parse().map_err(|_| invalid())?;
+    let rows = rows.parse().map_err(|_| invalid())?;
+    Ok((columns, rows))
+}
+
+/// Reads the image files given on the command line, checking them for overlaps.
+fn load_images(options: &Options) -> Result<Loader, String> {
+    let mut loader = Loader::new(options.overlap);
+    for path in &options.images {
+        let images = formats::read(Path::new(path), options.format, options.origin)
+            .map_err(|e| format!("failed to load image file '{}': {}", path, e))?;
+        for image in images {
+            loader.add(image)?;
+        }
+    }
+    if options.overlap == OverlapPolicy::Warn {
+        for overlap in loader.overlaps() {
+            eprintln!("Warning: {}", overlap);
+        }
+    }
+    Ok(loader)
+}
+
+/// Adds the labels of the `.sym` files found next to the object files to the VM's symbol table.
+fn load_symbols(vm: &mut VM, options: &Options) {
+    for path in &options.images {
+        let path = Path::new(path).with_extension("sym");
+        if !path.exists() {
+            continue;
+        }
+        match SymbolTable::read(&path) {
+            Ok(symbols) => vm.symbols_mut().extend(&symbols),
+            Err(e) => eprintln!("Warning: ignoring '{}': {}", path.display(), e),
+        }
+    }
+}
+
+/// Creates the VM and attaches the optional devices selected on the command line.
+fn build_vm(options: &Options) -> Result<VM, String> {
+    let mut vm = VM::new();
+    if let Some(seed) = options.seed {
+        vm.seed(seed);
+    }
+    if let Some(clock) = options.clock {
+        if let Some(rtc) = vm.device_mut::<RealTimeClock>() {
+            rtc.set_clock(clock);
+        }
+    }
+    if let Some(path) = &options.disk {
+        let disk =
+            Disk::open(path).map_err(|e| format!("failed to open disk '{}': {}", path, e))?;
+        vm.register_device(Disk::range(), Box::new(disk))?;
+    }
+    if let Some(endpoint) = &options.serial {
+        let serial =
+            Serial::open(endpoint).map_err(|e| format!("failed to open serial port: {}", e))?;
+        vm.register_device(
+            MemoryMappedRegister::SRSR as u16..=MemoryMappedRegister::STDR as u16,
+            Box::new(serial),
+        )?;
+    }
+    if options.audio.is_some() {
+        let generator = ToneGenerator::new(options.audio_clock.unwrap_or(DEFAULT_CLOCK_HZ));
+        vm.register_device(
+            MemoryMappedRegister::TGFR as u16..=MemoryMappedRegister::TGCR as u16,
+            Box::new(generator),
+        )?;
+    }
+    if let Some((columns, rows)) = options.text_display {
+        let renderer = if options.headless {
+            TextRenderer::Headless
+        } else {
+            TextRenderer::Ansi
+        };
+        let display = TextDisplay::new(TEXT_BASE, columns, rows, renderer)?;
+        vm.register_device(display.range(), Box::new(display))?;
+    }
+    if options.video || options.screenshot.is_some() || options.frames.is_some() {
+        let mut video = VideoDisplay::new();
+        if let Some(dir) = &options.frames {
+            video.dump_frames(
+                Path::new(dir),
+                options.frame_format.unwrap_or(FrameFormat::Png),
+                options.frame_interval.unwrap_or(FRAME_INTERVAL),
+            )?;
+        }
+        vm.register_device(VideoDisplay::range(), Box::new(video))?;
+    }
+    Ok(vm)
+}
+
+/// Presents the final state of the display and audio devices once the program stops.
+///
+/// Every device is presented even if another one fails, and each failure is reported.
+///
+/// # Errors
+///
+/// Returns the first `String` error.
+fn present_displays(vm: &mut VM, options: &Options) -> Result<(), String> {
+    let mut results = Vec::new();
+    if let Some(display) = vm.device_mut::<TextDisplay>() {
+        if options.headless {
+            for line in display.lines() {
+                println!("{}", line.trim_end());
+            }
+        } else {
+            results.push(("refreshing the text display", display.refresh()));
+        }
+    }
+    if let Some(video) = vm.device::<VideoDisplay>() {
+        if let Some(error) = video.last_error() {
+            eprintln!("Error dumping video frame: {}", error);
+        }
+        if let Some(path) = &options.screenshot {
+            results.push(("writing the screenshot", video.save(Path::new(path))));
+        }
+    }
+    if let (Some(generator), Some(path)) = (vm.device::<ToneGenerator>(), &options.audio) {
+        results.push(("writing the audio", generator.write_wav(Path::new(path))));
+    }
+    let mut first = Ok(());
+    for (action, result) in results {
+        if let Err(e) = result {
+            eprintln!("Error {}: {}", action, e);
+            first = first.and(Err(e));
+        }
+    }
+    first
+}
+
+/// Creates the VM, loads the images and their symbols and sets the entry point.
+///
+/// # Returns
+///
+/// The VM and the memory regions to dump once the program stops.
+fn prepare_vm(options: &Options) -> Result<(VM, Vec<RangeInclusive<u16>>), String> {
+    let mut vm = build_vm(options)?;
+    let mut rng = options.seed.map_or_else(Rng::from_time, Rng::new);
+    if let Some(fill) = options.init_memory {
+        vm.fill_memory(fill, &mut rng);
+    }
+    if let Some(fill) = options.init_registers {
+        vm.fill_registers(fill, &mut rng);
+    }
+    if options.flag_uninit || options.sanitize.is_some() {
+        vm.flag_uninitialized_reads();
+    }
+    let loader = load_images(options)?;
+    loader.load(&mut vm);
+    load_symbols(&mut vm, options);
+    if let Some(entry) = &options.entry {
+        let pc = loader.entry_point(entry)?;
+        vm.registers_mut().write(Register::PC, pc);
+    }
+    for (register, value) in &options.set_registers {
+        vm.registers_mut().write(*register, *value);
+    }
+    if let Some(mode) = options.sanitize {
+        let mut sanitizer = Sanitizer::new(mode);
+        for (register, _) in &options.set_registers {
+            sanitizer.initialize_register(*register);
+        }
+        vm.add_observer(Box::new(sanitizer));
+    }
+    if options.check_stack {
+        vm.add_observer(Box::new(StackChecker::new()));
+    }
+    if options.profile {
+        vm.add_observer(Box::new(Profiler::new()));
+    }
+    if options.coverage.is_some() || options.lcov.is_some() {
+        vm.add_observer(Box::new(Coverage::new()));
+    }
+    if options.map {
+        print!("{}", loader.memory_map());
+        println!("Entry point: x{:04X}", vm.registers().read(Register::PC));
+    }
+    let mut dump_ranges = options
+        .dump_ranges
+        .iter()
+        .map(|range| parse_range(range, vm.symbols()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if options.dump_only && dump_ranges.is_empty() {
+        dump_ranges = loader.images().iter().filter_map(Image::range).collect();
+    }
+    if matches!(options.dump_format, Some(DumpFormat::Image(_))) && dump_ranges.len() > 1 {
+        return Err("Image formats can only hold one dumped range".to_string());
+    }
+    Ok((vm, dump_ranges))
+}
+
+/// Writes the profile report to the profile file, or to standard output, and the
+/// collapsed call stacks to their file, if the program was profiled.
+fn write_profile(vm: &VM, options: &Options) -> Result<(), String> {
+    let Some(profiler) = vm.observer::<Profiler>() else {
+        return Ok(());
+    };
+    let mut writer: Box<dyn Write> = match &options.profile_file {
+        Some(path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => Box::new(io::stdout()),
+    };
+    let top = options.profile_top.unwrap_or(PROFILE_TOP);
+    profiler.write_report(vm.symbols(), top, &mut writer)?;
+    writer.flush().map_err(|e| e.to_string())?;
+    if let Some(path) = &options.collapsed {
+        let mut file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        profiler.write_collapsed(vm.symbols(), &mut file)?;
+    }
+    Ok(())
+}
+
+/// Adds the coverage of the run to the coverage file and exports it as lcov, if requested.
+fn write_coverage(vm: &VM, options: &Options) -> Result<(), String> {
+    let Some(run) = vm.observer::<Coverage>() else {
+        return Ok(());
+    };
+    let mut coverage = Coverage::new();
+    if let Some(path) = &options.coverage {
+        if Path::new(path).exists() {
+            coverage = Coverage::read(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+    coverage.merge(run);
+    if let Some(path) = &options.coverage {
+        fs::write(path, coverage.to_text()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if let Some(path) = &options.lcov {
+        let mut file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        coverage.write_lcov(&source_map(options)?, &mut file)?;
+    }
+    Ok(())
+}
+
+/// Maps the loaded words to the `.asm` files found next to the images, or to the images
+/// themselves when there is no source.
+fn source_map(options: &Options) -> Result<SourceMap, String> {
+    let mut sources = SourceMap::new();
+    for path in &options.images {
+        let source = Path::new(path).with_extension("asm");
+        if source.exists() {
+            let text = fs::read_to_string(&source).map_err(|e| e.to_string())?;
+            sources.add_assembly(&source.display().to_string(), &text)?;
+        }
+    }
+    for path in &options.images {
+        for image in formats::read(Path::new(path), options.format, options.origin)? {
+            sources.add_image(path, &image);
+        }
+    }
+    Ok(sources)
+}
+
+/// Writes the memory regions to dump to the dump file, or to standard output.
+fn dump_regions(vm: &VM, options: &Options, ranges: &[RangeInclusive<u16>]) -> Result<(), String> {
+    if ranges.is_empty() {
+        return Ok(());
+    }
+    let mut writer: Box<dyn Write> = match &options.dump_file {
+        Some(path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => Box::new(io::stdout()),
+    };
+    let format = options.dump_format.unwrap_or(DumpFormat::Hexdump);
+    for range in ranges {
+        vm.dump_memory(range.clone(), format, &mut writer)?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
 
 /// Entry point for the LC-3 Virtual Machine.
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: lc3-vm [object-file1] ...");
-        exit(2);
+    if args.get(1).is_some_and(|command| command == "link") {
+        if let Err(e) = link_command(&args[2..]) {
+            eprintln!("Error: {}", e);
+            exit(1);
+        }
+        return;
     }
-    let mut vm = VM::new();
-
-    // Disable input buffering for immediate input processing
-    let original_tio = match disable_input_buffering() {
-        Ok(tio) => tio,
+    if args.get(1).is_some_and(|command| command == "test") {
+        // Failed test cases exit with 1, specs that cannot be run with 2
+        match test_command(&args[2..]) {
+            Ok(true) => return,
+            Ok(false) => exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                exit(2);
+            }
+        }
+    }
+    if args.get(1).is_some_and(|command| command == "difftest") {
+        match difftest_command(&args[2..]) {
+            Ok(true) => return,
+            Ok(false) => exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                exit(2);
+            }
+        }
+    }
+    if args.get(1).is_some_and(|command| command == "fuzz") {
+        match fuzz_command(&args[2..]) {
+            Ok(true) => return,
+            Ok(false) => exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                exit(2);
+            }
+        }
+    }
+    // `lc3-vm dump` loads the images and dumps memory without running them
+    let dump_only = args.get(1).is_some_and(|command| command == "dump");
+    // `lc3-vm profile` runs them and reports where their instructions were executed
+    let profile = args.get(1).is_some_and(|command| command == "profile");
+    let options = match parse_args(&args[1 + (dump_only || profile) as usize..]) {
+        Ok(options) => Options {
+            dump_only,
+            profile,
+            ..options
+        },
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+    let (mut vm, dump_ranges) = match prepare_vm(&options) {
+        Ok(prepared) => prepared,
         Err(e) => {
-            eprintln!("Error disabling input buffering: {}", e);
+            eprintln!("Error: {}", e);
             exit(1);
         }
     };
+    if !options.dump_only {
+        run(&mut vm, &options);
+    }
+    if let Err(e) = write_profile(&vm, &options) {
+        eprintln!("Error writing the profile: {}", e);
+        exit(1);
+    }
+    if let Err(e) = write_coverage(&vm, &options) {
+        eprintln!("Error writing the coverage: {}", e);
+        exit(1);
+    }
+    if let Err(e) = dump_regions(&vm, &options, &dump_ranges) {
+        eprintln!("Error dumping memory: {}", e);
+        exit(1);
+    }
+}
 
-    for path in &args[1..] {
-        if let Err(msg) = vm.read_image_file(path) {
-            eprintln!("Error: failed to load image file '{}': {}", path, msg);
-            restore_input_buffering(&original_tio).unwrap_or_else(|e| {
-                eprintln!("Error restoring input buffering: {}", e);
+/// Runs the program with the terminal in unbuffered mode, then presents the devices.
+fn run(vm: &mut VM, options: &Options) {
+    // Disable input buffering for immediate input processing. When the input is not a
+    // terminal (e.g. redirected from a file when grading), there is no buffering to disable.
+    let original_tio = if io::stdin().is_terminal() {
+        match disable_input_buffering() {
+            Ok(tio) => Some(tio),
+            Err(e) => {
+                eprintln!("Error disabling input buffering: {}", e);
                 exit(1);
-            });
-            exit(1);
+            }
         }
-    }
+    } else {
+        None
+    };
+
     if let Err(e) = vm.run() {
-        eprintln!("Error while running the VM: {}", e)
+        eprintln!("Error while running the VM: {}", e);
+        eprint!("{}", vm.backtrace());
+    }
+    if options.flag_uninit {
+        for read in vm.uninitialized_reads() {
+            eprintln!(
+                "Warning: read of uninitialized address x{:04X} at {}",
+                read.address,
+                vm.symbols().describe(read.pc)
+            );
+        }
     }
+    // When halting, the violation was already reported as the error
+    if options.sanitize == Some(SanitizerMode::Report) {
+        if let Some(sanitizer) = vm.observer::<Sanitizer>() {
+            for violation in sanitizer.violations() {
+                eprintln!(
+                    "Sanitizer: {} at {}",
+                    violation.kind,
+                    vm.symbols().describe(violation.pc)
+                );
+            }
+        }
+    }
+    if let Some(checker) = vm.observer::<StackChecker>() {
+        for warning in checker.warnings() {
+            let subroutine = warning
+                .subroutine
+                .map(|address| format!(" in {}", vm.symbols().describe(address)))
+                .unwrap_or_default();
+            eprintln!(
+                "Stack: {}{} at {}",
+                warning.kind,
+                subroutine,
+                vm.symbols().describe(warning.pc)
+            );
+        }
+    }
+    // Failures are reported by `present_displays` itself
+    let _ = present_displays(vm, options);
 
-    if let Err(e) = restore_input_buffering(&original_tio) {
-        eprintln!("Error restoring input buffering: {}", e);
-        exit(1);
+    restore_terminal(original_tio.as_ref());
+}
+
+/// Restores the input buffering disabled at startup, if any, exiting on failure.
+fn restore_terminal(original_tio: Option<&Termios>) {
+    if let Some(tio) = original_tio {
+        if let Err(e) = restore_input_buffering(tio) {
+            eprintln!("Error restoring input buffering: {}", e);
+            exit(1);
+        }
     }
 }
@@ -0,0 +1,245 @@
+use crate::hardware::memory::MEMORY_SIZE;
+
+/// First address of the device page, which the model leaves out.
+pub const DEVICE_PAGE: u16 = 0xFE00;
+
+/// Condition code set by a negative result.
+pub const N: u16 = 0b100;
+
+/// Condition code set by a zero result.
+pub const Z: u16 = 0b010;
+
+/// Condition code set by a positive result.
+pub const P: u16 = 0b001;
+
+/// Deliberately simple model of the LC-3 instruction semantics, written straight from the
+/// ISA description to check the VM against.
+///
+/// The model has no devices, traps, interrupts or privilege levels: `TRAP`, `RTI`, the
+/// reserved opcode and accesses to the device page are left out. `LEA` sets the condition
+/// codes, as in the second edition of the ISA the VM implements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// R0 to R7.
+    pub registers: [u16; 8],
+    /// Program counter.
+    pub pc: u16,
+    /// Condition codes: `N`, `Z` or `P`.
+    pub cond: u16,
+    /// The 64K words of memory.
+    pub memory: Vec<u16>,
+    /// Address written by the last instruction, if any.
+    pub written: Option<u16>,
+}
+
+impl Default for Reference {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reference {
+    /// Creates a model with zeroed registers and memory, starting at x3000 with `Z` set.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Reference`.
+    pub fn new() -> Self {
+        Self {
+            registers: [0; 8],
+            pc: 0x3000,
+            cond: Z,
+            memory: vec![0; MEMORY_SIZE],
+            written: None,
+        }
+    }
+
+    /// Executes the instruction at the PC.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error, leaving the state unchanged, for instructions the model
+    /// leaves out: `TRAP`, `RTI`, the reserved opcode and accesses to the device page.
+    pub fn step(&mut self) -> Result<(), String> {
+        let instr = self.load(self.pc)?;
+        let written = self.written.take();
+        let result = self.execute(instr);
+        if result.is_err() {
+            self.written = written;
+        }
+        result
+    }
+
+    /// Executes an instruction, checking every access before changing the state.
+    fn execute(&mut self, instr: u16) -> Result<(), String> {
+        let next = self.pc.wrapping_add(1);
+        let dr = ((instr >> 9) & 7) as usize;
+        let sr1 = ((instr >> 6) & 7) as usize;
+        let imm = instr & (1 << 5) != 0;
+        let operand = if imm {
+            sext(instr, 5)
+        } else {
+            self.registers[(instr & 7) as usize]
+        };
+        let pc_offset9 = next.wrapping_add(sext(instr, 9));
+        let base_offset6 = self.registers[sr1].wrapping_add(sext(instr, 6));
+
+        match instr >> 12 {
+            // BR
+            0x0 => {
+                let nzp = (instr >> 9) & 7;
+                self.pc = if nzp & self.cond != 0 {
+                    pc_offset9
+                } else {
+                    next
+                };
+            }
+            // ADD
+            0x1 => {
+                self.set(dr, self.registers[sr1].wrapping_add(operand));
+                self.pc = next;
+            }
+            // LD
+            0x2 => {
+                let value = self.load(pc_offset9)?;
+                self.set(dr, value);
+                self.pc = next;
+            }
+            // ST
+            0x3 => {
+                self.store(pc_offset9, self.registers[dr])?;
+                self.pc = next;
+            }
+            // JSR, JSRR: the target is read before R7 is written
+            0x4 => {
+                let target = if instr & (1 << 11) != 0 {
+                    next.wrapping_add(sext(instr, 11))
+                } else {
+                    self.registers[sr1]
+                };
+                self.registers[7] = next;
+                self.pc = target;
+            }
+            // AND
+            0x5 => {
+                self.set(dr, self.registers[sr1] & operand);
+                self.pc = next;
+            }
+            // LDR
+            0x6 => {
+                let value = self.load(base_offset6)?;
+                self.set(dr, value);
+                self.pc = next;
+            }
+            // STR
+            0x7 => {
+                self.store(base_offset6, self.registers[dr])?;
+                self.pc = next;
+            }
+            // NOT
+            0x9 => {
+                self.set(dr, !self.registers[sr1]);
+                self.pc = next;
+            }
+            // LDI
+            0xA => {
+                let address = self.load(pc_offset9)?;
+                let value = self.load(address)?;
+                self.set(dr, value);
+                self.pc = next;
+            }
+            // STI
+            0xB => {
+                let address = self.load(pc_offset9)?;
+                self.store(address, self.registers[dr])?;
+                self.pc = next;
+            }
+            // JMP, RET
+            0xC => self.pc = self.registers[sr1],
+            // LEA
+            0xE => {
+                self.set(dr, pc_offset9);
+                self.pc = next;
+            }
+            _ => return Err(format!("x{:04X} is not modeled", instr)),
+        }
+        Ok(())
+    }
+
+    /// Reads a word outside the device page.
+    fn load(&self, address: u16) -> Result<u16, String> {
+        if address >= DEVICE_PAGE {
+            return Err(format!("x{:04X} is in the device page", address));
+        }
+        Ok(self.memory[address as usize])
+    }
+
+    /// Writes a word outside the device page.
+    fn store(&mut self, address: u16, value: u16) -> Result<(), String> {
+        if address >= DEVICE_PAGE {
+            return Err(format!("x{:04X} is in the device page", address));
+        }
+        self.memory[address as usize] = value;
+        self.written = Some(address);
+        Ok(())
+    }
+
+    /// Writes a register and sets the condition codes from the value.
+    fn set(&mut self, register: usize, value: u16) {
+        self.registers[register] = value;
+        self.cond = match value {
+            0 => Z,
+            v if v & 0x8000 != 0 => N,
+            _ => P,
+        };
+    }
+}
+
+/// Sign-extends the low `bits` bits of an instruction.
+fn sext(instr: u16, bits: u32) -> u16 {
+    let shift = 16 - bits;
+    (((instr << shift) as i16) >> shift) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &[u16]) -> Reference {
+        let mut model = Reference::new();
+        model.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+        for _ in program {
+            model.step().unwrap();
+        }
+        model
+    }
+
+    #[test]
+    fn arithmetic_sets_condition_codes() {
+        let model = run(&[0x103F, 0x1261, 0x5020]); // ADD R0, R0, #-1; ADD R1, R1, #1; AND R0, R0, #0
+        assert_eq!(model.registers[..2], [0, 1]);
+        assert_eq!(model.cond, Z);
+        assert_eq!(run(&[0x903F]).cond, N); // NOT R0, R0
+    }
+
+    #[test]
+    fn jsrr_through_r7_jumps_to_the_old_r7() {
+        let mut model = Reference::new();
+        model.registers[7] = 0x4000;
+        model.memory[0x3000] = 0x41C0; // JSRR R7
+        model.step().unwrap();
+        assert_eq!((model.pc, model.registers[7]), (0x4000, 0x3001));
+    }
+
+    #[test]
+    fn unmodeled_instructions_leave_the_state_unchanged() {
+        let mut model = Reference::new();
+        model.registers[1] = 0xFDFF;
+        model.memory[0x3000] = 0x6041; // LDR R0, R1, #1
+        let before = model.clone();
+        assert!(model.step().is_err());
+        assert_eq!(model, before);
+        model.memory[0x3000] = 0xF025; // HALT
+        assert!(model.step().is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use crate::isa::instructions::is_conditional_branch;
+use crate::loader::Image;
+use crate::utils::parse_word;
+use std::collections::BTreeMap;
+
+/// Mnemonics of the LC-3 instructions, trap aliases included, apart from the `BR` variants.
+const MNEMONICS: [&str; 22] = [
+    "ADD", "AND", "JMP", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "NOT", "RET", "RTI", "ST",
+    "STI", "STR", "TRAP", "GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT",
+];
+
+/// Source line a memory word was assembled from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// Index of the source file in `SourceMap::files`.
+    pub file: usize,
+    /// Line number, starting at 1.
+    pub line: u32,
+    /// Whether the word is an instruction rather than data (`.FILL`, `.BLKW`, `.STRINGZ`).
+    pub code: bool,
+    /// Whether the instruction is a branch, `BR` with at least one condition.
+    pub branch: bool,
+}
+
+/// Map from memory addresses to the source lines they were assembled from.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// Names of the source files.
+    files: Vec<String>,
+    /// Source line of each mapped address.
+    locations: BTreeMap<u16, Location>,
+}
+
+impl SourceMap {
+    /// Creates an empty source map.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `SourceMap`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps the words of an LC-3 assembly file to its lines.
+    ///
+    /// Addresses are laid out the way the assembler does: from each `.ORIG`, every instruction
+    /// and `.FILL` takes one word, `.BLKW n` takes `n` words and `.STRINGZ` one word per
+    /// character plus the terminating zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - Name of the file, as reported in coverage files.
+    /// * `text` - Contents of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error naming the line of an unknown opcode or invalid operand.
+    pub fn add_assembly(&mut self, file: &str, text: &str) -> Result<(), String> {
+        let index = self.add_file(file);
+        let mut address: Option<u16> = None;
+        for (number, line) in text.lines().enumerate() {
+            let number = number as u32 + 1;
+            let error = |message: String| format!("{}:{}: {}", file, number, message);
+            let line = strip_comment(line);
+            let mut tokens = line
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|t| !t.is_empty())
+                .peekable();
+            // A leading token that is neither an opcode nor a directive is a label
+            if tokens.peek().is_some_and(|t| !is_operation(t)) {
+                tokens.next();
+            }
+            let Some(operation) = tokens.next() else {
+                continue;
+            };
+            let operation = operation.to_ascii_uppercase();
+            let operand = tokens.next();
+            let (words, code) = match operation.as_str() {
+                ".ORIG" => {
+                    let origin =
+                        operand.ok_or_else(|| error(".ORIG requires an address".into()))?;
+                    address = Some(parse_number(origin).map_err(error)?);
+                    continue;
+                }
+                ".END" => {
+                    address = None;
+                    continue;
+                }
+                ".FILL" => (1, false),
+                ".BLKW" => {
+                    let count = operand.ok_or_else(|| error(".BLKW requires a count".into()))?;
+                    (parse_number(count).map_err(error)?, false)
+                }
+                ".STRINGZ" => {
+                    let start = line
+                        .find('"')
+                        .ok_or_else(|| error("missing string".into()))?;
+                    (string_length(&line[start + 1..]) + 1, false)
+                }
+                _ if is_operation(&operation) => (1, true),
+                _ => return Err(error(format!("unknown opcode '{}'", operation))),
+            };
+            let Some(start) = address else {
+                return Err(error("code outside of .ORIG and .END".into()));
+            };
+            let branch = code && is_conditional_branch_mnemonic(&operation);
+            for offset in 0..words {
+                self.locations.insert(
+                    start.wrapping_add(offset),
+                    Location {
+                        file: index,
+                        line: number,
+                        code,
+                        branch,
+                    },
+                );
+            }
+            address = Some(start.wrapping_add(words));
+        }
+        Ok(())
+    }
+
+    /// Maps the words of an image without source to lines of the image file, the word at
+    /// the origin being on line 1. Words already mapped, e.g. from an assembly file, are kept.
+    ///
+    /// As the image does not tell instructions from data, every word counts as code.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - Name of the image file.
+    /// * `image` - The image.
+    pub fn add_image(&mut self, file: &str, image: &Image) {
+        let index = self.add_file(file);
+        for (offset, word) in image.words.iter().enumerate() {
+            let address = image.origin.wrapping_add(offset as u16);
+            self.locations.entry(address).or_insert(Location {
+                file: index,
+                line: offset as u32 + 1,
+                code: true,
+                branch: is_conditional_branch(*word),
+            });
+        }
+    }
+
+    /// Returns the names of the source files.
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Returns the source line of an address.
+    pub fn location(&self, address: u16) -> Option<&Location> {
+        self.locations.get(&address)
+    }
+
+    /// Returns the mapped addresses and their source lines, sorted by address.
+    pub fn locations(&self) -> impl Iterator<Item = (u16, &Location)> {
+        self.locations
+            .iter()
+            .map(|(address, location)| (*address, location))
+    }
+
+    /// Returns the index of a file, adding it if needed.
+    fn add_file(&mut self, file: &str) -> usize {
+        match self.files.iter().position(|f| f == file) {
+            Some(index) => index,
+            None => {
+                self.files.push(file.to_string());
+                self.files.len() - 1
+            }
+        }
+    }
+}
+
+/// Checks whether a token is an opcode or an assembler directive.
+fn is_operation(token: &str) -> bool {
+    let upper = token.to_ascii_uppercase();
+    let is_branch = upper
+        .strip_prefix("BR")
+        .is_some_and(|flags| flags.len() <= 3 && flags.chars().all(|c| "NZP".contains(c)));
+    upper.starts_with('.') || is_branch || MNEMONICS.contains(&upper.as_str())
+}
+
+/// Checks whether a `BR` mnemonic has one or two conditions, so that it can go either way.
+fn is_conditional_branch_mnemonic(token: &str) -> bool {
+    let upper = token.to_ascii_uppercase();
+    upper.strip_prefix("BR").is_some_and(|flags| {
+        let conditions = "NZP".chars().filter(|c| flags.contains(*c)).count();
+        (1..=2).contains(&conditions)
+    })
+}
+
+/// Removes the comment of a line, ignoring semicolons within strings.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Returns the number of characters of a string literal, whose opening quote was consumed.
+fn string_length(text: &str) -> u16 {
+    let mut length = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => {
+                chars.next();
+            }
+            _ => {}
+        }
+        length += 1;
+    }
+    length
+}
+
+/// Parses an operand in LC-3 (`x3000`, `#10`) or C (`0x3000`) notation.
+fn parse_number(text: &str) -> Result<u16, String> {
+    parse_word(text.strip_prefix('#').unwrap_or(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "\
+; Prints a greeting
+        .ORIG x3000
+MAIN    LEA R0, HELLO   ; address of the string
+        PUTS
+LOOP    BRnz LOOP
+        HALT
+HELLO   .STRINGZ \"Hi; \\\"you\\\"\"
+BUFFER  .BLKW #2
+VALUE   .FILL x1234
+        .END
+";
+
+    #[test]
+    fn assembly_lines_are_laid_out_from_origin() {
+        let mut map = SourceMap::new();
+        map.add_assembly("hello.asm", PROGRAM).unwrap();
+        let line = |address| map.location(address).map(|l| (l.line, l.code, l.branch));
+        assert_eq!(line(0x3000), Some((3, true, false)));
+        assert_eq!(line(0x3002), Some((5, true, true)));
+        assert_eq!(line(0x3004), Some((7, false, false)));
+        // "Hi; \"you\"" is 9 characters, plus the terminating zero
+        assert_eq!(line(0x300D), Some((7, false, false)));
+        assert_eq!(line(0x300E), Some((8, false, false)));
+        assert_eq!(line(0x3010), Some((9, false, false)));
+        assert_eq!(line(0x3011), None);
+    }
+
+    #[test]
+    fn unconditional_branches_are_not_branch_points() {
+        let mut map = SourceMap::new();
+        let source = ".ORIG x3000\nBR #0\nBRnzp #0\nbrpn #0\n.END\n";
+        map.add_assembly("jumps.asm", source).unwrap();
+        let branch = |map: &SourceMap, address| map.location(address).unwrap().branch;
+        assert!(!branch(&map, 0x3000));
+        assert!(!branch(&map, 0x3001));
+        assert!(branch(&map, 0x3002));
+        let mut map = SourceMap::new();
+        map.add_image(
+            "jumps.obj",
+            &Image::new("jumps", 0x3000, vec![0x0E00, 0x0A00]),
+        );
+        assert!(!branch(&map, 0x3000));
+        assert!(branch(&map, 0x3001));
+    }
+
+    #[test]
+    fn unknown_opcodes_are_reported() {
+        let mut map = SourceMap::new();
+        let result = map.add_assembly("bad.asm", ".ORIG x3000\nLABEL FOO R1\n.END\n");
+        assert_eq!(result, Err("bad.asm:2: unknown opcode 'FOO'".to_string()));
+    }
+
+    #[test]
+    fn images_are_mapped_without_overriding_sources() {
+        let mut map = SourceMap::new();
+        map.add_assembly("hello.asm", PROGRAM).unwrap();
+        map.add_image("extra.obj", &Image::new("extra", 0x3010, vec![0, 0x0402]));
+        assert_eq!(map.location(0x3010).unwrap().file, 0);
+        assert_eq!(
+            map.location(0x3011),
+            Some(&Location {
+                file: 1,
+                line: 2,
+                code: true,
+                branch: true
+            })
+        );
+        assert_eq!(map.files(), ["hello.asm", "extra.obj"]);
+    }
+}
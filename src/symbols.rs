@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Table mapping labels to addresses, as emitted by the LC-3 assembler in `.sym` files.
+///
+/// The table is used to describe addresses in terms of the nearest preceding label,
+/// e.g. `LOOP+3`, in error messages and listings.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    /// Address of each label.
+    addresses: HashMap<String, u16>,
+    /// Label of each address; the first one defined wins when several share an address.
+    labels: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    /// Creates an empty symbol table.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `SymbolTable`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a `.sym` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the file cannot be read.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Ok(Self::parse(&text))
+    }
+
+    /// Parses the contents of a `.sym` file.
+    ///
+    /// Both the `lc3as` layout (`//  LOOP  3003`, below comment headers) and plain
+    /// `LOOP x3003` lines are accepted. Lines that do not hold exactly a label and a
+    /// hexadecimal address, such as the headers, are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut table = Self::new();
+        for line in text.lines() {
+            let line = line.trim_start().trim_start_matches('/');
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let &[label, address] = &fields[..] else {
+                continue;
+            };
+            let digits = address.trim_start_matches(['x', 'X']);
+            let address = match u16::from_str_radix(digits, 16) {
+                Ok(address) if is_label(label) && !digits.starts_with('+') => address,
+                _ => continue,
+            };
+            table.insert(label, address);
+        }
+        table
+    }
+
+    /// Defines a label, e.g. from the output of an assembler.
+    ///
+    /// # Arguments
+    ///
+    /// * `label` - Name of the label.
+    /// * `address` - Address the label refers to.
+    pub fn insert(&mut self, label: &str, address: u16) {
+        self.addresses.insert(label.to_string(), address);
+        self.labels
+            .entry(address)
+            .or_insert_with(|| label.to_string());
+    }
+
+    /// Adds all the labels of another table.
+    pub fn extend(&mut self, other: &SymbolTable) {
+        for (address, label) in &other.labels {
+            self.insert(label, *address);
+        }
+        for (label, address) in &other.addresses {
+            self.insert(label, *address);
+        }
+    }
+
+    /// Returns the address of a label.
+    pub fn address(&self, label: &str) -> Option<u16> {
+        self.addresses.get(label).copied()
+    }
+
+    /// Returns the label defined exactly at an address.
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    /// Returns the number of labels in the table.
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Returns `true` if the table has no labels.
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Returns the labels and their addresses, sorted by address then label.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        let mut symbols: Vec<(&str, u16)> = self
+            .addresses
+            .iter()
+            .map(|(label, address)| (label.as_str(), *address))
+            .collect();
+        symbols.sort_by(|a, b| (a.1, a.0).cmp(&(b.1, b.0)));
+        symbols.into_iter()
+    }
+
+    /// Formats the table as a `.sym` file in the `lc3as` layout.
+    pub fn to_sym(&self) -> String {
+        let mut text = String::from(
+            "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n",
+        );
+        for (label, address) in self.iter() {
+            text.push_str(&format!("//\t{:<16}  {:04X}\n", label, address));
+        }
+        text
+    }
+
+    /// Describes an address relative to the nearest label at or before it.
+    ///
+    /// # Returns
+    ///
+    /// `LABEL` or `LABEL+N`, or the address in hexadecimal (`x3003`) if no label precedes it.
+    pub fn describe(&self, address: u16) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((&base, label)) if base == address => label.clone(),
+            Some((&base, label)) => format!("{}+{}", label, address - base),
+            None => format!("x{:04X}", address),
+        }
+    }
+}
+
+/// Checks whether `text` is a valid LC-3 label: a letter or underscore followed by
+/// letters, digits or underscores.
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LC3AS_SYMBOLS: &str = "\
+// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+//\tMAIN              3000
+//\tLOOP              3003
+//\tDATA              3010
+";
+
+    #[test]
+    fn parse_reads_lc3as_symbol_file() {
+        let table = SymbolTable::parse(LC3AS_SYMBOLS);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.address("LOOP"), Some(0x3003));
+        assert_eq!(table.label(0x3010), Some("DATA"));
+    }
+
+    #[test]
+    fn parse_accepts_plain_lines_and_skips_invalid_ones() {
+        let table = SymbolTable::parse("START x4000\n1ABEL 4000\nEND 4G00\n");
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.address("START"), Some(0x4000));
+    }
+
+    #[test]
+    fn describe_uses_nearest_preceding_label() {
+        let table = SymbolTable::parse(LC3AS_SYMBOLS);
+        assert_eq!(table.describe(0x3003), "LOOP");
+        assert_eq!(table.describe(0x3006), "LOOP+3");
+        assert_eq!(table.describe(0x2FFF), "x2FFF");
+    }
+
+    #[test]
+    fn to_sym_round_trips() {
+        let table = SymbolTable::parse(LC3AS_SYMBOLS);
+        let text = table.to_sym();
+        assert!(text.contains("//\tLOOP              3003\n"));
+        let parsed = SymbolTable::parse(&text);
+        assert_eq!(
+            parsed.iter().collect::<Vec<_>>(),
+            table.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn first_label_at_an_address_wins() {
+        let mut table = SymbolTable::new();
+        table.insert("FIRST", 0x3000);
+        table.insert("SECOND", 0x3000);
+        assert_eq!(table.describe(0x3001), "FIRST+1");
+        assert_eq!(table.address("SECOND"), Some(0x3000));
+    }
+}
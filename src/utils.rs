@@ -46,10 +46,47 @@ pub fn sign_extend(x: u16, bit_count: usize) -> u16 {
     }
 }
 
+/// Parses a 16-bit word written in LC-3 hexadecimal (`x3000`), C hexadecimal (`0x3000`)
+/// or decimal (`12288`, `-1`) notation.
+///
+/// # Errors
+///
+/// Returns a `String` error if the text is not a number or does not fit in 16 bits.
+pub fn parse_word(text: &str) -> Result<u16, String> {
+    let invalid = || format!("Invalid number '{}'", text);
+    let hex = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('x'))
+        .or_else(|| text.strip_prefix('X'));
+    match hex {
+        Some(digits) => u16::from_str_radix(digits, 16).map_err(|_| invalid()),
+        None => match text.parse::<u16>() {
+            Ok(value) => Ok(value),
+            Err(_) => text.parse::<i16>().map(|v| v as u16).map_err(|_| invalid()),
+        },
+    }
+}
+
+/// Returns a path in the temporary directory, made unique to the test process by its id.
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("lc3-vm-{}-{}", std::process::id(), name))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_word_accepts_hex_and_decimal() {
+        assert_eq!(parse_word("x3000"), Ok(0x3000));
+        assert_eq!(parse_word("0xFE00"), Ok(0xFE00));
+        assert_eq!(parse_word("12288"), Ok(0x3000));
+        assert_eq!(parse_word("-1"), Ok(0xFFFF));
+        assert!(parse_word("x10000").is_err());
+        assert!(parse_word("start").is_err());
+    }
+
     #[test]
     fn sign_extend_bit_is_0() {
         let value = 0b0000_0000_0011_1111;
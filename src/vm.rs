@@ -1,97 +1,658 @@
-use crate::hardware::memory::Memory;
+use crate::analysis::call_stack::{CallStack, Frame, FrameKind};
+use crate::analysis::{Observer, Step};
+use crate::dump::{self, DumpFormat};
+use crate::formats;
+use crate::hardware::device::Device;
+use crate::hardware::display::Display;
+use crate::hardware::keyboard::Keyboard;
+use crate::hardware::machine_control::MachineControl;
+use crate::hardware::memory::{Memory, MemoryMappedRegister};
 use crate::hardware::registers::*;
-use crate::isa::{instructions::*, traps};
-use byteorder::{BigEndian, ReadBytesExt};
-use std::fs::File;
-use std::io::BufReader;
+use crate::hardware::rng::{RandomNumberGenerator, Rng};
+use crate::hardware::rtc::RealTimeClock;
+use crate::hardware::timer::Timer;
+use crate::isa::{instructions::*, interrupts, traps};
+use crate::loader::Image;
+use crate::symbols::SymbolTable;
+use crate::utils::parse_word;
+use std::any::Any;
+use std::collections::HashSet;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Initial contents of memory or registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fill {
+    /// All zeros, the default.
+    Zero,
+    /// Pseudorandom words.
+    Random,
+    /// The same word everywhere, e.g. `xDEAD`.
+    Value(u16),
+}
+
+impl Fill {
+    /// Parses `zero`, `random` or a word (`xDEAD`, `0xDEAD` or decimal).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error for any other text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "zero" => Ok(Fill::Zero),
+            "random" => Ok(Fill::Random),
+            _ => parse_word(text)
+                .map(Fill::Value)
+                .map_err(|_| format!("Invalid fill '{}', expected zero, random or a word", text)),
+        }
+    }
+
+    /// Returns the next word of the fill.
+    fn next(self, rng: &mut Rng) -> u16 {
+        match self {
+            Fill::Zero => 0,
+            Fill::Random => rng.next_u16(),
+            Fill::Value(value) => value,
+        }
+    }
+}
+
+/// A read of a memory word that was never loaded or written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UninitializedRead {
+    /// Address of the instruction that read the word.
+    pub pc: u16,
+    /// Address of the word.
+    pub address: u16,
+}
+
+/// Return address passed in R7 by `VM::call`: the call ends once the subroutine jumps to it.
+pub const CALL_RETURN: u16 = 0xFFFF;
+
+/// R6 set by `VM::call` before pushing the arguments, just below the device registers.
+pub const CALL_STACK: u16 = 0xFE00;
+
+/// Number of instructions a subroutine called with `VM::call` may execute.
+pub const CALL_BUDGET: u64 = 1_000_000;
+
+/// A memory word changed by a subroutine called with `VM::call`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryChange {
+    /// Address of the word.
+    pub address: u16,
+    /// Contents before the call, once the arguments were pushed.
+    pub before: u16,
+    /// Contents after the call.
+    pub after: u16,
+}
+
+/// State of the VM once a subroutine called with `VM::call` returned.
+#[derive(Debug, Clone)]
+pub struct CallResult {
+    /// Registers on return.
+    pub registers: Registers,
+    /// Memory words changed by the subroutine, sorted by address.
+    pub memory: Vec<MemoryChange>,
+    /// Number of instructions executed.
+    pub instructions: u64,
+}
 
 /// The VM struct represents the LC-3 virtual machine, containing the memory and registers.
-#[derive(Default)]
 pub struct VM {
     memory: Memory,
     registers: Registers,
+    symbols: SymbolTable,
+    uninitialized_reads: Vec<UninitializedRead>,
+    seen_uninitialized_reads: HashSet<UninitializedRead>,
+    observers: Vec<Box<dyn Observer>>,
+    call_stack: CallStack,
+    /// Address of the last executed or faulting instruction.
+    last_pc: u16,
+    /// Clock enable bit of the machine control register, refreshed when a device changes.
+    clock_enabled: bool,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
     /// Creates a new instance of the VM with initialized memory and registers.
     ///
+    /// The keyboard, display, timer, random number generator, real-time clock and machine
+    /// control register are registered on the device bus at their standard addresses.
+    ///
     /// # Returns
     ///
     /// A new instance of `VM`.
     pub fn new() -> Self {
-        Self {
+        let mut vm = Self {
             memory: Memory::new(),
             registers: Registers::new(),
+            symbols: SymbolTable::new(),
+            uninitialized_reads: Vec::new(),
+            seen_uninitialized_reads: HashSet::new(),
+            observers: Vec::new(),
+            call_stack: CallStack::new(),
+            last_pc: PC_START,
+            clock_enabled: true,
+        };
+        vm.register_default_devices()
+            .expect("default device ranges do not overlap");
+        vm
+    }
+
+    /// Maps a device to a range of addresses.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The addresses handled by the device.
+    /// * `device` - The device to register.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the range overlaps the range of an already registered device.
+    pub fn register_device(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Box<dyn Device>,
+    ) -> Result<(), String> {
+        self.memory.bus_mut().register(range, device)
+    }
+
+    /// Reseeds the random number generator device, making runs reproducible.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The new seed.
+    pub fn seed(&mut self, seed: u64) {
+        if let Some(rng) = self.device_mut::<RandomNumberGenerator>() {
+            rng.seed(seed);
         }
     }
 
+    /// Fills every word of memory, e.g. with random values to expose reads of uninitialized data.
+    ///
+    /// # Arguments
+    ///
+    /// * `fill` - The values to fill memory with.
+    /// * `rng` - Generator of the random values.
+    pub fn fill_memory(&mut self, fill: Fill, rng: &mut Rng) {
+        self.memory.fill(|| fill.next(rng));
+    }
+
+    /// Fills the general-purpose registers R0 to R7.
+    ///
+    /// # Arguments
+    ///
+    /// * `fill` - The values to fill the registers with.
+    /// * `rng` - Generator of the random values.
+    pub fn fill_registers(&mut self, fill: Fill, rng: &mut Rng) {
+        for reg in 0..8 {
+            self.registers.write(Register::from(reg), fill.next(rng));
+        }
+    }
+
+    /// Starts recording reads of memory words that were never loaded or written.
+    ///
+    /// Must be called before loading images, which would otherwise count as uninitialized.
+    pub fn flag_uninitialized_reads(&mut self) {
+        self.memory.track_initialization();
+    }
+
+    /// Returns the reads of uninitialized words recorded so far, once per instruction and word.
+    pub fn uninitialized_reads(&self) -> &[UninitializedRead] {
+        &self.uninitialized_reads
+    }
+
+    /// Adds an analysis notified of every executed instruction, e.g. the sanitizer.
+    ///
+    /// Accesses to RAM are recorded from then on, to be passed to the observers.
+    ///
+    /// # Arguments
+    ///
+    /// * `observer` - The analysis to add.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.memory.record_accesses();
+        self.observers.push(observer);
+    }
+
+    /// Returns the first added observer of type `T`.
+    pub fn observer<T: Observer>(&self) -> Option<&T> {
+        self.observers
+            .iter()
+            .find_map(|o| (o.as_ref() as &dyn Any).downcast_ref::<T>())
+    }
+
+    /// Returns a mutable reference to the first added observer of type `T`.
+    pub fn observer_mut<T: Observer>(&mut self) -> Option<&mut T> {
+        self.observers
+            .iter_mut()
+            .find_map(|o| (o.as_mut() as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    /// Returns the first registered device of type `T`.
+    pub fn device<T: Device>(&self) -> Option<&T> {
+        self.memory.bus().device::<T>()
+    }
+
+    /// Returns a mutable reference to the first registered device of type `T`.
+    pub fn device_mut<T: Device>(&mut self) -> Option<&mut T> {
+        self.memory.bus_mut().device_mut::<T>()
+    }
+
+    /// Registers the devices that are always present.
+    fn register_default_devices(&mut self) -> Result<(), String> {
+        use MemoryMappedRegister::*;
+        self.register_device(KBSR as u16..=KBDR as u16, Box::new(Keyboard::new()))?;
+        self.register_device(DSR as u16..=DDR as u16, Box::new(Display::new()))?;
+        self.register_device(TMSR as u16..=TMIR as u16, Box::new(Timer::new()))?;
+        self.register_device(
+            RNDR as u16..=RNDR as u16,
+            Box::new(RandomNumberGenerator::new()),
+        )?;
+        self.register_device(
+            RTSEC as u16..=RTYR as u16,
+            Box::new(RealTimeClock::default()),
+        )?;
+        self.register_device(MCR as u16..=MCR as u16, Box::new(MachineControl::new()))
+    }
+
     /// Reads an image file and loads its contents into the VM's memory.
     ///
+    /// The format (object, ASCII hex or binary, Intel HEX) is detected from the extension
+    /// and contents of the file; raw images need an origin and are loaded with `formats::read`.
+    ///
     /// # Arguments
     ///
-    /// * `path` - A string slice that holds the path to the object file to be loaded.
+    /// * `path` - A string slice that holds the path to the image file to be loaded.
     ///
     /// # Errors
     ///
     /// Returns a `String` error if the file cannot be opened, read, or if there is a memory overflow.
     pub fn read_image_file(&mut self, path: &str) -> Result<(), String> {
-        let file = File::open(path).map_err(|e| e.to_string())?;
-        let mut reader = BufReader::new(file);
+        for image in formats::read(Path::new(path), None, None)? {
+            self.load_image(&image);
+        }
+        Ok(())
+    }
 
-        // origin (first 2 bytes)
-        let mut address = reader.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-        while let Ok(instr) = reader.read_u16::<BigEndian>() {
-            self.memory.write(address, instr);
-            address = address
-                .checked_add(1)
-                .ok_or("Memory overflow, object file is too large")?;
+    /// Writes an image into the VM's memory at its origin.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image to load.
+    pub fn load_image(&mut self, image: &Image) {
+        for (offset, word) in image.words.iter().enumerate() {
+            self.memory
+                .write(image.origin.wrapping_add(offset as u16), *word);
         }
+    }
 
-        Ok(())
+    /// Writes the contents of a memory region, e.g. to inspect data structures after a run.
+    ///
+    /// Words are read from RAM without side effects: device registers are not accessed.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The addresses to dump.
+    /// * `format` - Hexdump, disassembly annotated with the VM's symbols, or a program image.
+    /// * `writer` - Destination of the dump.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if writing fails.
+    pub fn dump_memory(
+        &self,
+        range: RangeInclusive<u16>,
+        format: DumpFormat,
+        writer: &mut dyn Write,
+    ) -> Result<(), String> {
+        let words: Vec<u16> = range.clone().map(|a| self.memory.peek(a)).collect();
+        dump::write_dump(&words, *range.start(), format, &self.symbols, writer)
     }
 
-    /// Runs the VM, executing instructions in a loop until the VM is halted.
+    /// Reads a word of memory without side effects: device registers are not accessed.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address to read.
+    pub fn peek(&self, address: u16) -> u16 {
+        self.memory.peek(address)
+    }
+
+    /// Returns whether an address is mapped to a device register rather than RAM.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address to check.
+    pub fn is_mapped(&self, address: u16) -> bool {
+        self.memory.bus().is_mapped(address)
+    }
+
+    /// Returns the symbol table used to describe addresses.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Returns a mutable reference to the symbol table, e.g. to add the labels of a `.sym` file.
+    pub fn symbols_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    /// Returns the VM's registers.
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// Returns a mutable reference to the VM's registers, e.g. to set the entry point.
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /// Returns the shadow call stack of the subroutines, traps and interrupt handlers
+    /// that have not returned yet.
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// Formats a backtrace from the last executed instruction, e.g. the one that faulted,
+    /// through the call sites of the active subroutines.
+    pub fn backtrace(&self) -> String {
+        self.call_stack.backtrace(self.last_pc, &self.symbols)
+    }
+
+    /// Runs the VM, executing instructions in a loop until the VM is halted
+    /// or the clock enable bit of the machine control register is cleared.
+    ///
+    /// After every instruction the memory-mapped devices are advanced, and pending
+    /// interrupts with a priority higher than the current one are serviced.
     ///
     /// # Errors
     ///
-    /// Returns a `String` error if there is an issue with reading memory or executing instructions.
+    /// Returns a `String` error if there is an issue with reading memory or executing instructions,
+    /// or if an observer stops the VM. The error names the address of the faulting instruction,
+    /// relative to the nearest label.
     pub fn run(&mut self) -> Result<(), String> {
+        // Accesses made before running, e.g. loading images, are not made by instructions
+        self.memory.take_accesses();
+        while self.step()? {}
+        Ok(())
+    }
+
+    /// Runs the VM like `run`, executing at most a number of instructions, e.g. to stop
+    /// programs stuck in a loop.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget` - Maximum number of instructions to execute.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the VM halted within the budget, `false` if it was still running.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error like `run`.
+    pub fn run_for(&mut self, budget: u64) -> Result<bool, String> {
+        self.memory.take_accesses();
+        for _ in 0..budget {
+            if !self.step()? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Calls a single subroutine, e.g. to unit test it, and runs until it returns.
+    ///
+    /// The arguments are pushed on the stack, the first one on top, from `CALL_STACK` down,
+    /// and the first six are also passed in R0 to R5, so subroutines taking their arguments
+    /// either on the stack or in registers can be called. R7 is set to `CALL_RETURN` and
+    /// the call ends once the subroutine returns there. Images, symbols and other registers
+    /// are left as they are, so the subroutine can be called several times; the shadow call
+    /// stack is cleared, so a backtrace only shows the frames of the last call.
+    ///
+    /// The stack grows down from `CALL_STACK`, which is the top of the video framebuffer
+    /// when a `VideoDisplay` is attached: arguments must then be set with `registers_mut`.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - Address of the subroutine.
+    /// * `args` - The arguments.
+    ///
+    /// # Returns
+    ///
+    /// The registers on return and the memory words changed by the subroutine. A value
+    /// returned on the stack is the word at the returned R6.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error if the arguments do not fit below the stack or would be pushed
+    /// onto device registers, or if the subroutine faults, halts before returning or does not return within `CALL_BUDGET`
+    /// instructions.
+    pub fn call(&mut self, address: u16, args: &[u16]) -> Result<CallResult, String> {
+        self.call_with_budget(address, args, CALL_BUDGET)
+    }
+
+    /// Calls a single subroutine like `call`, with a budget of instructions.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - Address of the subroutine.
+    /// * `args` - The arguments.
+    /// * `budget` - Maximum number of instructions to execute.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `String` error like `call`.
+    pub fn call_with_budget(
+        &mut self,
+        address: u16,
+        args: &[u16],
+        budget: u64,
+    ) -> Result<CallResult, String> {
+        if args.len() > CALL_STACK as usize {
+            return Err(format!(
+                "{} arguments of {} do not fit below the stack at x{:04X}",
+                args.len(),
+                self.symbols.describe(address),
+                CALL_STACK
+            ));
+        }
+        let sp = CALL_STACK - args.len() as u16;
+        if let Some(mapped) = (sp..CALL_STACK).find(|&a| self.memory.bus().is_mapped(a)) {
+            return Err(format!(
+                "Arguments of {} would be pushed onto the device register at x{:04X}",
+                self.symbols.describe(address),
+                mapped
+            ));
+        }
+        self.call_stack = CallStack::new();
+        for (offset, arg) in args.iter().enumerate() {
+            self.memory.write(sp.wrapping_add(offset as u16), *arg);
+        }
+        for (reg, arg) in args.iter().take(6).enumerate() {
+            self.registers.write(Register::from(reg as u16), *arg);
+        }
+        self.registers.write(Register::R6, sp);
+        self.registers.write(Register::R7, CALL_RETURN);
+        self.registers.write(Register::PC, address);
+        let before: Vec<u16> = (0..=u16::MAX).map(|a| self.memory.peek(a)).collect();
+        self.memory.take_accesses();
+
+        let mut instructions = 0;
+        while self.registers.read(Register::PC) != CALL_RETURN {
+            if instructions == budget {
+                return Err(format!(
+                    "{} did not return within {} instructions",
+                    self.symbols.describe(address),
+                    budget
+                ));
+            }
+            instructions += 1;
+            if !self.step()? {
+                return Err(format!(
+                    "{} halted before returning",
+                    self.symbols.describe(address)
+                ));
+            }
+        }
+        let memory = (0..=u16::MAX)
+            .map(|address| MemoryChange {
+                address,
+                before: before[address as usize],
+                after: self.memory.peek(address),
+            })
+            .filter(|change| change.before != change.after)
+            .collect();
+        Ok(CallResult {
+            registers: self.registers.clone(),
+            memory,
+            instructions,
+        })
+    }
+
+    /// Executes one instruction, then advances the devices and services a pending interrupt.
+    ///
+    /// # Returns
+    ///
+    /// Whether the VM is still running.
+    fn step(&mut self) -> Result<bool, String> {
         let mut running = true;
-        while running {
-            let pc = self.registers.read(Register::PC);
-            let instr = self.memory.read(pc)?;
-            self.registers.write(Register::PC, pc.wrapping_add(1));
-            let op = Opcode::from(instr >> 12);
-            match op {
-                Opcode::BR => branch(&mut self.registers, instr),
-                Opcode::ADD => add(&mut self.registers, instr),
-                Opcode::LD => load(&mut self.registers, &mut self.memory, instr)?,
-                Opcode::ST => store(&mut self.registers, &mut self.memory, instr),
-                Opcode::JSR => jump_to_subroutine(&mut self.registers, instr),
-                Opcode::AND => and(&mut self.registers, instr),
-                Opcode::LDR => load_register(&mut self.registers, &mut self.memory, instr)?,
-                Opcode::STR => store_register(&mut self.registers, &mut self.memory, instr),
-                Opcode::NOT => not(&mut self.registers, instr),
-                Opcode::LDI => load_indirect(&mut self.registers, &mut self.memory, instr)?,
-                Opcode::STI => store_indirect(&mut self.registers, &mut self.memory, instr)?,
-                Opcode::JMP => jump(&mut self.registers, instr),
-                Opcode::LEA => load_effective_address(&mut self.registers, instr),
-                Opcode::TRAP => {
-                    traps::execute(&mut self.registers, &mut self.memory, instr, &mut running)?
+        let pc = self.registers.read(Register::PC);
+        self.last_pc = pc;
+        let instr = self
+            .execute(pc, &mut running)
+            .map_err(|e| format!("{} at {}", e, self.symbols.describe(pc)))?;
+        for address in self.memory.take_uninitialized_reads() {
+            let read = UninitializedRead { pc, address };
+            if self.seen_uninitialized_reads.insert(read) {
+                self.uninitialized_reads.push(read);
+            }
+        }
+
+        // Devices are ticked before the observers are notified, so that they see the
+        // transfers started by the instruction
+        let pending = self.memory.tick();
+        self.observe(pc, instr)
+            .map_err(|e| format!("{} at {}", e, self.symbols.describe(pc)))?;
+        if let Some(irq) = pending {
+            if irq.priority > self.registers.priority() {
+                let return_address = self.registers.read(Register::PC);
+                interrupts::interrupt(&mut self.registers, &mut self.memory, irq)?;
+                self.call_stack.push(Frame {
+                    kind: FrameKind::Interrupt,
+                    call_site: pc,
+                    subroutine: self.registers.read(Register::PC),
+                    return_address,
+                    stack_pointer: self.registers.read(Register::R6),
+                });
+                self.memory.take_accesses();
+                for observer in &mut self.observers {
+                    observer.interrupt(irq, &self.registers);
                 }
-                Opcode::RTI | Opcode::RES => {
-                    println!("RTI and RES are not implemented");
+            }
+        }
+        Ok(running && self.clock_enabled())
+    }
+
+    /// Fetches and executes the instruction at `pc`, returning it.
+    fn execute(&mut self, pc: u16, running: &mut bool) -> Result<u16, String> {
+        let instr = self.memory.read(pc)?;
+        self.registers.write(Register::PC, pc.wrapping_add(1));
+        let op = Opcode::from(instr >> 12);
+        match op {
+            Opcode::BR => branch(&mut self.registers, instr),
+            Opcode::ADD => add(&mut self.registers, instr),
+            Opcode::LD => load(&mut self.registers, &mut self.memory, instr)?,
+            Opcode::ST => store(&mut self.registers, &mut self.memory, instr),
+            Opcode::JSR => {
+                jump_to_subroutine(&mut self.registers, instr);
+                self.call_stack.push(Frame {
+                    kind: FrameKind::Subroutine,
+                    call_site: pc,
+                    subroutine: self.registers.read(Register::PC),
+                    return_address: pc.wrapping_add(1),
+                    stack_pointer: self.registers.read(Register::R6),
+                });
+            }
+            Opcode::AND => and(&mut self.registers, instr),
+            Opcode::LDR => load_register(&mut self.registers, &mut self.memory, instr)?,
+            Opcode::STR => store_register(&mut self.registers, &mut self.memory, instr),
+            Opcode::NOT => not(&mut self.registers, instr),
+            Opcode::LDI => load_indirect(&mut self.registers, &mut self.memory, instr)?,
+            Opcode::STI => store_indirect(&mut self.registers, &mut self.memory, instr)?,
+            Opcode::JMP => {
+                jump(&mut self.registers, instr);
+                if (instr >> 6) & 0x7 == 7 {
+                    self.call_stack.ret(self.registers.read(Register::PC));
                 }
             }
+            Opcode::LEA => load_effective_address(&mut self.registers, instr),
+            Opcode::TRAP => {
+                // The frame is left on the stack if the trap routine fails
+                self.call_stack.push(Frame {
+                    kind: FrameKind::Trap,
+                    call_site: pc,
+                    subroutine: instr & 0xFF,
+                    return_address: pc.wrapping_add(1),
+                    stack_pointer: self.registers.read(Register::R6),
+                });
+                traps::execute(&mut self.registers, &mut self.memory, instr, running)?;
+                self.call_stack.pop();
+            }
+            Opcode::RTI => {
+                interrupts::return_from_interrupt(&mut self.registers, &mut self.memory)?;
+                self.call_stack.rti();
+            }
+            Opcode::RES => return Err(format!("Illegal opcode x{:04X}", instr)),
+        }
+        Ok(instr)
+    }
+
+    /// Passes an executed instruction and its accesses to RAM to the observers.
+    fn observe(&mut self, pc: u16, instr: u16) -> Result<(), String> {
+        if self.observers.is_empty() {
+            return Ok(());
+        }
+        let accesses = self.memory.take_accesses();
+        let step = Step {
+            pc,
+            instr,
+            registers: &self.registers,
+            accesses: &accesses,
+            call_stack: &self.call_stack,
+        };
+        for observer in &mut self.observers {
+            observer.step(&step)?;
         }
         Ok(())
     }
+
+    /// Checks the clock enable bit of the machine control register, if one is registered.
+    ///
+    /// The bit is only looked up on the bus after a device changed, not on every instruction.
+    fn clock_enabled(&mut self) -> bool {
+        if self.memory.bus_mut().take_changed() {
+            self.clock_enabled = self
+                .device::<MachineControl>()
+                .is_none_or(|mcr| mcr.clock_enabled());
+        }
+        self.clock_enabled
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::{self, Write};
+    use crate::hardware::disk::{transfer_program, Disk};
+    use crate::hardware::video::VideoDisplay;
+    use std::fs::File;
+    use std::io;
 
     const TEST_FILES_PATH: &str = "tests/assembly/";
 
@@ -125,6 +686,343 @@ mod tests {
         assert!(vm.read_image_file("nonexistent_file.obj").is_err());
     }
 
+    #[test]
+    fn run_services_timer_interrupt() {
+        let mut vm = VM::new();
+        let program = [
+            0x2007, // x3000 LD R0, INTERVAL
+            0xB009, // x3001 STI R0, TMIR_ADDR
+            0x2006, // x3002 LD R0, CONTROL
+            0xB006, // x3003 STI R0, TMCR_ADDR
+            0x2202, // x3004 LD R1, FLAG
+            0x05FE, // x3005 BRz #-2 (spin until the handler sets FLAG)
+            0xF025, // x3006 HALT
+            0x0000, // x3007 FLAG
+            0x000A, // x3008 INTERVAL: every 10 instructions
+            0xC400, // x3009 CONTROL: enable, interrupts, priority 4
+            0xFE0A, // x300A TMCR_ADDR
+            0xFE0C, // x300B TMIR_ADDR
+        ];
+        let handler = [
+            0xA004, // x4000 LDI R0, TMSR_ADDR (acknowledge)
+            0x54A0, // x4001 AND R2, R2, #0
+            0x14A1, // x4002 ADD R2, R2, #1
+            0xB402, // x4003 STI R2, FLAG_ADDR
+            0x8000, // x4004 RTI
+            0xFE08, // x4005 TMSR_ADDR
+            0x3007, // x4006 FLAG_ADDR
+        ];
+        for (i, word) in program.iter().enumerate() {
+            vm.memory.write(PC_START + i as u16, *word);
+        }
+        for (i, word) in handler.iter().enumerate() {
+            vm.memory.write(0x4000 + i as u16, *word);
+        }
+        vm.memory
+            .write(interrupts::INTERRUPT_VECTOR_TABLE + 0x81, 0x4000);
+
+        assert!(vm.run().is_ok());
+        assert_eq!(vm.memory.read(0x3007).unwrap(), 1);
+        assert_eq!(vm.registers.read(Register::R0), 0x8000);
+        assert!(vm.registers.is_user_mode());
+    }
+
+    #[test]
+    fn run_stops_when_clock_is_disabled() {
+        let mut vm = VM::new();
+        let program = [
+            0x5020, // x3000 AND R0, R0, #0
+            0xB001, // x3001 STI R0, MCR_ADDR
+            0x0FFD, // x3002 BRnzp #-3 (never reached)
+            0xFFFE, // x3003 MCR_ADDR
+        ];
+        for (i, word) in program.iter().enumerate() {
+            vm.memory.write(PC_START + i as u16, *word);
+        }
+        assert!(vm.run().is_ok());
+        assert_eq!(vm.registers.read(Register::PC), PC_START + 2);
+    }
+
+    #[test]
+    fn run_stops_when_clock_is_disabled_by_host() {
+        let mut vm = VM::new();
+        vm.memory.write(PC_START, 0x0FFF); // BRnzp #-1
+        assert_eq!(vm.run_for(10), Ok(false));
+        vm.device_mut::<MachineControl>().unwrap().write(0xFFFE, 0);
+        assert_eq!(vm.run_for(10), Ok(true));
+        assert_eq!(vm.registers.read(Register::PC), PC_START);
+    }
+
+    #[test]
+    fn run_for_stops_when_budget_is_exhausted() {
+        let mut vm = VM::new();
+        vm.memory.write(PC_START, 0x1021); // ADD R0, R0, #1
+        vm.memory.write(PC_START + 1, 0x0FFE); // BRnzp #-2
+        assert_eq!(vm.run_for(7), Ok(false));
+        assert_eq!(vm.registers.read(Register::R0), 4);
+        vm.memory.write(PC_START + 1, 0xF025); // HALT
+        assert_eq!(vm.run_for(7), Ok(true));
+    }
+
+    #[test]
+    fn call_passes_arguments_in_registers_and_returns_to_sentinel() {
+        let mut vm = VM::new();
+        let program = [
+            0x1DBF, // x3000 TWICE: ADD R6, R6, #-1
+            0x7F80, // x3001 STR R7, R6, #0
+            0x4803, // x3002 JSR DOUBLE
+            0x6F80, // x3003 LDR R7, R6, #0
+            0x1DA1, // x3004 ADD R6, R6, #1
+            0xC1C0, // x3005 RET
+            0x1000, // x3006 DOUBLE: ADD R0, R0, R0
+            0xC1C0, // x3007 RET
+        ];
+        vm.load_image(&Image::new("test", PC_START, program.to_vec()));
+        let result = vm.call(0x3000, &[21]).unwrap();
+        assert_eq!(result.registers.read(Register::R0), 42);
+        assert_eq!(result.registers.read(Register::R6), CALL_STACK - 1);
+        assert_eq!(result.instructions, 8);
+        assert_eq!(
+            result.memory,
+            [MemoryChange {
+                address: CALL_STACK - 2,
+                before: 0,
+                after: CALL_RETURN
+            }]
+        );
+        assert_eq!(
+            vm.call(0x3006, &[5]).unwrap().registers.read(Register::R0),
+            10
+        );
+    }
+
+    #[test]
+    fn call_pushes_arguments_on_the_stack() {
+        let mut vm = VM::new();
+        let program = [
+            0x6180, // x3000 SUM: LDR R0, R6, #0
+            0x6381, // x3001 LDR R1, R6, #1
+            0x1001, // x3002 ADD R0, R0, R1
+            0x3001, // x3003 ST R0, RESULT
+            0xC1C0, // x3004 RET
+            0x0000, // x3005 RESULT
+        ];
+        vm.load_image(&Image::new("test", PC_START, program.to_vec()));
+        // The LDRs replace the arguments passed in R0 and R1 with the ones on the stack
+        let result = vm.call(0x3000, &[10, 20]).unwrap();
+        assert_eq!(vm.peek(CALL_STACK - 2), 10);
+        assert_eq!(
+            result.memory,
+            [MemoryChange {
+                address: 0x3005,
+                before: 0,
+                after: 30
+            }]
+        );
+    }
+
+    #[test]
+    fn call_fails_if_subroutine_does_not_return() {
+        let mut vm = VM::new();
+        vm.memory.write(0x3000, 0x0FFF); // LOOP: BRnzp LOOP
+        vm.memory.write(0x3001, 0xF025); // HALT
+        vm.symbols_mut().insert("LOOP", 0x3000);
+        assert_eq!(
+            vm.call_with_budget(0x3000, &[], 100).unwrap_err(),
+            "LOOP did not return within 100 instructions"
+        );
+        assert_eq!(
+            vm.call(0x3001, &[]).unwrap_err(),
+            "LOOP+1 halted before returning"
+        );
+    }
+
+    #[test]
+    fn call_clears_frames_of_previous_call() {
+        let mut vm = VM::new();
+        let program = [
+            0x4801, // x3000 OUTER: JSR SPIN
+            0xC1C0, // x3001 RET
+            0x0FFF, // x3002 SPIN: BRnzp SPIN
+            0xD000, // x3003 illegal opcode
+        ];
+        vm.load_image(&Image::new("test", PC_START, program.to_vec()));
+        assert!(vm.call_with_budget(0x3000, &[], 10).is_err());
+        assert_eq!(vm.call_stack().frames().len(), 1);
+        assert!(vm.call(0x3003, &[]).is_err());
+        assert!(vm.call_stack().frames().is_empty());
+    }
+
+    #[test]
+    fn call_rejects_arguments_on_device_registers() {
+        let mut vm = VM::new();
+        vm.register_device(VideoDisplay::range(), Box::new(VideoDisplay::new()))
+            .unwrap();
+        vm.memory.write(0x3000, 0xC1C0); // RET
+        assert_eq!(
+            vm.call(0x3000, &[1, 2]).unwrap_err(),
+            "Arguments of x3000 would be pushed onto the device register at xFDFE"
+        );
+        assert!(vm.call(0x3000, &[]).is_ok());
+    }
+
+    #[test]
+    fn call_rejects_more_arguments_than_fit_below_the_stack() {
+        let mut vm = VM::new();
+        let args = vec![0; CALL_STACK as usize + 1];
+        assert_eq!(
+            vm.call(0x3000, &args).unwrap_err(),
+            "65025 arguments of x3000 do not fit below the stack at xFE00"
+        );
+    }
+
+    #[test]
+    fn run_reports_illegal_opcode_at_label() {
+        let mut vm = VM::new();
+        vm.memory.write(PC_START, 0x1021); // ADD R0, R0, #1
+        vm.memory.write(PC_START + 1, 0xD000); // reserved opcode
+        vm.symbols_mut().insert("MAIN", PC_START);
+        assert_eq!(vm.run(), Err("Illegal opcode xD000 at MAIN+1".to_string()));
+    }
+
+    #[test]
+    fn run_reports_unknown_trap_vector() {
+        let mut vm = VM::new();
+        vm.memory.write(PC_START, 0xF000); // TRAP x00
+        assert_eq!(
+            vm.run(),
+            Err("Unknown trap vector x00 at x3000".to_string())
+        );
+        assert_eq!(vm.registers.read(Register::R7), 0);
+    }
+
+    #[test]
+    fn backtrace_names_callers_of_faulting_instruction() {
+        let mut vm = VM::new();
+        let program = [
+            0x4801, // x3000 JSR OUTER
+            0xF025, // x3001 HALT
+            0x4801, // x3002 OUTER: JSR INNER
+            0xC1C0, // x3003 RET
+            0x1021, // x3004 INNER: ADD R0, R0, #1
+            0xD000, // x3005 illegal opcode
+        ];
+        vm.load_image(&Image::new("test", PC_START, program.to_vec()));
+        vm.symbols_mut().insert("MAIN", 0x3000);
+        vm.symbols_mut().insert("OUTER", 0x3002);
+        vm.symbols_mut().insert("INNER", 0x3004);
+        assert!(vm.run().is_err());
+        assert_eq!(
+            vm.backtrace(),
+            "#0 x3005 in INNER+1\n#1 x3002 in OUTER\n#2 x3000 in MAIN\n"
+        );
+    }
+
+    #[test]
+    fn call_stack_is_empty_after_returns() {
+        let mut vm = VM::new();
+        let program = [
+            0x4802, // x3000 JSR SUB
+            0xF025, // x3001 HALT
+            0x0000, // x3002
+            0x1021, // x3003 SUB: ADD R0, R0, #1
+            0xC1C0, // x3004 RET
+        ];
+        vm.load_image(&Image::new("test", PC_START, program.to_vec()));
+        assert!(vm.run().is_ok());
+        assert!(vm.call_stack().frames().is_empty());
+    }
+
+    #[test]
+    fn dump_memory_writes_object_image() {
+        let mut vm = VM::new();
+        vm.memory.write(0x4000, 0x1234);
+        let mut output = Vec::new();
+        vm.dump_memory(
+            0x4000..=0x4001,
+            DumpFormat::Image(formats::ImageFormat::Object),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, [0x40, 0x00, 0x12, 0x34, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn run_flags_reads_of_uninitialized_words() {
+        let mut vm = VM::new();
+        vm.fill_memory(Fill::Value(0xDEAD), &mut Rng::new(0));
+        vm.flag_uninitialized_reads();
+        let program = [
+            0x2002, // x3000 LD R0, x3003 (initialized)
+            0x2202, // x3001 LD R1, x3004 (never written)
+            0xF025, // x3002 HALT
+            0x0007, // x3003
+        ];
+        for (i, word) in program.iter().enumerate() {
+            vm.memory.write(PC_START + i as u16, *word);
+        }
+        assert!(vm.run().is_ok());
+        assert_eq!(vm.registers.read(Register::R1), 0xDEAD);
+        assert_eq!(
+            vm.uninitialized_reads(),
+            [UninitializedRead {
+                pc: 0x3001,
+                address: 0x3004
+            }]
+        );
+    }
+
+    #[test]
+    fn words_read_from_disk_are_initialized() {
+        let (disk, mut program) = transfer_program("uninit", &[0x1234], 0x4000);
+        program.extend([
+            0xA3FB, // x3009 LDI R1, x3005 (the buffer)
+            0xF025, // x300A HALT
+        ]);
+        let mut vm = VM::new();
+        vm.register_device(Disk::range(), Box::new(disk)).unwrap();
+        vm.fill_memory(Fill::Value(0xDEAD), &mut Rng::new(0));
+        vm.flag_uninitialized_reads();
+        vm.load_image(&Image::new("transfer", PC_START, program));
+        assert!(vm.run().is_ok());
+        assert_eq!(vm.registers.read(Register::R1), 0x1234);
+        assert_eq!(vm.uninitialized_reads(), []);
+    }
+
+    #[test]
+    fn fill_registers_with_seeded_random_values_is_reproducible() {
+        let mut a = VM::new();
+        let mut b = VM::new();
+        a.fill_registers(Fill::Random, &mut Rng::new(5));
+        b.fill_registers(Fill::Random, &mut Rng::new(5));
+        for reg in 0..8 {
+            assert_eq!(
+                a.registers.read(Register::from(reg)),
+                b.registers.read(Register::from(reg))
+            );
+        }
+        assert_eq!(a.registers.read(Register::PC), PC_START);
+    }
+
+    #[test]
+    fn register_device_rejects_overlapping_range() {
+        let mut vm = VM::new();
+        let result = vm.register_device(0xFE00..=0xFE00, Box::new(Keyboard::new()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn seeded_random_number_generator_is_reproducible() {
+        let mut a = VM::new();
+        let mut b = VM::new();
+        a.seed(99);
+        b.seed(99);
+        let rndr = MemoryMappedRegister::RNDR as u16;
+        for _ in 0..4 {
+            assert_eq!(a.memory.read(rndr).unwrap(), b.memory.read(rndr).unwrap());
+        }
+    }
+
     #[test]
     fn read_image_file_invalid_format() {
         // Create a file with invalid content (not enough bytes for origin address)